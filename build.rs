@@ -0,0 +1,59 @@
+//! With the `bundled` feature, build libxml2 from source and link it statically
+//! instead of linking the system library.
+
+fn main() {
+    #[cfg(feature = "bundled")]
+    bundled::build();
+}
+
+#[cfg(feature = "bundled")]
+mod bundled {
+    use std::env;
+    use std::path::PathBuf;
+
+    /// Where the libxml2 sources are unless `LIBXML2_SOURCE_DIR` says otherwise.
+    const DEFAULT_SOURCE: &str = "vendor/libxml2";
+
+    pub fn build() {
+        println!("cargo:rerun-if-env-changed=LIBXML2_SOURCE_DIR");
+        let source = env::var_os("LIBXML2_SOURCE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SOURCE));
+        if !source.join("CMakeLists.txt").is_file() {
+            panic!(
+                "the bundled feature needs the libxml2 sources in {}, e.g. from\n  \
+                 git clone --depth 1 --branch v2.13.9 \
+                 https://gitlab.gnome.org/GNOME/libxml2.git {DEFAULT_SOURCE}\n\
+                 or set LIBXML2_SOURCE_DIR",
+                source.display()
+            );
+        }
+        println!("cargo:rerun-if-changed={}", source.display());
+
+        // Only what validation needs: no compression, character set conversion
+        // beyond the built-in encodings, Python bindings or command-line programs.
+        let out = cmake::Config::new(&source)
+            .define("BUILD_SHARED_LIBS", "OFF")
+            .define("LIBXML2_WITH_ICONV", "OFF")
+            .define("LIBXML2_WITH_ICU", "OFF")
+            .define("LIBXML2_WITH_LZMA", "OFF")
+            .define("LIBXML2_WITH_ZLIB", "OFF")
+            .define("LIBXML2_WITH_PYTHON", "OFF")
+            .define("LIBXML2_WITH_PROGRAMS", "OFF")
+            .define("LIBXML2_WITH_TESTS", "OFF")
+            .build();
+        for lib in ["lib", "lib64"] {
+            println!("cargo:rustc-link-search=native={}", out.join(lib).display());
+        }
+        if env::var("CARGO_CFG_TARGET_ENV").as_deref() == Ok("msvc") {
+            println!("cargo:rustc-link-lib=static=libxml2s");
+        } else {
+            println!("cargo:rustc-link-lib=static=xml2");
+        }
+        if env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("windows") {
+            println!("cargo:rustc-link-lib=ws2_32");
+        } else {
+            println!("cargo:rustc-link-lib=m");
+        }
+    }
+}
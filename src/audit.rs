@@ -0,0 +1,49 @@
+//! A JSON Lines log of the schemas fetched over the network, for environments that
+//! must show which external resources a run touched.
+//!
+//! Only fetches made by validate-xml itself are logged. Schemas that a schema imports
+//! or includes, and external entities, are loaded by libxml2 and are not.
+
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where fetches are logged, if anywhere.
+static LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Log every fetch to `file` from now on.
+pub fn enable(file: File) {
+    LOG.get_or_init(|| Mutex::new(file));
+}
+
+/// One fetch of a schema.
+#[derive(Serialize)]
+pub struct Fetch<'a> {
+    pub url: &'a str,
+    /// The HTTP status, if a response was received.
+    pub status: Option<u16>,
+    /// The size of the response body, if it was received.
+    pub bytes: Option<usize>,
+    pub duration_ms: f64,
+    /// "downloaded", or "failed" if no response body was received. Each schema is
+    /// downloaded once per run and reused from memory afterwards.
+    pub outcome: &'static str,
+}
+
+/// Log a fetch, if fetches are logged.
+pub fn record(fetch: &Fetch) -> io::Result<()> {
+    let Some(log) = LOG.get() else {
+        return Ok(());
+    };
+    let mut line = serde_json::to_value(fetch)?;
+    line["timestamp"] = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+        .into();
+    let mut log = log.lock().unwrap();
+    writeln!(log, "{line}")?;
+    log.flush()
+}
@@ -0,0 +1,113 @@
+//! Known failures recorded by an earlier run, so that adopting validation for an old corpus
+//! only fails on new or changed failures.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{FileResult, Status};
+
+/// How a file failed, ignoring line numbers so that unrelated edits keep it known.
+#[derive(PartialEq, Eq, Serialize, Deserialize)]
+struct Failure {
+    status: Status,
+    /// Sorted, distinct error messages.
+    errors: Vec<String>,
+}
+
+impl Failure {
+    fn new(result: &FileResult) -> Failure {
+        let mut errors: Vec<String> = result
+            .errors
+            .iter()
+            .map(|error| error.message.clone())
+            .collect();
+        errors.sort();
+        errors.dedup();
+        Failure {
+            status: result.status,
+            errors,
+        }
+    }
+}
+
+/// Failures by path relative to the validated directory.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Baseline {
+    files: BTreeMap<PathBuf, Failure>,
+}
+
+impl Baseline {
+    pub fn load(path: &Path) -> io::Result<Baseline> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    /// Record a failed file.
+    pub fn record(&mut self, relative_path: &Path, result: &FileResult) {
+        self.files
+            .insert(relative_path.to_owned(), Failure::new(result));
+    }
+
+    /// Whether a failed file fails exactly as recorded.
+    pub fn contains(&self, relative_path: &Path, result: &FileResult) -> bool {
+        self.files
+            .get(relative_path)
+            .is_some_and(|failure| *failure == Failure::new(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codes, ErrorDetail};
+
+    fn failed(line: i32, messages: &[&str]) -> FileResult {
+        FileResult {
+            errors: messages
+                .iter()
+                .map(|message| ErrorDetail {
+                    line,
+                    ..ErrorDetail::new(message.to_string(), &codes::INVALID_VALUE)
+                })
+                .collect(),
+            ..FileResult::new("corpus/a.xml", Status::Invalid)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("baseline.json");
+        let mut baseline = Baseline::default();
+        baseline.record(Path::new("a.xml"), &failed(3, &["b", "a", "b"]));
+        baseline.save(&path).unwrap();
+
+        let baseline = Baseline::load(&path).unwrap();
+        // Other lines, order and repetitions still match.
+        assert!(baseline.contains(Path::new("a.xml"), &failed(7, &["a", "b"])));
+        assert!(!baseline.contains(Path::new("a.xml"), &failed(3, &["a"])));
+        assert!(!baseline.contains(Path::new("a.xml"), &failed(3, &["a", "b", "c"])));
+        assert!(!baseline.contains(Path::new("b.xml"), &failed(3, &["a", "b"])));
+        let malformed = FileResult {
+            status: Status::Malformed,
+            ..failed(3, &["a", "b"])
+        };
+        assert!(!baseline.contains(Path::new("a.xml"), &malformed));
+    }
+
+    #[test]
+    fn empty_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("baseline.json");
+        Baseline::default().save(&path).unwrap();
+        let baseline = Baseline::load(&path).unwrap();
+        assert!(!baseline.contains(Path::new("a.xml"), &failed(1, &["a"])));
+        assert!(Baseline::load(&dir.path().join("missing.json")).is_err());
+    }
+}
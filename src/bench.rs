@@ -0,0 +1,97 @@
+//! Reproducible performance measurement of whole runs.
+
+use rayon::prelude::*;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::{discover, schema_loads, validate, SchemaLoad, Settings};
+
+/// Timings of one run over the directory.
+#[derive(Serialize)]
+struct Run {
+    /// Whether this run had to load the schemas.
+    cold: bool,
+    discovery_ms: f64,
+    validation_ms: f64,
+    files_per_second: f64,
+}
+
+/// Distribution of a measurement over several runs.
+#[derive(Serialize)]
+struct Percentiles {
+    min: f64,
+    p50: f64,
+    p90: f64,
+    max: f64,
+}
+
+impl Percentiles {
+    /// Nearest-rank percentiles of `values`, which must not be empty.
+    fn new(mut values: Vec<f64>) -> Percentiles {
+        values.sort_by(f64::total_cmp);
+        let rank = |p: f64| values[((p * values.len() as f64).ceil() as usize).max(1) - 1];
+        Percentiles {
+            min: values[0],
+            p50: rank(0.5),
+            p90: rank(0.9),
+            max: values[values.len() - 1],
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    files: usize,
+    runs: Vec<Run>,
+    /// Throughput of the warm runs, if there were any.
+    warm_files_per_second: Option<Percentiles>,
+    /// How long each schema took to load, during the cold run.
+    schemas: Vec<SchemaLoad>,
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Validate the directory `runs` times and print the timings on stdout as JSON.
+///
+/// Only the first run is cold: later runs reuse the schemas cached in memory.
+/// The operating system's file cache is not dropped between runs.
+pub fn run(settings: &Settings, runs: usize) -> io::Result<()> {
+    let mut files = 0;
+    let mut timings = Vec::with_capacity(runs);
+    for i in 0..runs {
+        let start = Instant::now();
+        let paths = discover(settings);
+        let discovered = Instant::now();
+        files = paths.len();
+        paths.into_par_iter().for_each(|path| {
+            validate(path, settings);
+        });
+        let validated = Instant::now();
+
+        timings.push(Run {
+            cold: i == 0,
+            discovery_ms: milliseconds(discovered - start),
+            validation_ms: milliseconds(validated - discovered),
+            files_per_second: files as f64 / (validated - start).as_secs_f64(),
+        });
+    }
+
+    let warm: Vec<f64> = timings
+        .iter()
+        .filter(|run| !run.cold)
+        .map(|run| run.files_per_second)
+        .collect();
+    let report = Report {
+        files,
+        runs: timings,
+        warm_files_per_second: (!warm.is_empty()).then(|| Percentiles::new(warm)),
+        schemas: schema_loads(),
+    };
+
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &report)?;
+    writeln!(stdout)
+}
@@ -0,0 +1,124 @@
+//! Results of an earlier run, so that files whose content and schemas have not changed
+//! since are reported without validating them again.
+//!
+//! A file's result is reused when the SHA-256 digest of its content and of its
+//! schemas is unchanged. Local schemas are digested by content and remote ones by URL
+//! alone, since they are assumed not to change, just as they are downloaded once per
+//! run. Schemas that they include or import are not digested.
+
+use cached::proc_macro::cached;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{is_remote, local_path, FileResult, Status};
+
+/// A result and the digest of the inputs it was computed from.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    digest: String,
+    result: FileResult,
+}
+
+/// Results by path relative to the validated directory.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Cache {
+    /// The digest of the settings that affect results, which invalidates every
+    /// entry when it changes.
+    settings: String,
+    files: BTreeMap<PathBuf, Entry>,
+}
+
+impl Cache {
+    /// Load a cache for the given settings digest, empty if the file does not exist,
+    /// or was written with other settings or by another version.
+    pub fn load(path: &Path, settings: String) -> io::Result<Cache> {
+        let empty = Cache {
+            settings,
+            files: BTreeMap::new(),
+        };
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(empty),
+            Err(e) => return Err(e),
+        };
+        match serde_json::from_slice::<Cache>(&bytes) {
+            Ok(cache) if cache.settings == empty.settings => Ok(cache),
+            Ok(_) => {
+                tracing::info!("Settings changed since {} was written", path.display());
+                Ok(empty)
+            }
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable cache {}: {e}", path.display());
+                Ok(empty)
+            }
+        }
+    }
+
+    /// Save the results, leaving out files that no longer exist under `root`.
+    pub fn save(&mut self, path: &Path, root: &Path) -> io::Result<()> {
+        self.files
+            .retain(|relative_path, _| root.join(relative_path).exists());
+        fs::write(path, serde_json::to_string(self)? + "\n")
+    }
+
+    /// The recorded result of a file, if its inputs still have this digest.
+    pub fn get(&self, relative_path: &Path, digest: &str) -> Option<FileResult> {
+        self.files
+            .get(relative_path)
+            .filter(|entry| entry.digest == digest)
+            .map(|entry| entry.result.clone())
+    }
+
+    /// Record the result of a file. Internal errors are not recorded, since they may
+    /// not happen again, e.g. when a schema could not be downloaded.
+    pub fn record(&mut self, relative_path: &Path, digest: String, result: &FileResult) {
+        if result.status == Status::InternalError {
+            self.files.remove(relative_path);
+            return;
+        }
+        self.files.insert(
+            relative_path.to_owned(),
+            Entry {
+                digest,
+                result: result.clone(),
+            },
+        );
+    }
+}
+
+/// The digest of a file's content and of the schemas it is validated against, or
+/// `None` if a local schema cannot be read.
+pub fn digest(content: &[u8], urls: &[String]) -> Option<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(content);
+    for url in urls {
+        hasher.update([0]);
+        hasher.update(url.as_bytes());
+        hasher.update([0]);
+        hasher.update(schema_digest(url.clone())?);
+    }
+    Some(hex(&hasher.finalize()))
+}
+
+/// The digest of a settings description.
+pub fn settings_digest(description: &str) -> String {
+    hex(&Sha256::digest(description.as_bytes()))
+}
+
+/// The digest of a local schema's content, which is read once per run, or an empty
+/// one for a remote schema.
+#[cached]
+fn schema_digest(url: String) -> Option<String> {
+    if is_remote(&url) {
+        return Some(String::new());
+    }
+    Some(hex(&Sha256::digest(fs::read(local_path(&url)).ok()?)))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -0,0 +1,256 @@
+//! Stable identifiers for kinds of errors, e.g. VX2003 for an element the schema does
+//! not allow there.
+//!
+//! libxml2 reports many codes for closely related problems, and its codes name
+//! clauses of the XML Schema specification rather than what went wrong. Each of them
+//! falls in one category here, so that reports, baselines and suppression rules can
+//! refer to errors by a code that does not change between libxml2 versions.
+//!
+//! VX1xxx codes concern whole files, VX2xxx schema validity, VX3xxx well-formedness
+//! and DTDs, VX4xxx loading schemas and VX5xxx custom rules.
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+/// The stable identifier of a code, e.g. "VX2003".
+pub type Id = &'static str;
+
+/// A category of errors.
+pub struct Code {
+    pub id: Id,
+    /// A short name, e.g. "invalid-element".
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+pub const NO_SCHEMA: Code = Code {
+    id: "VX1001",
+    name: "no-schema",
+    description: "The document has no schema reference.",
+};
+
+pub const MALFORMED: Code = Code {
+    id: "VX1002",
+    name: "malformed",
+    description: "The document is not well-formed XML.",
+};
+
+pub const INTERNAL_ERROR: Code = Code {
+    id: "VX1003",
+    name: "internal-error",
+    description: "Validation generated an internal error.",
+};
+
+pub const ERRORS_OMITTED: Code = Code {
+    id: "VX1004",
+    name: "errors-omitted",
+    description:
+        "More errors than --max-error-details were found, and the rest were left out of the report.",
+};
+
+pub const UNDECLARED_ROOT: Code = Code {
+    id: "VX2001",
+    name: "undeclared-root",
+    description: "The schema does not declare the root element.",
+};
+
+pub const INVALID_CONTENT: Code = Code {
+    id: "VX2002",
+    name: "invalid-content",
+    description: "An element has missing, unexpected or misordered content.",
+};
+
+pub const INVALID_ELEMENT: Code = Code {
+    id: "VX2003",
+    name: "invalid-element",
+    description: "An element is not allowed, or is abstract, nil or fixed wrongly.",
+};
+
+pub const UNKNOWN_ATTRIBUTE: Code = Code {
+    id: "VX2004",
+    name: "unknown-attribute",
+    description: "An element has an attribute the schema does not allow.",
+};
+
+pub const MISSING_ATTRIBUTE: Code = Code {
+    id: "VX2005",
+    name: "missing-attribute",
+    description: "An element lacks a required attribute.",
+};
+
+pub const INVALID_VALUE: Code = Code {
+    id: "VX2006",
+    name: "invalid-value",
+    description: "A value does not match its type or facets, e.g. a pattern or enumeration.",
+};
+
+const IDENTITY_CONSTRAINT: Code = Code {
+    id: "VX2007",
+    name: "identity-constraint",
+    description: "A key, unique or keyref constraint is violated.",
+};
+
+pub const NAMESPACE_MISMATCH: Code = Code {
+    id: "VX2008",
+    name: "namespace-mismatch",
+    description: "The root element is not in the schema's target namespace.",
+};
+
+pub const DUPLICATE_ID: Code = Code {
+    id: "VX2009",
+    name: "duplicate-id",
+    description: "An ID is defined more than once in the document (with --check-ids).",
+};
+
+const DANGLING_IDREF: Code = Code {
+    id: "VX2010",
+    name: "dangling-idref",
+    description: "An IDREF refers to an ID the document does not define (with --check-ids).",
+};
+
+const SCHEMA_VALIDITY: Code = Code {
+    id: "VX2099",
+    name: "schema-validity",
+    description: "Some other schema validity error.",
+};
+
+pub const NOT_WELL_FORMED: Code = Code {
+    id: "VX3001",
+    name: "not-well-formed",
+    description: "The XML parser rejected the document.",
+};
+
+const DTD: Code = Code {
+    id: "VX3002",
+    name: "dtd",
+    description: "A DTD error, or an entity or external resource that could not be loaded.",
+};
+
+pub const SCHEMA_NOT_LOADED: Code = Code {
+    id: "VX4001",
+    name: "schema-not-loaded",
+    description: "A schema could not be fetched or is not a valid XML Schema.",
+};
+
+pub const BLOCKED_HOST: Code = Code {
+    id: "VX4002",
+    name: "blocked-host",
+    description: "A remote schema is ruled out by --allowed-hosts, --blocked-hosts, --block-private-addresses or --no-remote-schemas.",
+};
+
+pub const RULE: Code = Code {
+    id: "VX5001",
+    name: "rule",
+    description: "A rule from --rule or --plugin found a problem.",
+};
+
+pub const RULE_FAILED: Code = Code {
+    id: "VX5002",
+    name: "rule-failed",
+    description: "A rule from --rule, --plugin or --script could not check the document.",
+};
+
+pub const SCRIPT: Code = Code {
+    id: "VX5003",
+    name: "script",
+    description: "An assertion of a --script failed.",
+};
+
+pub const ASSERTION: Code = Code {
+    id: "VX5004",
+    name: "assertion",
+    description: "An assert of the configuration file does not hold.",
+};
+
+const OTHER: Code = Code {
+    id: "VX9999",
+    name: "other",
+    description: "Any other libxml2 error.",
+};
+
+/// Every code, in order.
+pub const CATALOG: &[&Code] = &[
+    &NO_SCHEMA,
+    &MALFORMED,
+    &INTERNAL_ERROR,
+    &ERRORS_OMITTED,
+    &UNDECLARED_ROOT,
+    &INVALID_CONTENT,
+    &INVALID_ELEMENT,
+    &UNKNOWN_ATTRIBUTE,
+    &MISSING_ATTRIBUTE,
+    &INVALID_VALUE,
+    &IDENTITY_CONSTRAINT,
+    &NAMESPACE_MISMATCH,
+    &DUPLICATE_ID,
+    &DANGLING_IDREF,
+    &SCHEMA_VALIDITY,
+    &NOT_WELL_FORMED,
+    &DTD,
+    &SCHEMA_NOT_LOADED,
+    &BLOCKED_HOST,
+    &RULE,
+    &RULE_FAILED,
+    &SCRIPT,
+    &ASSERTION,
+    &OTHER,
+];
+
+/// Read the identifier of a code in the catalog, e.g. from a cached result.
+pub fn deserialize_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Id, D::Error> {
+    let id = String::deserialize(deserializer)?;
+    CATALOG
+        .iter()
+        .find(|code| code.id == id)
+        .map(|code| code.id)
+        .ok_or_else(|| D::Error::custom(format!("unknown error code {id:?}")))
+}
+
+// libxml2's xmlErrorDomain values.
+const FROM_PARSER: i32 = 1;
+const FROM_NAMESPACE: i32 = 3;
+const FROM_DTD: i32 = 4;
+pub const FROM_IO: i32 = 8;
+const FROM_HTTP: i32 = 10;
+const FROM_DATATYPE: i32 = 15;
+const FROM_SCHEMASP: i32 = 16;
+const FROM_SCHEMASV: i32 = 17;
+const FROM_VALID: i32 = 23;
+
+/// The `xmlParserErrors` code of a file that libxml2 failed to open.
+pub const XML_IO_LOAD_ERROR: i32 = 1549;
+
+/// The category of a libxml2 error, given its domain and `xmlParserErrors` code.
+pub fn for_libxml2(domain: i32, code: i32) -> &'static Code {
+    match domain {
+        FROM_SCHEMASV | FROM_DATATYPE => match code {
+            // XML_SCHEMAV_NOROOT, XML_SCHEMAV_CVC_ELT_1 and
+            // XML_SCHEMAV_DOCUMENT_ELEMENT_MISSING.
+            1801 | 1845 | 1872 => &UNDECLARED_ROOT,
+            // XML_SCHEMAV_ELEMCONT, XML_SCHEMAV_EXTRACONTENT,
+            // XML_SCHEMAV_CVC_COMPLEX_TYPE_2_*, XML_SCHEMAV_ELEMENT_CONTENT and
+            // XML_SCHEMAV_CVC_COMPLEX_TYPE_1.
+            1810 | 1813 | 1841..=1844 | 1871 | 1873 => &INVALID_CONTENT,
+            // Undeclared, wrong, abstract and non-nillable elements, and
+            // XML_SCHEMAV_CVC_ELT_2 to XML_SCHEMAV_CVC_ELT_7.
+            1802 | 1803 | 1805 | 1808 | 1812 | 1815 | 1846..=1860 => &INVALID_ELEMENT,
+            // XML_SCHEMAV_INVALIDATTR, XML_SCHEMAV_ATTRUNKNOWN and
+            // XML_SCHEMAV_CVC_COMPLEX_TYPE_3_*.
+            1814 | 1820 | 1865..=1867 => &UNKNOWN_ATTRIBUTE,
+            // XML_SCHEMAV_CVC_COMPLEX_TYPE_4.
+            1868 => &MISSING_ATTRIBUTE,
+            // Datatypes, facets, attribute values and XML_SCHEMAV_CVC_TYPE_*.
+            1819 | 1821..=1840 | 1861..=1864 | 1874..=1876 => &INVALID_VALUE,
+            // XML_SCHEMAV_CVC_IDC.
+            1877 => &IDENTITY_CONSTRAINT,
+            _ if domain == FROM_DATATYPE => &INVALID_VALUE,
+            _ => &SCHEMA_VALIDITY,
+        },
+        FROM_PARSER | FROM_NAMESPACE => &NOT_WELL_FORMED,
+        // XML_DTD_UNKNOWN_ID.
+        FROM_VALID if code == 536 => &DANGLING_IDREF,
+        FROM_DTD | FROM_VALID => &DTD,
+        FROM_SCHEMASP | FROM_IO | FROM_HTTP => &SCHEMA_NOT_LOADED,
+        _ => &OTHER,
+    }
+}
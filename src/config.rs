@@ -0,0 +1,684 @@
+//! Configuration files, whose settings act as defaults for command-line options.
+//!
+//! Each setting is named after a long option of the usage text, with dashes written
+//! as underscores, e.g. `schema_rule = ["imports/**=import.xsd"]` for
+//! `--schema-rule=imports/**=import.xsd`. Settings are turned into command-line
+//! arguments before docopt sees them, so every option can be configured and options
+//! given on the command line take precedence.
+
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files looked for in the current directory when `--config` is not given.
+pub const DEFAULT_FILES: &[&str] = &["validate-xml.toml", "validate-xml.json"];
+
+/// Options that only make sense on the command line.
+const COMMAND_LINE_ONLY: &[&str] = &[
+    "help",
+    "version",
+    "generate-man",
+    "config",
+    "profile",
+    "dump-schema",
+    "all-targets",
+];
+
+/// The settings an `[[overrides]]` section may change, besides its `path`.
+pub const OVERRIDABLE: &[&str] = &["extension", "schema", "schema_rule", "fail_on"];
+
+/// The settings of a `[targets.<name>]` section: its directory, which is required,
+/// and the settings it overrides.
+pub const TARGET_SETTINGS: &[&str] = &["dir", "extension", "schema", "schema_rule", "fail_on"];
+
+/// The environment variable selecting a profile when `--profile` is not given.
+pub const PROFILE_VARIABLE: &str = "VALIDATE_XML_PROFILE";
+
+/// The settings of a configuration file.
+pub struct Config {
+    pub path: PathBuf,
+    /// The shared settings, overridden by those of the selected profile.
+    pub settings: Map<String, Value>,
+    /// The selected profile and the settings it overrides.
+    profile: Option<(String, Vec<String>)>,
+    /// The `[[overrides]]` sections, each with the `path` glob of the files it
+    /// applies to.
+    pub overrides: Vec<Map<String, Value>>,
+    /// The `[targets.<name>]` sections by name, checked together by `--all-targets`.
+    pub targets: Vec<(String, Map<String, Value>)>,
+    /// The `assert` XPath assertions checked in every document.
+    pub assertions: Vec<Map<String, Value>>,
+}
+
+/// A long option of the usage text.
+pub struct OptionSpec {
+    /// The long name without dashes, e.g. "schema-rule".
+    pub name: String,
+    short: Option<String>,
+    /// The value placeholder, e.g. "rule" for `--schema-rule=<rule>`.
+    pub placeholder: Option<String>,
+    /// Whether the option may be given several times.
+    pub repeatable: bool,
+    pub default: Option<String>,
+    /// The lines of the option's help text.
+    pub description: Vec<String>,
+}
+
+impl OptionSpec {
+    /// The configuration key of the option, e.g. "schema_rule".
+    pub fn key(&self) -> String {
+        self.name.replace('-', "_")
+    }
+
+    /// The environment variable setting the option, e.g. `VALIDATE_XML_SCHEMA_RULE`.
+    pub fn variable(&self) -> String {
+        format!("VALIDATE_XML_{}", self.key().to_uppercase())
+    }
+
+    pub fn takes_value(&self) -> bool {
+        self.placeholder.is_some()
+    }
+
+    /// Whether the option appears in command-line arguments.
+    fn is_given(&self, args: &[String]) -> bool {
+        command_line_value(self, args).is_some()
+    }
+
+    /// Whether an argument is this option's short flag, alone or bundled with
+    /// others as in `-qv`.
+    fn is_short_flag(&self, arg: &str) -> bool {
+        let Some(short) = self
+            .short
+            .as_deref()
+            .and_then(|short| short.strip_prefix('-'))
+        else {
+            return false;
+        };
+        match arg.strip_prefix('-') {
+            Some(flags) if !self.takes_value() && !flags.starts_with('-') => flags.contains(short),
+            _ => false,
+        }
+    }
+}
+
+/// The options of the usage text that a configuration file may set.
+pub fn options(usage: &str) -> Vec<OptionSpec> {
+    let (patterns, options) = usage.split_once("\nOptions:").unwrap_or((usage, ""));
+    let mut specs: Vec<OptionSpec> = Vec::new();
+    for line in options.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with('-') {
+            // The continuation of an option's help text.
+            if let Some(spec) = specs.last_mut() {
+                spec.description.push(line.to_owned());
+            }
+            continue;
+        }
+        let (flags, text) = line.split_once("  ").unwrap_or((line, ""));
+        let mut spec = OptionSpec {
+            name: String::new(),
+            short: None,
+            placeholder: None,
+            repeatable: false,
+            default: None,
+            description: Vec::new(),
+        };
+        for flag in flags.split_whitespace() {
+            match flag.strip_prefix("--") {
+                Some(long) => {
+                    let (name, placeholder) = match long.split_once('=') {
+                        Some((name, placeholder)) => (name, Some(placeholder)),
+                        None => (long, None),
+                    };
+                    spec.name = name.to_owned();
+                    spec.placeholder = placeholder
+                        .map(|p| p.trim_start_matches('<').trim_end_matches('>').to_owned());
+                }
+                None => spec.short = Some(flag.to_owned()),
+            }
+        }
+        if !text.trim().is_empty() {
+            spec.description.push(text.trim().to_owned());
+        }
+        specs.push(spec);
+    }
+    for spec in &mut specs {
+        if let Some(placeholder) = &spec.placeholder {
+            spec.repeatable = patterns.contains(&format!("[--{}=<{placeholder}>]...", spec.name));
+        }
+        let description = spec.description.join(" ");
+        spec.default = description
+            .split_once("[default: ")
+            .and_then(|(_, rest)| rest.split_once(']'))
+            .map(|(default, _)| default.to_owned());
+    }
+    specs.retain(|spec| !COMMAND_LINE_ONLY.contains(&spec.name.as_str()));
+    specs
+}
+
+/// The value of a long option in command-line arguments not yet parsed by docopt.
+fn option_value(args: &[String], name: &str) -> Option<String> {
+    let long = format!("--{name}");
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if *arg == long {
+            return args.next().cloned();
+        }
+        if let Some(value) = arg.strip_prefix(&format!("{long}=")) {
+            return Some(value.to_owned());
+        }
+    }
+    None
+}
+
+/// Whether the command of command-line arguments not yet parsed by docopt checks
+/// files and so takes configured settings. Merging, comparing and listing reports,
+/// writing or describing configuration files, and printing help, the version, the
+/// man page or a schema take none of the options.
+pub fn applies_to(args: &[String]) -> bool {
+    let args = args.get(1..).unwrap_or_default();
+    let prints = args.iter().any(|arg| {
+        matches!(
+            arg.as_str(),
+            "-h" | "--help" | "--version" | "--generate-man"
+        ) || arg.starts_with("--dump-schema")
+    });
+    let other_command = match args {
+        [command, ..] if matches!(command.as_str(), "merge-reports" | "history" | "diff") => true,
+        [config, command, ..] => config == "config" && (command == "init" || command == "schema"),
+        _ => false,
+    };
+    !prints && !other_command
+}
+
+/// The configuration file given with `--config`, or else a default file in the
+/// current directory, if there is one.
+pub fn find(args: &[String]) -> Option<PathBuf> {
+    option_value(args, "config").map(PathBuf::from).or_else(|| {
+        DEFAULT_FILES
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.is_file())
+    })
+}
+
+/// Read a configuration file and apply the profile given with `--profile`, or else
+/// by the `VALIDATE_XML_PROFILE` environment variable, if any.
+///
+/// Profiles are tables under `profile`, e.g. `[profile.ci]`, whose settings
+/// override the shared ones. A profile's `[[overrides]]`, `[targets.<name>]` and
+/// `assert` replace the shared ones.
+pub fn open(path: &Path, args: &[String]) -> Result<Config, String> {
+    let mut settings = load(path)?;
+    let profiles = match settings.remove("profile") {
+        Some(Value::Object(profiles)) => profiles,
+        None => Map::new(),
+        Some(_) => return Err(format!("{}: \"profile\" is not a table", path.display())),
+    };
+    let name = option_value(args, "profile").or_else(|| {
+        std::env::var(PROFILE_VARIABLE)
+            .ok()
+            .filter(|name| !name.is_empty())
+    });
+    let profile = match name {
+        None => None,
+        Some(name) => {
+            let profile_settings = match profiles.get(&name) {
+                Some(Value::Object(profile_settings)) => profile_settings,
+                Some(_) => {
+                    return Err(format!(
+                        "{}: profile {name:?} is not a table",
+                        path.display()
+                    ))
+                }
+                None => return Err(format!("{}: no profile {name:?}", path.display())),
+            };
+            let keys = profile_settings.keys().cloned().collect();
+            settings.extend(profile_settings.clone());
+            Some((name, keys))
+        }
+    };
+    let mut tables = |key: &str| {
+        let not_tables = || format!("{}: {key:?} is not an array of tables", path.display());
+        match settings.remove(key) {
+            None => Ok(Vec::new()),
+            Some(Value::Array(sections)) => sections
+                .into_iter()
+                .map(|section| match section {
+                    Value::Object(section) => Ok(section),
+                    _ => Err(not_tables()),
+                })
+                .collect(),
+            Some(_) => Err(not_tables()),
+        }
+    };
+    let overrides = tables("overrides")?;
+    let assertions = tables("assert")?;
+    let targets = match settings.remove("targets") {
+        None => Vec::new(),
+        Some(Value::Object(targets)) => targets
+            .into_iter()
+            .map(|(name, target)| match target {
+                Value::Object(target) => Ok((name, target)),
+                _ => Err(format!(
+                    "{}: target {name:?} is not a table",
+                    path.display()
+                )),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => return Err(format!("{}: \"targets\" is not a table", path.display())),
+    };
+    Ok(Config {
+        path: path.to_owned(),
+        settings,
+        profile,
+        overrides,
+        targets,
+        assertions,
+    })
+}
+
+impl Config {
+    /// Where a setting of this file comes from, for `config show`.
+    fn source(&self, key: &str) -> String {
+        match &self.profile {
+            Some((name, keys)) if keys.iter().any(|k| k == key) => {
+                format!("{} [profile.{name}]", self.path.display())
+            }
+            _ => self.path.display().to_string(),
+        }
+    }
+}
+
+/// Read a configuration file: JSON if its name ends in `.json`, TOML otherwise.
+pub fn load(path: &Path) -> Result<Map<String, Value>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let config = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&strip_json_comments(&text)).map_err(|e| e.to_string())
+    } else {
+        toml::from_str(&text).map_err(|e| e.to_string())
+    };
+    config.map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Remove `//` comments and trailing commas, which JSON configuration files may use.
+fn strip_json_comments(text: &str) -> String {
+    let mut without_comments = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            without_comments.push(c);
+            match c {
+                '\\' => without_comments.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '/' && chars.peek() == Some(&'/') {
+            while chars.peek().is_some_and(|&c| c != '\n') {
+                chars.next();
+            }
+        } else {
+            in_string = c == '"';
+            without_comments.push(c);
+        }
+    }
+
+    let mut json = String::with_capacity(without_comments.len());
+    let mut in_string = false;
+    let mut chars = without_comments.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if in_string {
+            json.push(c);
+            match c {
+                '\\' => json.extend(chars.next().map(|(_, c)| c)),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        let rest = without_comments[i + 1..].trim_start();
+        if c == ',' && (rest.starts_with('}') || rest.starts_with(']')) {
+            continue;
+        }
+        in_string = c == '"';
+        json.push(c);
+    }
+    json
+}
+
+/// The settings given by `VALIDATE_XML_*` environment variables, one for every option
+/// a configuration file may set. Flags take true or false (or 1 or 0), and options
+/// that may be repeated take one value per line. They replace the settings of the
+/// same name of the configuration file, so false turns a flag off.
+pub fn environment(specs: &[OptionSpec]) -> Result<Map<String, Value>, String> {
+    let mut settings = Map::new();
+    for spec in specs {
+        let variable = spec.variable();
+        let Some(text) = std::env::var_os(&variable) else {
+            continue;
+        };
+        let text = text
+            .into_string()
+            .map_err(|_| format!("{variable} is not valid Unicode"))?;
+        let value = if !spec.takes_value() {
+            match text.trim().to_ascii_lowercase().as_str() {
+                "true" | "1" => Value::Bool(true),
+                "false" | "0" | "" => Value::Bool(false),
+                _ => return Err(format!("{variable} must be true or false")),
+            }
+        } else if spec.repeatable {
+            text.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| Value::String(line.to_owned()))
+                .collect()
+        } else {
+            Value::String(text)
+        };
+        settings.insert(spec.key(), value);
+    }
+    Ok(settings)
+}
+
+/// Add the settings of `config` to the command-line arguments `args`, except for
+/// options that are already given there.
+pub fn apply(
+    mut args: Vec<String>,
+    config: &Map<String, Value>,
+    specs: &[OptionSpec],
+) -> Result<Vec<String>, String> {
+    let mut extra = Vec::new();
+    for (key, value) in config {
+        let spec = specs
+            .iter()
+            .find(|spec| spec.key() == *key)
+            .ok_or_else(|| format!("unknown configuration setting {key:?}"))?;
+        if spec.is_given(&args) {
+            continue;
+        }
+        extra.extend(arguments(spec, value)?);
+    }
+    args.splice(1..1, extra);
+    Ok(args)
+}
+
+/// The command-line arguments for setting an option to a configured value.
+fn arguments(spec: &OptionSpec, value: &Value) -> Result<Vec<String>, String> {
+    let key = spec.key();
+    let scalar = |value: &Value| match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => Err(format!("setting {key:?} must be a string or a number")),
+    };
+    match (value, spec.takes_value()) {
+        (Value::Bool(true), false) => Ok(vec![format!("--{}", spec.name)]),
+        (Value::Bool(false), false) => Ok(Vec::new()),
+        (_, false) => Err(format!("setting {key:?} must be true or false")),
+        (Value::Array(values), true) if spec.repeatable => values
+            .iter()
+            .map(|value| Ok(format!("--{}={}", spec.name, scalar(value)?)))
+            .collect(),
+        (Value::Array(_), true) => Err(format!("setting {key:?} takes a single value")),
+        (value, true) => Ok(vec![format!("--{}={}", spec.name, scalar(value)?)]),
+    }
+}
+
+/// The help text of an option without its default, which is shown as its value.
+fn help_lines(spec: &OptionSpec) -> Vec<String> {
+    spec.description
+        .iter()
+        .map(|line| match line.split_once("[default: ") {
+            Some((before, after)) => {
+                let after = after.split_once(']').map_or("", |(_, after)| after);
+                format!("{}{after}", before.trim_end())
+            }
+            None => line.clone(),
+        })
+        .map(|line| line.trim().to_owned())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// The value shown for an option in a new configuration file.
+fn example_value(spec: &OptionSpec) -> Value {
+    match (&spec.placeholder, &spec.default) {
+        (None, _) => Value::Bool(false),
+        (Some(_), _) if spec.repeatable => Value::Array(Vec::new()),
+        (Some(_), Some(default)) => default
+            .parse::<i64>()
+            .map(Value::from)
+            .unwrap_or_else(|_| Value::String(default.clone())),
+        (Some(placeholder), None) => Value::String(format!("<{placeholder}>")),
+    }
+}
+
+const HEADER: &[&str] = &[
+    "Configuration for validate-xml.",
+    "",
+    "Each setting is the long command-line option of the same name, with dashes",
+    "written as underscores. Options given on the command line or in VALIDATE_XML_*",
+    "environment variables take precedence.",
+    "Uncomment a setting to change it.",
+];
+
+/// A new configuration file in `format`, "toml" or "json", with every option
+/// commented out at its default and explained.
+pub fn init(specs: &[OptionSpec], format: &str) -> Result<String, String> {
+    let mut text = String::new();
+    match format {
+        "toml" => {
+            for line in HEADER {
+                text += &format!("#{}{line}\n", if line.is_empty() { "" } else { " " });
+            }
+            for spec in specs {
+                text += "\n";
+                for line in help_lines(spec) {
+                    text += &format!("# {line}\n");
+                }
+                text += &format!("# {} = {}\n", spec.key(), example_value(spec));
+            }
+        }
+        "json" => {
+            text += "{\n";
+            for line in HEADER {
+                text += &format!("  //{}{line}\n", if line.is_empty() { "" } else { " " });
+            }
+            for spec in specs {
+                text += "\n";
+                for line in help_lines(spec) {
+                    text += &format!("  // {line}\n");
+                }
+                text += &format!("  // \"{}\": {},\n", spec.key(), example_value(spec));
+            }
+            text += "}\n";
+        }
+        _ => return Err(format!("unknown configuration format {format:?}")),
+    }
+    Ok(text)
+}
+
+/// The value of an option given in command-line arguments, if it is. Arguments after
+/// `--` are not options.
+fn command_line_value(spec: &OptionSpec, args: &[String]) -> Option<Value> {
+    let long = format!("--{}", spec.name);
+    let mut values = Vec::new();
+    let mut args = args.iter().take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        if let Some(value) = arg.strip_prefix(&format!("{long}=")) {
+            values.push(Value::String(value.to_owned()));
+        } else if *arg == long || spec.short.as_ref() == Some(arg) || spec.is_short_flag(arg) {
+            if spec.takes_value() {
+                values.extend(args.next().map(|value| Value::String(value.clone())));
+            } else {
+                values.push(Value::Bool(true));
+            }
+        }
+    }
+    match values.len() {
+        0 => None,
+        _ if spec.repeatable => Some(Value::Array(values)),
+        _ => values.pop(),
+    }
+}
+
+/// Every option's effective value and where it comes from, one per line, given the
+/// command-line arguments, the environment settings and the configuration file, if
+/// any.
+pub fn show(
+    args: &[String],
+    environment: &Map<String, Value>,
+    config: Option<&Config>,
+    specs: &[OptionSpec],
+) -> String {
+    let mut text = String::new();
+    if let Some((name, _)) = config.and_then(|config| config.profile.as_ref()) {
+        text += &format!("# profile: {name}\n");
+    }
+    for spec in specs {
+        let key = spec.key();
+        let from_file = config.and_then(|config| Some((config, config.settings.get(&key)?)));
+        let (value, source) = match (command_line_value(spec, args), from_file) {
+            (Some(value), _) => (value, "command line".to_owned()),
+            (None, _) if environment.contains_key(&key) => {
+                (environment[&key].clone(), spec.variable())
+            }
+            (None, Some((config, value))) => (value.clone(), config.source(&key)),
+            (None, None) if spec.takes_value() && spec.default.is_none() && !spec.repeatable => {
+                text += &format!("# {key} is not set\n");
+                continue;
+            }
+            (None, None) => (example_value(spec), "default".to_owned()),
+        };
+        text += &format!("{key} = {value}  # {source}\n");
+    }
+    for section in config.iter().flat_map(|config| &config.overrides) {
+        text += "\n[[overrides]]\n";
+        for (key, value) in section {
+            text += &format!("{key} = {value}\n");
+        }
+    }
+    for section in config.iter().flat_map(|config| &config.assertions) {
+        text += "\n[[assert]]\n";
+        for (key, value) in section {
+            text += &format!("{key} = {value}\n");
+        }
+    }
+    for (name, section) in config.iter().flat_map(|config| &config.targets) {
+        text += &format!("\n[targets.{name}]\n");
+        for (key, value) in section {
+            text += &format!("{key} = {value}\n");
+        }
+    }
+    text
+}
+
+/// A JSON Schema of configuration files, for editors and linters.
+pub fn schema(specs: &[OptionSpec]) -> Value {
+    let mut settings: Map<String, Value> = specs
+        .iter()
+        .map(|spec| {
+            let mut property = match (spec.takes_value(), spec.repeatable) {
+                (false, _) => json!({ "type": "boolean" }),
+                (true, false) => json!({ "type": ["string", "number"] }),
+                (true, true) => {
+                    json!({ "type": "array", "items": { "type": ["string", "number"] } })
+                }
+            };
+            property["description"] = Value::String(help_lines(spec).join(" "));
+            if spec.default.is_some() || !spec.takes_value() {
+                property["default"] = example_value(spec);
+            }
+            (spec.key(), property)
+        })
+        .collect();
+    let mut section: Map<String, Value> = OVERRIDABLE
+        .iter()
+        .filter_map(|key| Some((key.to_string(), settings.get(*key)?.clone())))
+        .collect();
+    section.insert(
+        "path".to_owned(),
+        json!({
+            "type": "string",
+            "description": "Glob of the files the section applies to, relative to the checked directory.",
+        }),
+    );
+    settings.insert(
+        "overrides".to_owned(),
+        json!({
+            "type": "array",
+            "description": "Settings for the files matching a path glob. The last matching section wins.",
+            "items": {
+                "type": "object",
+                "properties": section,
+                "required": ["path"],
+                "additionalProperties": false,
+            },
+        }),
+    );
+    let mut target: Map<String, Value> = TARGET_SETTINGS
+        .iter()
+        .filter_map(|key| Some((key.to_string(), settings.get(*key)?.clone())))
+        .collect();
+    target.insert(
+        "dir".to_owned(),
+        json!({
+            "type": "string",
+            "description": "Directory of the target, relative to the configuration file.",
+        }),
+    );
+    settings.insert(
+        "targets".to_owned(),
+        json!({
+            "type": "object",
+            "description": "Named directories checked together by --all-targets, each with its own settings.",
+            "additionalProperties": {
+                "type": "object",
+                "properties": target,
+                "required": ["dir"],
+                "additionalProperties": false,
+            },
+        }),
+    );
+    settings.insert(
+        "assert".to_owned(),
+        json!({
+            "type": "array",
+            "description": "XPath assertions that every parsed document must satisfy.",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "xpath": { "type": "string", "description": "The XPath expression to check." },
+                    "exists": { "type": "boolean", "description": "Whether it must select something, or nothing." },
+                    "equals": { "type": "string", "description": "The string value it must have." },
+                    "matches": { "type": "string", "description": "A regex that its string value must match." },
+                    "message": { "type": "string", "description": "The error to report when the assertion fails." },
+                },
+                "required": ["xpath"],
+                "additionalProperties": false,
+            },
+        }),
+    );
+    let mut properties = settings.clone();
+    properties.insert(
+        "profile".to_owned(),
+        json!({
+            "type": "object",
+            "description": "Profiles selected with --profile, overriding the shared settings.",
+            "additionalProperties": {
+                "type": "object",
+                "properties": settings,
+                "additionalProperties": false,
+            },
+        }),
+    );
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "validate-xml configuration",
+        "type": "object",
+        "properties": properties,
+        "additionalProperties": false,
+    })
+}
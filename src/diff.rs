@@ -0,0 +1,192 @@
+//! Comparing two json reports, e.g. of the main branch and of a change, to gate on
+//! what got worse without keeping a baseline.
+
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+
+/// What a report says about a file.
+struct Outcome {
+    status: String,
+    /// Distinct error messages, ignoring line numbers as baselines do.
+    errors: BTreeSet<String>,
+}
+
+impl Outcome {
+    fn fails(&self) -> bool {
+        matches!(
+            self.status.as_str(),
+            "invalid" | "malformed" | "internal_error"
+        )
+    }
+}
+
+/// The outcomes in a json report by path.
+fn load(path: &str) -> Result<BTreeMap<String, Outcome>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let report: Value = serde_json::from_str(&text).map_err(|e| format!("{path}: {e}"))?;
+    let files = report["files"]
+        .as_array()
+        .ok_or_else(|| format!("{path}: \"files\" is not an array"))?;
+    Ok(files
+        .iter()
+        .map(|file| {
+            let text = |value: &Value| value.as_str().unwrap_or_default().to_owned();
+            let errors = file["errors"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|error| text(&error["message"]))
+                .collect();
+            let outcome = Outcome {
+                status: text(&file["status"]),
+                errors,
+            };
+            (text(&file["path"]), outcome)
+        })
+        .collect())
+}
+
+/// Print the files that fail in the new report but did not in the old one, those
+/// that no longer fail, and those that fail with other errors. Returns whether any
+/// file newly fails.
+pub fn run(old_path: &str, new_path: &str, out: &mut dyn Write) -> Result<bool, String> {
+    let old = load(old_path)?;
+    let new = load(new_path)?;
+
+    let mut newly_failing = Vec::new();
+    let mut newly_passing = Vec::new();
+    let mut changed = Vec::new();
+    for (path, outcome) in &new {
+        match old.get(path) {
+            Some(before)
+                if before.fails() && outcome.fails() && before.errors != outcome.errors =>
+            {
+                changed.push(format!(
+                    "{path}: {} distinct errors, was {}",
+                    outcome.errors.len(),
+                    before.errors.len()
+                ));
+            }
+            Some(before) if before.fails() && outcome.fails() => {}
+            Some(before) if before.fails() => {
+                newly_passing.push(format!(
+                    "{path} ({}, was {})",
+                    outcome.status, before.status
+                ));
+            }
+            before if outcome.fails() => {
+                let was = before.map_or("absent", |before| before.status.as_str());
+                newly_failing.push(format!("{path} ({}, was {was})", outcome.status));
+            }
+            _ => {}
+        }
+    }
+
+    let write_error = |e: std::io::Error| e.to_string();
+    for (title, lines) in [
+        ("Newly failing", &newly_failing),
+        ("Newly passing", &newly_passing),
+        ("Failing with changed errors", &changed),
+    ] {
+        writeln!(out, "{title}: {}", lines.len()).map_err(write_error)?;
+        for line in lines {
+            writeln!(out, "  {line}").map_err(write_error)?;
+        }
+    }
+    Ok(!newly_failing.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A json report of `(path, status, error messages)` in `dir`.
+    fn report(dir: &tempfile::TempDir, name: &str, files: &[(&str, &str, &[&str])]) -> String {
+        let files: Vec<_> = files
+            .iter()
+            .map(|(path, status, errors)| {
+                let errors: Vec<_> = errors
+                    .iter()
+                    .map(|message| json!({ "line": 1, "message": message }))
+                    .collect();
+                json!({ "path": path, "status": status, "errors": errors })
+            })
+            .collect();
+        let path = dir.path().join(name);
+        fs::write(&path, json!({ "files": files }).to_string()).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn diff(old: &str, new: &str) -> (bool, String) {
+        let mut out = Vec::new();
+        let worse = run(old, new, &mut out).unwrap();
+        (worse, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = report(
+            &dir,
+            "old.json",
+            &[
+                ("fixed.xml", "invalid", &["a"]),
+                ("broken.xml", "valid", &[]),
+                ("changed.xml", "invalid", &["a"]),
+                ("same.xml", "malformed", &["a"]),
+            ],
+        );
+        let new = report(
+            &dir,
+            "new.json",
+            &[
+                ("added.xml", "malformed", &["a"]),
+                ("broken.xml", "invalid", &["a"]),
+                ("changed.xml", "invalid", &["a", "b", "b"]),
+                ("fixed.xml", "valid", &[]),
+                ("same.xml", "malformed", &["a", "a"]),
+            ],
+        );
+        assert_eq!(
+            diff(&old, &new),
+            (
+                true,
+                "Newly failing: 2\n\
+                 \x20 added.xml (malformed, was absent)\n\
+                 \x20 broken.xml (invalid, was valid)\n\
+                 Newly passing: 1\n\
+                 \x20 fixed.xml (valid, was invalid)\n\
+                 Failing with changed errors: 1\n\
+                 \x20 changed.xml: 2 distinct errors, was 1\n"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn nothing_newly_failing() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = report(&dir, "old.json", &[("a.xml", "invalid", &["a"])]);
+        let new = report(&dir, "new.json", &[("a.xml", "no_schema", &[])]);
+        let (worse, out) = diff(&old, &new);
+        assert!(!worse);
+        assert!(out.starts_with("Newly failing: 0\nNewly passing: 1\n"));
+    }
+
+    #[test]
+    fn bad_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = report(&dir, "good.json", &[]);
+        let bad = dir.path().join("bad.json");
+        fs::write(&bad, r#"{"files": {}}"#).unwrap();
+        let bad = bad.to_str().unwrap();
+        let error = run(&good, bad, &mut Vec::new()).unwrap_err();
+        assert_eq!(error, format!("{bad}: \"files\" is not an array"));
+        let missing = dir.path().join("missing.json");
+        let error = run(missing.to_str().unwrap(), &good, &mut Vec::new()).unwrap_err();
+        assert!(error.starts_with(missing.to_str().unwrap()));
+    }
+}
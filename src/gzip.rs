@@ -0,0 +1,32 @@
+//! Reading gzip-compressed documents, e.g. `record.cmdi.gz`.
+
+use flate2::read::MultiGzDecoder;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Whether a file is compressed, judging by its `.gz` extension.
+pub fn is_compressed(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "gz")
+}
+
+/// Read a document, decompressing it if it is compressed.
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    if !is_compressed(path) {
+        return fs::read(path);
+    }
+    let mut content = Vec::new();
+    // Concatenated gzip members are read as one document, as zlib does.
+    MultiGzDecoder::new(File::open(path)?)
+        .read_to_end(&mut content)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidInput
+            | io::ErrorKind::InvalidData
+            | io::ErrorKind::UnexpectedEof => io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not valid gzip data", path.display()),
+            ),
+            _ => e,
+        })?;
+    Ok(content)
+}
@@ -0,0 +1,103 @@
+//! Explanations of common libxml2 messages, with suggested fixes.
+//!
+//! libxml2 words its messages after the XML Schema specification. The hints here
+//! say what is usually wrong with the document and how to fix it. They are matched
+//! against the message text, so messages of other libxml2 versions may go without a
+//! hint.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Message patterns and their hints, in which `$name` stands for a capture group.
+const HINTS: &[(&str, &str)] = &[
+    (
+        r"^Element '(?P<element>[^']+)': This element is not expected\. Expected is one of \( (?P<expected>.+) \)\.$",
+        "$element is out of place: the schema expects one of $expected here. Check the spelling, order and namespace of the child elements.",
+    ),
+    (
+        r"^Element '(?P<element>[^']+)': This element is not expected\. Expected is \( (?P<expected>.+) \)\.$",
+        "$element is out of place: the schema expects $expected here. Check the spelling, order and namespace of the child elements.",
+    ),
+    (
+        r"^Element '(?P<element>[^']+)': This element is not expected\.$",
+        "The enclosing element allows no further children here. Remove $element, or move it to where the schema allows it.",
+    ),
+    (
+        r"^Element '(?P<element>[^']+)': Missing child element\(s\)\. Expected is one of \( (?P<expected>.+) \)\.$",
+        "$element is incomplete: add one of $expected at its end.",
+    ),
+    (
+        r"^Element '(?P<element>[^']+)': Missing child element\(s\)\. Expected is \( (?P<expected>.+) \)\.$",
+        "$element is incomplete: add $expected at its end.",
+    ),
+    (
+        r"^Element '(?P<element>[^']+)': No matching global declaration available for the validation root\.$",
+        "The schema does not declare the root element $element. Check that the document's namespace (xmlns) matches the schema's targetNamespace, and that schemaLocation names the right schema.",
+    ),
+    (
+        r"^The namespace of the root element is (?P<actual>.+), but the schema's target namespace is (?P<expected>.+)\.$",
+        "Elements the schema declares are in the namespace $expected. Set xmlns on the root element to it, or check that this is the right schema.",
+    ),
+    (
+        r"^Element '(?P<element>[^']+)', attribute '(?P<attribute>[^']+)': The attribute '[^']+' is not allowed\.$",
+        "$element may not have the $attribute attribute. Check its spelling, or remove it.",
+    ),
+    (
+        r"^Element '(?P<element>[^']+)': The attribute '(?P<attribute>[^']+)' is required but missing\.$",
+        "Add the $attribute attribute to $element.",
+    ),
+    (
+        r"\[facet 'enumeration'\] The value '(?P<value>[^']*)' is not an element of the set \{(?P<set>.*)\}\.$",
+        "'$value' is not one of the allowed values: use one of $set.",
+    ),
+    (
+        r"\[facet 'pattern'\] The value '(?P<value>[^']*)' is not accepted by the pattern '(?P<pattern>.*)'\.$",
+        "Change '$value' to match the pattern $pattern.",
+    ),
+    (
+        r"\[facet '(?P<facet>(min|max)(Length|Inclusive|Exclusive)|length)'\]",
+        "The value breaks the schema's $facet limit; see the message for the allowed bound.",
+    ),
+    (
+        r"'(?P<value>[^']*)' is not a valid value of the (atomic|list|union) type '(?P<type>[^']+)'\.$",
+        "'$value' is not a valid $type. Correct the value, and check for stray whitespace.",
+    ),
+    (
+        r"Element '(?P<element>[^']+)': Element content is not allowed, because the content type is empty\.$",
+        "$element must be empty: remove its text and child elements.",
+    ),
+    (
+        r"Element '(?P<element>[^']+)': Character content other than whitespace is not allowed",
+        "$element may only contain elements: remove or wrap its text.",
+    ),
+    (
+        r"Failed to locate a schema at location '(?P<location>[^']+)'|failed to load (external entity|HTTP resource) \x22(?P<resource>[^\x22]+)\x22",
+        "Could not load ${location}${resource}. Check the URL and network access, or validate against a local copy with --schema.",
+    ),
+    (
+        r"^Skipping import of schema located at '(?P<location>[^']+)' for the namespace '(?P<namespace>[^']+)', since the namespace was already imported",
+        "Harmless: another schema already imported $namespace, so $location was not read. Make every schema import the same location to silence this.",
+    ),
+    (
+        r"The target namespace '(?P<actual>[^']*)' of the (included|redefined|imported) schema '(?P<location>[^']+)' (differs from|has to be absent|does not match)",
+        "$location declares the namespace '$actual', which does not fit how it is included or imported. Point the import at the right schema, or fix its namespace attribute.",
+    ),
+];
+
+lazy_static! {
+    static ref PATTERNS: Vec<(Regex, &'static str)> = HINTS
+        .iter()
+        .map(|&(pattern, hint)| (Regex::new(pattern).expect("invalid hint pattern"), hint))
+        .collect();
+}
+
+/// An explanation of a libxml2 message and how to fix the problem, if it is a
+/// common one.
+pub fn hint(message: &str) -> Option<String> {
+    PATTERNS.iter().find_map(|(pattern, hint)| {
+        let captures = pattern.captures(message)?;
+        let mut text = String::new();
+        captures.expand(hint, &mut text);
+        Some(text)
+    })
+}
@@ -0,0 +1,352 @@
+//! A history of runs in an SQLite database, to follow trends across runs: new
+//! failures, flaky files and files that got slower. Needs the `history` feature,
+//! which is on by default.
+
+#[cfg(feature = "history")]
+use rusqlite::{params, Connection};
+#[cfg(feature = "history")]
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+#[cfg(feature = "history")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{FileResult, Status};
+
+#[cfg(not(feature = "history"))]
+const WITHOUT_HISTORY: &str = "validate-xml was built without the history feature";
+
+#[cfg(feature = "history")]
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    -- Seconds since the Unix epoch.
+    started REAL NOT NULL,
+    root TEXT NOT NULL,
+    duration_ms REAL NOT NULL,
+    files INTEGER NOT NULL,
+    valid INTEGER NOT NULL,
+    invalid INTEGER NOT NULL,
+    malformed INTEGER NOT NULL,
+    internal_error INTEGER NOT NULL,
+    no_schema INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS files (
+    run INTEGER NOT NULL REFERENCES runs (id),
+    -- Relative to the root of the run.
+    path TEXT NOT NULL,
+    status TEXT NOT NULL,
+    error_count INTEGER NOT NULL,
+    duration_ms REAL NOT NULL,
+    PRIMARY KEY (run, path)
+);
+";
+
+/// The statuses that count as failing in trends, whatever `--require-schema` was.
+#[cfg(feature = "history")]
+const FAILING: &str = "('invalid', 'malformed', 'internal_error')";
+
+/// The outcomes of the files of a run, gathered as they are checked.
+#[derive(Default)]
+pub struct History {
+    files: Vec<(String, Status, usize, f64)>,
+}
+
+impl History {
+    pub fn record(&mut self, relative_path: &Path, result: &FileResult) {
+        self.files.push((
+            relative_path.to_string_lossy().into_owned(),
+            result.status,
+            result.errors.len(),
+            result.duration_ms,
+        ));
+    }
+
+    /// Append the run, which took `run`, to the database, creating it if needed.
+    pub fn save(&self, path: &str, root: &Path, run: Duration) -> Result<(), String> {
+        #[cfg(feature = "history")]
+        return self.insert(path, root, run).map_err(|e| e.to_string());
+        #[cfg(not(feature = "history"))]
+        {
+            let _ = (path, root, run);
+            Err(WITHOUT_HISTORY.to_owned())
+        }
+    }
+
+    #[cfg(feature = "history")]
+    fn insert(&self, path: &str, root: &Path, run: Duration) -> rusqlite::Result<()> {
+        let mut db = Connection::open(path)?;
+        db.execute_batch(SCHEMA)?;
+        let transaction = db.transaction()?;
+
+        let count = |status| {
+            self.files
+                .iter()
+                .filter(|(_, file_status, ..)| *file_status == status)
+                .count() as i64
+        };
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .saturating_sub(run);
+        transaction.execute(
+            "INSERT INTO runs (started, root, duration_ms, files, valid, invalid, malformed,
+                               internal_error, no_schema)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                started.as_secs_f64(),
+                root.to_string_lossy(),
+                run.as_secs_f64() * 1000.0,
+                self.files.len() as i64,
+                count(Status::Valid),
+                count(Status::Invalid),
+                count(Status::Malformed),
+                count(Status::InternalError),
+                count(Status::NoSchema),
+            ],
+        )?;
+        let run_id = transaction.last_insert_rowid();
+
+        {
+            let mut insert_file = transaction.prepare(
+                "INSERT OR REPLACE INTO files (run, path, status, error_count, duration_ms)
+                 VALUES (?, ?, ?, ?, ?)",
+            )?;
+            for (path, status, error_count, duration_ms) in &self.files {
+                insert_file.execute(params![
+                    run_id,
+                    path,
+                    status.name(),
+                    *error_count as i64,
+                    duration_ms
+                ])?;
+            }
+        }
+        transaction.commit()
+    }
+}
+
+/// Print the trends of the last `last` runs recorded in a database: their
+/// summaries, the files that fail in the latest run but did not in the one before,
+/// the files that went between passing and failing more than once, and the files
+/// that took much longer in the latest run than on average.
+pub fn report(path: &str, last: usize, out: &mut dyn Write) -> Result<(), String> {
+    if !Path::new(path).exists() {
+        return Err(format!("{path} does not exist"));
+    }
+    #[cfg(feature = "history")]
+    return write_report(path, last, out).map_err(|e| e.to_string());
+    #[cfg(not(feature = "history"))]
+    {
+        let _ = (last, out);
+        Err(WITHOUT_HISTORY.to_owned())
+    }
+}
+
+#[cfg(feature = "history")]
+fn write_report(path: &str, last: usize, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let db = Connection::open(path)?;
+    db.execute_batch(SCHEMA)?;
+
+    let mut runs = db.prepare(
+        "SELECT id, datetime(started, 'unixepoch'), files, valid, invalid, malformed,
+                internal_error, no_schema, duration_ms
+         FROM runs ORDER BY id DESC LIMIT ?",
+    )?;
+    let mut rows = runs.query([last as i64])?;
+    let mut ids = Vec::new();
+    writeln!(out, "Recent runs:")?;
+    while let Some(run) = rows.next()? {
+        ids.push(run.get::<_, i64>(0)?);
+        writeln!(
+            out,
+            "  #{} {} UTC: {} files: {} valid, {} invalid, {} malformed, {} internal errors, {} without schema, in {:.1} s",
+            run.get::<_, i64>(0)?,
+            run.get::<_, String>(1)?,
+            run.get::<_, i64>(2)?,
+            run.get::<_, i64>(3)?,
+            run.get::<_, i64>(4)?,
+            run.get::<_, i64>(5)?,
+            run.get::<_, i64>(6)?,
+            run.get::<_, i64>(7)?,
+            run.get::<_, f64>(8)? / 1000.0
+        )?;
+    }
+    let (Some(&latest), Some(&previous)) = (ids.first(), ids.get(1)) else {
+        return Ok(());
+    };
+    let oldest = *ids.last().unwrap();
+
+    let mut new_failures = db.prepare(&format!(
+        "SELECT path, status FROM files AS latest
+         WHERE run = ?1 AND status IN {FAILING}
+           AND NOT EXISTS (SELECT 1 FROM files AS previous
+                           WHERE previous.run = ?2 AND previous.path = latest.path
+                             AND previous.status IN {FAILING})
+         ORDER BY path"
+    ))?;
+    let mut rows = new_failures.query([latest, previous])?;
+    writeln!(out, "New failures in run #{latest}:")?;
+    while let Some(file) = rows.next()? {
+        writeln!(
+            out,
+            "  {} ({})",
+            file.get::<_, String>(0)?,
+            file.get::<_, String>(1)?
+        )?;
+    }
+
+    let mut flaky = db.prepare(&format!(
+        "SELECT path, SUM(changed) AS changes FROM (
+             SELECT path,
+                    (status IN {FAILING})
+                        <> LAG(status IN {FAILING}) OVER (PARTITION BY path ORDER BY run)
+                        AS changed
+             FROM files WHERE run >= ?1)
+         GROUP BY path HAVING changes >= 2
+         ORDER BY changes DESC, path"
+    ))?;
+    let mut rows = flaky.query([oldest])?;
+    writeln!(out, "Flaky files:")?;
+    while let Some(file) = rows.next()? {
+        writeln!(
+            out,
+            "  {}: {} changes between passing and failing",
+            file.get::<_, String>(0)?,
+            file.get::<_, i64>(1)?
+        )?;
+    }
+
+    // Only files that took at least 10 ms longer, to leave out noise.
+    let mut slower = db.prepare(
+        "SELECT latest.path, latest.duration_ms, AVG(earlier.duration_ms) AS average
+         FROM files AS latest
+         JOIN files AS earlier
+           ON earlier.path = latest.path AND earlier.run >= ?2 AND earlier.run < ?1
+         WHERE latest.run = ?1
+         GROUP BY latest.path
+         HAVING latest.duration_ms > 2 * average AND latest.duration_ms - average >= 10
+         ORDER BY latest.duration_ms - average DESC
+         LIMIT 10",
+    )?;
+    let mut rows = slower.query([latest, oldest])?;
+    writeln!(out, "Slower in run #{latest} than on average:")?;
+    while let Some(file) = rows.next()? {
+        writeln!(
+            out,
+            "  {}: {:.1} ms, {:.1} ms on average",
+            file.get::<_, String>(0)?,
+            file.get::<_, f64>(1)?,
+            file.get::<_, f64>(2)?
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "history"))]
+mod tests {
+    use super::*;
+
+    /// Save a run of files with their status and duration in milliseconds.
+    fn save_run(db: &str, files: &[(&str, Status, f64)]) {
+        let mut history = History::default();
+        for &(path, status, duration_ms) in files {
+            let mut result = FileResult::new(path, status);
+            result.duration_ms = duration_ms;
+            history.record(Path::new(path), &result);
+        }
+        history
+            .save(db, Path::new("corpus"), Duration::from_millis(20))
+            .unwrap();
+    }
+
+    fn report_of(db: &str) -> String {
+        let mut out = Vec::new();
+        report(db, 10, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn saved_runs_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("history.db");
+        let db = db.to_str().unwrap();
+        save_run(
+            db,
+            &[
+                ("a.cmdi", Status::Valid, 5.0),
+                ("b.cmdi", Status::Valid, 5.0),
+                ("c.cmdi", Status::Invalid, 5.0),
+            ],
+        );
+        save_run(
+            db,
+            &[
+                ("a.cmdi", Status::Invalid, 5.0),
+                ("b.cmdi", Status::Valid, 5.0),
+                ("c.cmdi", Status::Valid, 5.0),
+            ],
+        );
+        save_run(
+            db,
+            &[
+                ("a.cmdi", Status::Valid, 5.0),
+                ("b.cmdi", Status::Malformed, 5.0),
+                ("c.cmdi", Status::Valid, 100.0),
+            ],
+        );
+
+        let report = report_of(db);
+        let runs: Vec<&str> = report
+            .lines()
+            .filter(|line| line.starts_with("  #"))
+            .collect();
+        assert_eq!(runs.len(), 3, "{report}");
+        assert!(runs[0].starts_with("  #3 "), "{report}");
+        assert!(
+            runs[0].ends_with(
+                "3 files: 2 valid, 0 invalid, 1 malformed, 0 internal errors, 0 without schema, in 0.0 s"
+            ),
+            "{report}"
+        );
+        assert!(runs[2].starts_with("  #1 "), "{report}");
+        assert!(
+            report.contains("New failures in run #3:\n  b.cmdi (malformed)\nFlaky files:\n"),
+            "{report}"
+        );
+        assert!(
+            report.contains("Flaky files:\n  a.cmdi: 2 changes between passing and failing\n"),
+            "{report}"
+        );
+        assert!(
+            report.ends_with(
+                "Slower in run #3 than on average:\n  c.cmdi: 100.0 ms, 5.0 ms on average\n"
+            ),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn one_run_has_no_trends() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("history.db");
+        let db = db.to_str().unwrap();
+        save_run(db, &[("a.cmdi", Status::NoSchema, 1.0)]);
+
+        let report = report_of(db);
+        assert_eq!(report.lines().count(), 2, "{report}");
+        assert!(report.contains(
+            "1 files: 0 valid, 0 invalid, 0 malformed, 0 internal errors, 1 without schema"
+        ));
+    }
+
+    #[test]
+    fn missing_database_is_not_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("history.db");
+        let error = report(db.to_str().unwrap(), 10, &mut Vec::new()).unwrap_err();
+        assert!(error.ends_with("does not exist"), "{error}");
+        assert!(!db.exists());
+    }
+}
@@ -0,0 +1,88 @@
+//! Diagnostic logging through `tracing`, to stderr or a log file.
+//!
+//! Logs never go to stdout, which is reserved for machine-readable reports.
+
+use std::fmt::{self, Write as _};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use crate::progress;
+
+/// Writes each enabled event as a line, e.g. `INFO Downloading now http://...`.
+struct Logger {
+    level: LevelFilter,
+    /// The log file, or `None` for stderr.
+    file: Option<Mutex<Box<dyn Write + Send>>>,
+    next_span: AtomicU64,
+}
+
+/// Formats an event's message followed by its other fields as `name=value`.
+struct Line(String);
+
+impl Visit for Line {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, " {value:?}");
+        } else {
+            let _ = write!(self.0, " {}={value:?}", field.name());
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            let _ = write!(self.0, " {value}");
+        } else {
+            let _ = write!(self.0, " {}={value}", field.name());
+        }
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= &self.level
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.level)
+    }
+
+    fn new_span(&self, _span: &Attributes) -> Id {
+        Id::from_u64(self.next_span.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn record(&self, _span: &Id, _values: &Record) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event) {
+        let mut line = Line(event.metadata().level().to_string());
+        event.record(&mut line);
+        line.0.push('\n');
+        // Logging is best effort: failing to log must not stop validation.
+        let _ = match &self.file {
+            Some(file) => file.lock().unwrap().write_all(line.0.as_bytes()),
+            None => progress::suspend(|| io::stderr().write_all(line.0.as_bytes())),
+        };
+    }
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+/// Log events at `level` or more severe to `file`, or else to stderr.
+pub fn init(level: LevelFilter, file: Option<Box<dyn Write + Send>>) {
+    let logger = Logger {
+        level,
+        file: file.map(Mutex::new),
+        // Span ids must not be 0.
+        next_span: AtomicU64::new(1),
+    };
+    tracing::subscriber::set_global_default(logger).unwrap();
+}
@@ -0,0 +1,284 @@
+//! A Language Server Protocol server on stdin and stdout, publishing validation errors
+//! of open documents as diagnostics.
+//!
+//! Documents are validated as they are opened, changed and saved, with full text
+//! synchronization. Schemas are found as for files on disk, and stay cached in memory.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+use crate::{codes, hinted_schema_urls, validate_memory, FileResult, Settings, Severity, Status};
+
+/// Serve a client on stdin and stdout until it exits.
+pub fn run(settings: &Settings) -> io::Result<()> {
+    serve(settings, &mut io::stdin().lock(), &mut io::stdout().lock())
+}
+
+/// Serve a client until it exits or closes `input`.
+fn serve(settings: &Settings, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+    let mut documents: HashMap<String, String> = HashMap::new();
+    while let Some(message) = read_message(input)? {
+        let message = match message {
+            Ok(message) => message,
+            // The message is skipped, so the client can go on.
+            Err(e) => {
+                let error = json!({ "code": -32700, "message": format!("parse error: {e}") });
+                send(
+                    output,
+                    json!({ "jsonrpc": "2.0", "id": null, "error": error }),
+                )?;
+                continue;
+            }
+        };
+        let id = message.get("id").cloned();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match message["method"].as_str().unwrap_or_default() {
+            "initialize" => {
+                let capabilities = json!({
+                    "capabilities": {
+                        "textDocumentSync": {
+                            "openClose": true,
+                            // Full text on every change.
+                            "change": 1,
+                            "save": true,
+                        },
+                    },
+                    "serverInfo": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                });
+                reply(output, id, capabilities)?;
+            }
+            "shutdown" => reply(output, id, Value::Null)?,
+            "exit" => return Ok(()),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                documents.insert(uri.to_owned(), text.to_owned());
+                publish(output, uri, &documents[uri], settings)?;
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                {
+                    documents.insert(uri.to_owned(), text.to_owned());
+                    publish(output, uri, &documents[uri], settings)?;
+                }
+            }
+            "textDocument/didSave" => {
+                if let Some(text) = documents.get(uri) {
+                    publish(output, uri, text, settings)?;
+                }
+            }
+            "textDocument/didClose" => {
+                documents.remove(uri);
+                notify(output, uri, Vec::new())?;
+            }
+            method => {
+                if let Some(id) = id {
+                    let error = json!({
+                        "code": -32601,
+                        "message": format!("method {method:?} is not supported"),
+                    });
+                    send(
+                        output,
+                        json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read one message, or `None` at the end of the input. A message that is not JSON
+/// is an error of its own, after which the next message may still be read.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<serde_json::Result<Value>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)))
+}
+
+fn send(output: &mut impl Write, message: Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
+
+fn reply(output: &mut impl Write, id: Option<Value>, result: Value) -> io::Result<()> {
+    send(
+        output,
+        json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+    )
+}
+
+fn notify(output: &mut impl Write, uri: &str, diagnostics: Vec<Value>) -> io::Result<()> {
+    send(
+        output,
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }),
+    )
+}
+
+/// Validate a document and publish its diagnostics.
+fn publish(output: &mut impl Write, uri: &str, text: &str, settings: &Settings) -> io::Result<()> {
+    let path = url::Url::parse(uri)
+        .ok()
+        .and_then(|url| url.to_file_path().ok())
+        .unwrap_or_else(|| PathBuf::from(uri));
+    let name = path.to_string_lossy().replace('\0', "");
+    let relative_path = path.strip_prefix(&settings.root).unwrap_or(&path);
+    let schemas = match settings.configured_schema(relative_path) {
+        Some((url, _)) => vec![url],
+        None => hinted_schema_urls(text.as_bytes(), &path),
+    };
+    let result = validate_memory(&name, text.as_bytes(), Some(schemas), settings);
+    notify(output, uri, diagnostics(&result))
+}
+
+/// The diagnostics of a validated document, each covering the rest of the line it
+/// starts on. Errors in other files, e.g. a schema that does not compile, are put on
+/// the first line, naming the file, since their positions are not in the document.
+fn diagnostics(result: &FileResult) -> Vec<Value> {
+    let mut diagnostics: Vec<Value> = result
+        .errors
+        .iter()
+        .map(|error| {
+            let in_document = error.file.as_ref().is_none_or(|file| *file == result.path);
+            let (line, character, message) = match &error.file {
+                Some(file) if !in_document => (0, 0, format!("{file}: {}", error.message)),
+                _ => (
+                    (error.line - 1).max(0),
+                    (error.column - 1).max(0),
+                    error.message.clone(),
+                ),
+            };
+            json!({
+                "range": {
+                    "start": { "line": line, "character": character },
+                    "end": { "line": line + 1, "character": 0 },
+                },
+                "severity": match error.severity {
+                    Severity::Warning => 2,
+                    Severity::Error | Severity::Fatal => 1,
+                },
+                "code": error.error_code,
+                "source": env!("CARGO_PKG_NAME"),
+                "message": message,
+            })
+        })
+        .collect();
+    let whole_document = match result.status {
+        Status::Malformed => Some(codes::MALFORMED),
+        Status::InternalError => Some(codes::INTERNAL_ERROR),
+        Status::Valid | Status::Invalid | Status::NoSchema | Status::Removed => None,
+    };
+    if let (Some(code), true) = (whole_document, diagnostics.is_empty()) {
+        diagnostics.push(json!({
+            "range": {
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": 1, "character": 0 },
+            },
+            "severity": 1,
+            "code": code.id,
+            "source": env!("CARGO_PKG_NAME"),
+            "message": code.description,
+        }));
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests, ErrorDetail};
+    use std::path::Path;
+
+    fn framed(body: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    /// The messages sent in answer to `input`.
+    fn answers(input: &str) -> Vec<Value> {
+        let settings = tests::settings(Path::new("."));
+        let mut output = Vec::new();
+        serve(&settings, &mut input.as_bytes(), &mut output).unwrap();
+        let mut output = &output[..];
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            messages.push(message.unwrap());
+        }
+        messages
+    }
+
+    #[test]
+    fn malformed_messages_are_answered_and_skipped() {
+        let input = framed("{\"jsonrpc\": \"2.0\", \"id\": 1,")
+            + &framed(r#"{"jsonrpc": "2.0", "id": 2, "method": "shutdown"}"#);
+        let messages = answers(&input);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["error"]["code"], -32700);
+        assert_eq!(messages[0]["id"], Value::Null);
+        assert_eq!(messages[1]["id"], 2);
+        assert_eq!(messages[1]["result"], Value::Null);
+    }
+
+    #[test]
+    fn errors_of_other_files_are_on_the_first_line() {
+        let result = FileResult {
+            errors: vec![
+                ErrorDetail {
+                    file: Some("note.xml".to_owned()),
+                    line: 3,
+                    column: 5,
+                    ..ErrorDetail::new("in the document".to_owned(), &codes::INVALID_VALUE)
+                },
+                ErrorDetail {
+                    file: Some("note.xsd".to_owned()),
+                    line: 7,
+                    ..ErrorDetail::new("in the schema".to_owned(), &codes::SCHEMA_NOT_LOADED)
+                },
+            ],
+            ..FileResult::new("note.xml", Status::InternalError)
+        };
+        let diagnostics = diagnostics(&result);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0]["range"]["start"],
+            json!({ "line": 2, "character": 4 })
+        );
+        assert_eq!(diagnostics[0]["message"], "in the document");
+        assert_eq!(
+            diagnostics[1]["range"]["start"],
+            json!({ "line": 0, "character": 0 })
+        );
+        assert_eq!(diagnostics[1]["message"], "note.xsd: in the schema");
+    }
+}
@@ -1,23 +1,163 @@
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::io::BufReader;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 // TODO use clap
+use bytes::Bytes;
 use cached::proc_macro::cached;
 use docopt::Docopt;
-use reqwest::blocking::Client;
-use serde::Deserialize;
-use std::ffi::CString;
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use rayon::prelude::*;
+use reqwest::blocking::{Client, Response};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString};
+use std::fmt;
+
+mod audit;
+mod baseline;
+mod bench;
+mod cache;
+mod codes;
+mod config;
+mod diff;
+mod gzip;
+mod hints;
+mod history;
+mod logging;
+mod lsp;
+mod man;
+mod metrics;
+#[cfg(feature = "native")]
+mod native;
+mod network;
+mod output;
+mod prefix;
+mod progress;
+mod rules;
+mod serve;
+mod template;
+mod watch;
+
+use baseline::Baseline;
+use cache::Cache;
+use history::History;
+use metrics::Metrics;
+use network::HostPolicy;
+use output::{Color, Format, OutputWriter, Templates};
+use progress::ProgressFormat;
+use template::Template;
 
 /// For libxml2 FFI.
-use libc::{c_char, c_int, c_uint, FILE};
+use libc::{c_char, c_int, c_long, c_uint, c_void, FILE};
 
 /// Fake opaque structs from C libxml2.
-pub enum XmlSchema {}
+pub enum XmlDoc {}
 pub enum XmlSchemaParserCtxt {}
 pub enum XmlSchemaValidCtxt {}
+pub enum XmlValidCtxt {}
+pub enum XmlXPathContext {}
+pub enum XmlXPathObject {}
+pub enum XmlXPathCompExpr {}
+pub enum XmlParserCtxt {}
+pub enum XmlParserInputBuffer {}
+
+/// Error reported by libxml2, mirroring `xmlError`.
+#[repr(C)]
+pub struct XmlError {
+    pub domain: c_int,
+    pub code: c_int,
+    pub message: *const c_char,
+    pub level: c_int,
+    pub file: *const c_char,
+    pub line: c_int,
+    pub str1: *const c_char,
+    pub str2: *const c_char,
+    pub str3: *const c_char,
+    pub int1: c_int,
+    /// Column, or 0 if not available.
+    pub int2: c_int,
+    pub ctxt: *mut c_void,
+    pub node: *mut c_void,
+}
+
+/// Leading fields of libxml2's `xmlNode`; the rest are never accessed from Rust.
+#[repr(C)]
+pub struct XmlNode {
+    pub _private: *mut c_void,
+    pub node_type: c_int,
+    pub name: *const c_char,
+    pub children: *mut XmlNode,
+    pub last: *mut XmlNode,
+    pub parent: *mut XmlNode,
+    pub next: *mut XmlNode,
+    pub prev: *mut XmlNode,
+    pub doc: *mut XmlDoc,
+    pub ns: *mut XmlNs,
+}
+
+/// Leading fields of libxml2's `xmlDoc`, read through a cast of the opaque [`XmlDoc`].
+#[repr(C)]
+pub struct XmlDocFields {
+    pub _private: *mut c_void,
+    pub node_type: c_int,
+    pub name: *const c_char,
+    pub children: *mut XmlNode,
+    pub last: *mut XmlNode,
+    pub parent: *mut XmlNode,
+    pub next: *mut XmlNode,
+    pub prev: *mut XmlNode,
+    pub doc: *mut XmlDoc,
+    pub compression: c_int,
+    pub standalone: c_int,
+    pub int_subset: *mut c_void,
+    pub ext_subset: *mut c_void,
+    pub old_ns: *mut XmlNs,
+    pub version: *const c_char,
+    pub encoding: *const c_char,
+}
+
+/// Leading fields of libxml2's `xmlSchema`.
+#[repr(C)]
+pub struct XmlSchema {
+    pub name: *const c_char,
+    pub target_namespace: *const c_char,
+}
+
+/// Leading fields of libxml2's `xmlNs`.
+#[repr(C)]
+pub struct XmlNs {
+    pub next: *mut XmlNs,
+    pub ns_type: c_int,
+    pub href: *const c_char,
+    pub prefix: *const c_char,
+}
+
+/// Leading fields of libxml2's `xmlParserInput`.
+#[repr(C)]
+pub struct XmlParserInput {
+    pub buf: *mut XmlParserInputBuffer,
+    pub filename: *const c_char,
+}
+
+pub type XmlStructuredErrorFunc = extern "C" fn(user_data: *mut c_void, error: *const XmlError);
+pub type XmlExternalEntityLoader = unsafe extern "C" fn(
+    url: *const c_char,
+    id: *const c_char,
+    ctxt: *mut XmlParserCtxt,
+) -> *mut XmlParserInput;
 
 /// We know that libxml2 schema data structure is [thread-safe](http://xmlsoft.org/threads.hml).
 #[derive(Clone, Copy)]
@@ -26,17 +166,100 @@ struct XmlSchemaPtr(pub *mut XmlSchema);
 unsafe impl Send for XmlSchemaPtr {}
 unsafe impl Sync for XmlSchemaPtr {}
 
-#[link(name = "xml2")]
+/// Parser options from libxml2's `xmlParserOption`.
+pub const XML_PARSE_NOENT: c_int = 1 << 1;
+pub const XML_PARSE_DTDLOAD: c_int = 1 << 2;
+pub const XML_PARSE_NOBLANKS: c_int = 1 << 8;
+pub const XML_PARSE_NONET: c_int = 1 << 11;
+pub const XML_PARSE_HUGE: c_int = 1 << 19;
+
+/// Parse a comma-separated list of `--parser-options`, e.g. "huge,noblanks".
+fn parse_parser_options(list: &str) -> Result<c_int, String> {
+    let mut options = 0;
+    for option in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        options |= match option {
+            "huge" => XML_PARSE_HUGE,
+            "noent" => XML_PARSE_NOENT,
+            "dtdload" => XML_PARSE_DTDLOAD,
+            "nonet" => XML_PARSE_NONET,
+            "noblanks" => XML_PARSE_NOBLANKS,
+            _ => return Err(format!("unknown parser option {option:?}")),
+        };
+    }
+    Ok(options)
+}
+
+// The bundled feature links the static library built by build.rs instead.
+#[cfg_attr(not(feature = "bundled"), link(name = "xml2"))]
 extern "C" {
     pub fn xmlInitParser();
     pub fn xmlInitGlobals();
+    pub fn xmlSetStructuredErrorFunc(ctx: *mut c_void, handler: XmlStructuredErrorFunc);
+    pub static xmlFree: unsafe extern "C" fn(*mut c_void);
 
-    // xmlschemas
-    pub fn xmlSchemaNewMemParserCtxt(
+    // parser
+    pub fn xmlReadFile(
+        file_name: *const c_char,
+        encoding: *const c_char,
+        options: c_int,
+    ) -> *mut XmlDoc;
+    pub fn xmlReadMemory(
         buffer: *const c_char,
         size: c_int,
-    ) -> *mut XmlSchemaParserCtxt;
+        url: *const c_char,
+        encoding: *const c_char,
+        options: c_int,
+    ) -> *mut XmlDoc;
+    pub fn xmlFreeDoc(doc: *mut XmlDoc);
+    pub fn xmlSetExternalEntityLoader(f: XmlExternalEntityLoader);
+    pub fn xmlGetExternalEntityLoader() -> XmlExternalEntityLoader;
+
+    // xmlIO
+    pub fn xmlParserInputBufferCreateMem(
+        mem: *const c_char,
+        size: c_int,
+        enc: c_int,
+    ) -> *mut XmlParserInputBuffer;
+    pub fn xmlFreeParserInputBuffer(input: *mut XmlParserInputBuffer);
+    pub fn xmlNewIOInputStream(
+        ctxt: *mut XmlParserCtxt,
+        input: *mut XmlParserInputBuffer,
+        enc: c_int,
+    ) -> *mut XmlParserInput;
+    pub fn xmlCharStrdup(cur: *const c_char) -> *mut c_char;
+
+    // tree
+    pub fn xmlDocGetRootElement(doc: *const XmlDoc) -> *mut XmlNode;
+    pub fn xmlDocDumpMemory(doc: *mut XmlDoc, mem: *mut *mut c_char, size: *mut c_int);
+    pub fn xmlGetLineNo(node: *const XmlNode) -> c_long;
+
+    // valid
+    pub fn xmlNewValidCtxt() -> *mut XmlValidCtxt;
+    pub fn xmlFreeValidCtxt(ctxt: *mut XmlValidCtxt);
+    pub fn xmlValidateDocumentFinal(ctxt: *mut XmlValidCtxt, doc: *mut XmlDoc) -> c_int;
+    /// The attribute defining an ID. An `xmlAttr` starts like an `xmlNode`.
+    pub fn xmlGetID(doc: *mut XmlDoc, id: *const c_char) -> *mut XmlNode;
+
+    // xpath
+    pub fn xmlXPathCompile(expression: *const c_char) -> *mut XmlXPathCompExpr;
+    pub fn xmlXPathFreeCompExpr(comp: *mut XmlXPathCompExpr);
+    pub fn xmlXPathNewContext(doc: *mut XmlDoc) -> *mut XmlXPathContext;
+    pub fn xmlXPathFreeContext(ctxt: *mut XmlXPathContext);
+    pub fn xmlXPathEvalExpression(
+        expression: *const c_char,
+        ctxt: *mut XmlXPathContext,
+    ) -> *mut XmlXPathObject;
+    pub fn xmlXPathCastToString(object: *mut XmlXPathObject) -> *mut c_char;
+    pub fn xmlXPathFreeObject(object: *mut XmlXPathObject);
+
+    // xmlschemas
+    pub fn xmlSchemaNewDocParserCtxt(doc: *mut XmlDoc) -> *mut XmlSchemaParserCtxt;
     //pub fn xmlSchemaSetParserErrors();
+    pub fn xmlSchemaSetParserStructuredErrors(
+        ctxt: *mut XmlSchemaParserCtxt,
+        serror: XmlStructuredErrorFunc,
+        ctx: *mut c_void,
+    );
     pub fn xmlSchemaParse(ctxt: *const XmlSchemaParserCtxt) -> *mut XmlSchema;
     pub fn xmlSchemaFreeParserCtxt(ctxt: *mut XmlSchemaParserCtxt);
     pub fn xmlSchemaDump(output: *mut FILE, schema: *const XmlSchema);
@@ -44,133 +267,3373 @@ extern "C" {
     pub fn xmlSchemaNewValidCtxt(schema: *const XmlSchema) -> *mut XmlSchemaValidCtxt;
     pub fn xmlSchemaFreeValidCtxt(ctxt: *mut XmlSchemaValidCtxt);
     //pub fn xmlSchemaSetValidErrors();
+    pub fn xmlSchemaSetValidStructuredErrors(
+        ctxt: *mut XmlSchemaValidCtxt,
+        serror: XmlStructuredErrorFunc,
+        ctx: *mut c_void,
+    );
     pub fn xmlSchemaValidateFile(
         ctxt: *const XmlSchemaValidCtxt,
         file_name: *const c_char,
         options: c_uint,
     ) -> c_int;
+    pub fn xmlSchemaValidateDoc(ctxt: *const XmlSchemaValidCtxt, doc: *const XmlDoc) -> c_int;
 }
 
 const USAGE: &str = "
 Validate XML files concurrently and downloading remote XML Schemas only once.
 
 Usage:
-  validate-xml [--extension=<extension>] <dir>
+  validate-xml bench [options] [--root-element=<name>]... [--runs=<n>] [--schema-rule=<rule>]... [--suppress=<rule>]... [--rule=<rule>]... [--plugin=<wasm>]... [--script=<file>]... <dir>
+  validate-xml serve [options] [--listen=<addr>] [--schema-rule=<rule>]... [--suppress=<rule>]... [--rule=<rule>]... [--plugin=<wasm>]... [--script=<file>]... [<dir>]
+  validate-xml lsp [options] [--schema-rule=<rule>]... [--suppress=<rule>]... [--rule=<rule>]... [--plugin=<wasm>]... [--script=<file>]... [<dir>]
+  validate-xml merge-reports [--output=<path>] <json-report>...
+  validate-xml history [--last=<n>] <db>
+  validate-xml diff <old-report> <new-report>
+  validate-xml [options] [--report=<report>]... [--suppress=<rule>]... [--rule=<rule>]... [--plugin=<wasm>]... [--script=<file>]... -
+  validate-xml [options] [--root-element=<name>]... [--report=<report>]... [--suppress=<rule>]... [--rule=<rule>]... [--plugin=<wasm>]... [--script=<file>]... --all-targets
+  validate-xml [options] [--root-element=<name>]... [--report=<report>]... [--schema-rule=<rule>]... [--suppress=<rule>]... [--rule=<rule>]... [--plugin=<wasm>]... [--script=<file>]... <dir>
   validate-xml (-h | --help)
   validate-xml --version
+  validate-xml --generate-man
+  validate-xml --dump-schema=<schema>
+  validate-xml config init [--format=<format>]
+  validate-xml config show [options] [--root-element=<name>]... [--report=<report>]... [--schema-rule=<rule>]... [--suppress=<rule>]... [--rule=<rule>]... [--plugin=<wasm>]... [--script=<file>]...
+  validate-xml config schema
 
 Options:
   -h --help                Show this screen.
   --version                Show version.
-  --extension=<extension>  File extension of XML files [default: cmdi].
+  --generate-man           Print a man page in roff format on stdout.
+  --dump-schema=<schema>   Load a schema, local or remote, and print
+                           libxml2's compiled view of it on stdout.
+  --config=<file>          Read default options from this configuration file
+                           instead of validate-xml.toml or validate-xml.json
+                           in the current directory.
+  --profile=<name>         Apply the [profile.<name>] settings of the
+                           configuration file, e.g. ci or dev. Defaults to
+                           $VALIDATE_XML_PROFILE.
+  --all-targets            Check every [targets.<name>] directory of the
+                           configuration file into one report, each with its
+                           own extension, schemas and fail_on outcomes.
+  --extension=<extension>  File extension of XML files, which may also be
+                           gzip-compressed with a further .gz extension
+                           [default: cmdi].
+  --case-insensitive-extensions
+                           Match the extension in any case, e.g. .CMDI and
+                           .Cmdi as well as .cmdi.
+  --require-schema         Count files without a schema reference as failures.
+  --schema=<schema>        Validate against this schema instead of the
+                           schemaLocation hint or any schema rule.
+  --schema-rule=<rule>     Validate files matching a glob against a schema,
+                           given as GLOB=SCHEMA, e.g.
+                           \"imports/**/*.xml=schemas/import.xsd\".
+                           The glob is relative to <dir> and the first
+                           matching rule wins over the schemaLocation hint.
+  --check-well-formed      Check that files without a schema reference are
+                           at least well-formed XML.
+  --check-ids              Report IDs defined more than once (VX2009) and
+                           IDREFs to undefined IDs (VX2010) as such. IDs and
+                           IDREFs are the attributes the schema types so.
+  --backend=<name>         Validate with libxml2, or with native, an
+                           experimental validator for common schemas in
+                           builds with the native feature. native does not
+                           run --check-ids, rules, plugins or scripts
+                           [default: libxml2].
+  --root-element=<name>    Only check files whose root element has this local
+                           name, given as NAME, or as NAME,NAMESPACE to also
+                           match its namespace URI, e.g. \"note,urn:note\".
+                           May be repeated.
+  --suppress=<rule>        Leave errors out of reports and the exit status,
+                           given as CODE, CODE:GLOB or CODE:GLOB:REGEX, e.g.
+                           \"VX2006:legacy/**:pattern\". The glob is relative
+                           to <dir> and the regex searches the message. May
+                           be repeated.
+  --rule=<rule>            Check each parsed file with a built-in rule after
+                           schema validation, given as NAME=ARGUMENT. The
+                           only rule is filename=XPATH, which requires the
+                           file name without extensions to equal the string
+                           value of XPATH, e.g.
+                           \"filename=//*[local-name()='record']/@id\". May
+                           be repeated.
+  --plugin=<wasm>          Check each parsed file with a WebAssembly rule
+                           plugin after schema validation. Needs the plugins
+                           feature. May be repeated.
+  --script=<file>          Check each parsed file with a Rhai script after
+                           schema validation. Needs the scripts feature. May
+                           be repeated.
+  --shard=<i/n>            Only check the i-th of n parts of the files, e.g.
+                           2/8. Files are assigned to parts by their path;
+                           merge-reports combines the json reports of all
+                           parts.
+  --sample=<n>             Only check n of the files, picked at random, e.g.
+                           for a quick check of a large corpus.
+  --seed=<n>               Pick the same files for --sample as another run
+                           with this seed. The seed of each run is logged.
+  --staged                 Only check the files under <dir> that are staged
+                           for the next git commit, e.g. in a pre-commit
+                           hook.
+  --watch                  After checking all files, keep checking files as
+                           they are added or changed, until interrupted.
+  --dry-run                List the files that would be checked and the
+                           schemas each would be validated against, without
+                           validating anything.
+  --list-schemas           List each distinct schema the files reference,
+                           with the number of files using it.
+  -q --quiet               Only report files that fail the run, by --fail-on
+                           and the baseline, in the human report.
+  -v --verbose             Show the source line of each error in the human
+                           report, and end it with a summary of the run,
+                           including how long each schema took to load.
+  --context=<n>            With --verbose, also show this many lines before
+                           and after each error's line [default: 0].
+  --max-error-details=<n>  Report at most this many errors per file, then one
+                           counting the errors left out (VX1004). The exit
+                           status, baseline, history and metrics still
+                           count every error.
+  --by-directory=<depth>   End the human report with pass and fail counts
+                           per directory, grouping files by the first
+                           <depth> directories of their path under <dir>.
+  --color=<when>           Color the human report: auto (on a terminal,
+                           unless NO_COLOR is set), always or never
+                           [default: auto].
+  --log-level=<level>      Log diagnostics at this level or more severe:
+                           off, error, warn, info, debug or trace
+                           [default: info].
+  --log-file=<path>        Append the log to this file instead of stderr.
+  --fetch-log=<path>       Append a JSON line to this file for each schema
+                           downloaded, with its URL, HTTP status, size and
+                           duration.
+  --progress               Show progress bars when stderr is a terminal.
+  --progress-format=<fmt>  Show progress as bars, or as jsonl: one JSON
+                           event per line with the phase, completed and
+                           total counts, current file and timestamp.
+  --progress-fd=<fd>       Write jsonl progress to this open file
+                           descriptor instead of stderr. Unix only.
+  --output=<path>          Write the report to this file instead.
+  --report=<report>        Also write a report given as FORMAT:PATH, e.g.
+                           \"sarif:results.sarif\". May be repeated.
+  --baseline=<file>        Do not fail on the known failures recorded in
+                           this baseline file, only on new or changed ones.
+  --write-baseline=<file>  Record the failures of this run in a baseline
+                           file.
+  --cache=<file>           Report files whose content and schemas are
+                           unchanged with the results recorded in this file,
+                           instead of validating them, and record the
+                           results of this run in it.
+  --fail-on=<outcomes>     Comma-separated outcomes that make the exit status
+                           1: invalid, malformed, error (internal errors),
+                           skipped (no schema reference) and warning (files
+                           with schema warnings). Defaults to every failure
+                           in the report.
+  --no-fail                Always exit with status 0 once the run completes,
+                           e.g. to only collect reports.
+  --history=<db>           Append a summary of this run and the outcome of
+                           each file to this SQLite database, for
+                           validate-xml history.
+  --last=<n>               Number of recent runs that validate-xml history
+                           looks at [default: 10].
+  --metrics-file=<path>    Write Prometheus metrics of the run to this file,
+                           in the node exporter's textfile collector format.
+  --jobs=<n>               Number of validation threads. Defaults to the
+                           number of cores.
+  --largest-first          Start validating the largest files first, so
+                           that a few big files do not run on alone at the
+                           end.
+  --format=<format>        Report format: human (on stderr) or ndjson (on
+                           stdout) as files are checked, or json, sarif,
+                           gitlab (Code Quality) or schemas (a summary per
+                           schema) on stdout at the end, or template lines
+                           from the templates below. Defaults to human.
+                           For config init: toml (the default) or json.
+  --format-template=<tmpl>
+                           Report each file as a line from this template
+                           instead, e.g. \"{path}: {status}\". Fields:
+                           {path}, {status}, {error_count}, {first_error},
+                           {first_error_code}, {root_element},
+                           {root_namespace}, {schemas} and {duration_ms}.
+                           {{ and }} are literal braces, \\n and \\t a
+                           newline and a tab.
+  --summary-template=<tmpl>
+                           End the report with a line from this template.
+                           Fields: {files}, {valid}, {invalid},
+                           {malformed}, {internal_error}, {no_schema},
+                           {removed}, {error_count} and {suppressed}.
+  --keep-duplicate-errors  Report every occurrence of an error instead of
+                           folding identical ones into a repeat count.
+  --runs=<n>               Number of benchmark runs. Only the first loads
+                           schemas; the others reuse them [default: 5].
+  --listen=<addr>          Address to serve the HTTP validation API on, which
+                           validates posted documents and files under <dir>
+                           [default: 127.0.0.1:8080].
+  --allowed-hosts=<hosts>  Comma-separated hosts that schemas may be
+                           downloaded from, each with its subdomains. A
+                           schema on any other host is an error rather than
+                           a download.
+  --blocked-hosts=<hosts>  Comma-separated hosts, each with its subdomains,
+                           that schemas may not be downloaded from.
+  --no-remote-schemas      Only validate against local schemas: a remote
+                           schema is an error rather than a download.
+  --host-delay=<ms>        Wait at least this many milliseconds between
+                           schema downloads from the same host, for servers
+                           that throttle clients [default: 0].
+  --block-private-addresses
+                           Do not download schemas from loopback, private
+                           or link-local addresses, also when a host name
+                           resolves to one or a download is redirected to
+                           one, e.g. when validating untrusted documents.
+                           serve does so by default.
+  --allow-private-addresses
+                           Let serve download schemas from private
+                           addresses, e.g. from a schema server on the
+                           same network.
+  --allow-external-entities
+                           Load external DTDs and entities, including over
+                           the network, and substitute entities. By default
+                           documents may not touch the network and entities
+                           are left unexpanded.
+  --parser-options=<opts>  Comma-separated libxml2 parser options to add:
+                           huge (lift the limits on node size and depth,
+                           and on entity expansion), noent (substitute
+                           entities), dtdload (load external DTDs), nonet
+                           (never use the network) and noblanks (drop
+                           ignorable whitespace).
 ";
 
 #[derive(Deserialize)]
 struct Args {
+    flag_all_targets: bool,
     flag_extension: String,
+    flag_case_insensitive_extensions: bool,
+    flag_require_schema: bool,
+    flag_schema: Option<String>,
+    flag_schema_rule: Vec<String>,
+    flag_suppress: Vec<String>,
+    flag_root_element: Vec<String>,
+    flag_rule: Vec<String>,
+    flag_plugin: Vec<String>,
+    flag_script: Vec<String>,
+    flag_allow_external_entities: bool,
+    flag_allowed_hosts: Option<String>,
+    flag_blocked_hosts: Option<String>,
+    flag_block_private_addresses: bool,
+    flag_allow_private_addresses: bool,
+    flag_no_remote_schemas: bool,
+    flag_host_delay: u64,
+    flag_parser_options: Option<String>,
+    flag_keep_duplicate_errors: bool,
+    flag_check_well_formed: bool,
+    flag_check_ids: bool,
+    flag_backend: Backend,
+    flag_dry_run: bool,
+    flag_shard: Option<String>,
+    flag_sample: Option<usize>,
+    flag_seed: Option<u64>,
+    cmd_merge_reports: bool,
+    cmd_history: bool,
+    cmd_diff: bool,
+    arg_old_report: String,
+    arg_new_report: String,
+    arg_db: String,
+    flag_history: Option<String>,
+    flag_last: usize,
+    arg_json_report: Vec<String>,
+    flag_watch: bool,
+    flag_list_schemas: bool,
+    flag_format: Option<String>,
+    flag_format_template: Option<String>,
+    flag_summary_template: Option<String>,
+    flag_jobs: Option<usize>,
+    flag_largest_first: bool,
+    flag_output: Option<String>,
+    flag_verbose: bool,
+    flag_context: usize,
+    flag_quiet: bool,
+    flag_staged: bool,
+    flag_color: Color,
+    flag_by_directory: Option<usize>,
+    flag_max_error_details: Option<usize>,
+    flag_log_level: String,
+    flag_log_file: Option<String>,
+    flag_fetch_log: Option<String>,
+    flag_progress: bool,
+    flag_progress_format: Option<ProgressFormat>,
+    flag_progress_fd: Option<i32>,
+    flag_report: Vec<String>,
+    flag_baseline: Option<String>,
+    flag_fail_on: Option<String>,
+    flag_no_fail: bool,
+    flag_metrics_file: Option<String>,
+    flag_write_baseline: Option<String>,
+    flag_cache: Option<String>,
+    flag_runs: usize,
     arg_dir: String,
+    /// `-`: validate a single document from standard input.
+    cmd__: bool,
+    flag_generate_man: bool,
+    flag_dump_schema: Option<String>,
+    cmd_config: bool,
+    cmd_init: bool,
+    cmd_show: bool,
+    cmd_schema: bool,
+    cmd_bench: bool,
+    cmd_serve: bool,
+    cmd_lsp: bool,
+    flag_listen: String,
 }
 
-/// Return the first Schema URL found, if any.
-/// Panic on any I/O error.
-fn extract_schema_url(path: &Path) -> Option<String> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r#"xsi:schemaLocation="\S+\s+(.+?)""#)
-            .expect("failed to compile schemaLocation regex");
+/// What validates documents against their schemas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Backend {
+    Libxml2,
+    /// The validator of `native.rs`, in builds with the native feature.
+    Native,
+}
+
+/// Run-wide settings shared by all validation tasks.
+#[derive(Clone)]
+struct Settings {
+    root: PathBuf,
+    /// File extension of the XML files to validate.
+    extension: String,
+    /// Whether `.XML` and `.Xml` count as the extension `xml`.
+    case_insensitive_extensions: bool,
+    require_schema: bool,
+    check_well_formed: bool,
+    check_ids: bool,
+    backend: Backend,
+    schema: Option<String>,
+    schema_rules: SchemaRules,
+    /// libxml2 parser options for reading documents.
+    parse_options: c_int,
+    deduplicate_errors: bool,
+    /// The part of the files to check, if not all.
+    shard: Option<Shard>,
+    /// The root elements of the files to check, if not all.
+    root_elements: Vec<RootElement>,
+    /// The files to pick at random, if not all.
+    sample: Option<Sample>,
+    /// Settings for parts of the root directory, later ones taking precedence.
+    overrides: Vec<Override>,
+    suppressions: Vec<Suppression>,
+    /// Checks run on each parsed document after schema validation.
+    rules: Vec<Arc<dyn rules::Rule>>,
+    /// The hosts that schemas may be downloaded from.
+    hosts: HostPolicy,
+}
+
+impl Settings {
+    /// The path of a checked file relative to the root directory.
+    fn relative_path(&self, result: &FileResult) -> PathBuf {
+        let path = Path::new(&result.path);
+        path.strip_prefix(&self.root).unwrap_or(path).to_owned()
+    }
+
+    /// The overrides applying to a file, the one taking precedence first.
+    fn overrides_for<'a>(&'a self, relative_path: &'a Path) -> impl Iterator<Item = &'a Override> {
+        self.overrides
+            .iter()
+            .rev()
+            .filter(move |section| section.path.is_match(relative_path))
+    }
+
+    /// Whether a file has the extension of the files to check, possibly followed by
+    /// `.gz`.
+    fn has_extension(&self, path: &Path) -> bool {
+        let uncompressed;
+        let path = if gzip::is_compressed(path) {
+            uncompressed = path.with_extension("");
+            &uncompressed
+        } else {
+            path
+        };
+        let relative_path = path.strip_prefix(&self.root).unwrap_or(path);
+        let extension = self
+            .overrides_for(relative_path)
+            .find_map(|section| section.extension.as_ref())
+            .unwrap_or(&self.extension);
+        path.extension()
+            .and_then(|actual| actual.to_str())
+            .is_some_and(|actual| {
+                actual == extension
+                    || self.case_insensitive_extensions && actual.eq_ignore_ascii_case(extension)
+            })
     }
 
-    let file = File::open(path).unwrap();
-    let reader = BufReader::new(file);
-    for line in reader.lines() {
-        if let Some(caps) = RE.captures(&line.unwrap()) {
-            return Some(caps[1].to_owned());
+    /// Whether a file has one of the root elements of `--root-element`, found by
+    /// reading its start. A file that cannot be read is kept, for checking it to
+    /// report why.
+    fn has_root_element(&self, path: &Path) -> bool {
+        if self.root_elements.is_empty() {
+            return true;
+        }
+        let root = if gzip::is_compressed(path) {
+            gzip::read(path).map(|document| peek_root_element(&document))
+        } else {
+            prefix::read(path, has_root_start_tag).map(|document| peek_root_element(&document))
+        };
+        match root {
+            Ok(Some((name, namespace))) => self
+                .root_elements
+                .iter()
+                .any(|expected| expected.matches(&name, namespace.as_deref())),
+            Ok(None) => false,
+            Err(_) => true,
         }
     }
-    None
+
+    /// The schema that a file must use regardless of its schemaLocation hint, if
+    /// any, and where it was configured.
+    fn configured_schema(&self, relative_path: &Path) -> Option<(String, &'static str)> {
+        self.overrides_for(relative_path)
+            .find_map(|section| {
+                section.schema.clone().or_else(|| {
+                    section
+                        .schema_rules
+                        .as_ref()
+                        .and_then(|rules| rules.schema_for(relative_path))
+                })
+            })
+            .map(|url| (url, "override"))
+            .or_else(|| Some((self.schema.clone()?, "--schema")))
+            .or_else(|| Some((self.schema_rules.schema_for(relative_path)?, "schema rule")))
+    }
+
+    /// Whether a schema is one of `--schema`, the schema rules or the overrides.
+    fn is_configured_schema(&self, url: &str) -> bool {
+        let in_rules = |rules: &SchemaRules| rules.schemas.iter().any(|schema| schema == url);
+        self.schema.as_deref() == Some(url)
+            || in_rules(&self.schema_rules)
+            || self.overrides.iter().any(|section| {
+                section.schema.as_deref() == Some(url)
+                    || section.schema_rules.as_ref().is_some_and(in_rules)
+            })
+    }
+
+    /// Whether an error of a file is left out by `--suppress`.
+    fn is_suppressed(&self, relative_path: &Path, error: &ErrorDetail) -> bool {
+        self.suppressions
+            .iter()
+            .any(|suppression| suppression.matches(relative_path, error))
+    }
+
+    /// The outcomes that fail the run for a file, if an override sets them.
+    fn fail_on_for(&self, relative_path: &Path) -> Option<FailOn> {
+        self.overrides_for(relative_path)
+            .find_map(|section| section.fail_on)
+    }
 }
 
-/// Cache schema into memory after downloading from Web once and stashing into memory.
+/// Settings for the files under part of the root directory, from an `[[overrides]]`
+/// section of the configuration file.
+#[derive(Clone)]
+struct Override {
+    /// The files it applies to, relative to the root directory.
+    path: GlobMatcher,
+    extension: Option<String>,
+    schema: Option<String>,
+    schema_rules: Option<SchemaRules>,
+    fail_on: Option<FailOn>,
+}
+
+impl Override {
+    fn new(section: &serde_json::Map<String, serde_json::Value>) -> Result<Override, String> {
+        let Some(path) = section.get("path").and_then(|path| path.as_str()) else {
+            return Err("every [[overrides]] section needs a path glob".to_owned());
+        };
+        let string = |key: &str| match section.get(key) {
+            None => Ok(None),
+            Some(serde_json::Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(format!("overrides for {path:?}: {key} must be a string")),
+        };
+        let schema_rules = match section.get("schema_rule") {
+            None => None,
+            Some(rules) => {
+                let rules: Vec<String> = serde_json::from_value(rules.clone()).map_err(|_| {
+                    format!("overrides for {path:?}: schema_rule must be an array of strings")
+                })?;
+                Some(SchemaRules::new(&rules)?)
+            }
+        };
+        if let Some(key) = section
+            .keys()
+            .find(|key| *key != "path" && !config::OVERRIDABLE.contains(&key.as_str()))
+        {
+            return Err(format!(
+                "overrides for {path:?}: {key} cannot be overridden"
+            ));
+        }
+        Ok(Override {
+            path: Glob::new(path)
+                .map_err(|e| format!("overrides for {path:?}: {e}"))?
+                .compile_matcher(),
+            extension: string("extension")?,
+            schema: string("schema")?,
+            schema_rules,
+            fail_on: string("fail_on")?
+                .map(|list| FailOn::parse(&list))
+                .transpose()?,
+        })
+    }
+}
+
+/// A directory checked by `--all-targets`, from a `[targets.<name>]` section of the
+/// configuration file.
+struct Target {
+    name: String,
+    settings: Settings,
+    fail_on: Option<FailOn>,
+}
+
+impl Target {
+    /// The target of a section, whose settings replace those of `base`. Its
+    /// directory is relative to the configuration file `config_path`.
+    fn new(
+        name: &str,
+        section: &serde_json::Map<String, serde_json::Value>,
+        config_path: &Path,
+        base: &Settings,
+    ) -> Result<Target, String> {
+        let string = |key: &str| match section.get(key) {
+            None => Ok(None),
+            Some(serde_json::Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(format!("target {name:?}: {key} must be a string")),
+        };
+        if let Some(key) = section
+            .keys()
+            .find(|key| !config::TARGET_SETTINGS.contains(&key.as_str()))
+        {
+            return Err(format!("target {name:?}: unknown setting {key}"));
+        }
+        let Some(dir) = string("dir")? else {
+            return Err(format!("target {name:?} needs a dir"));
+        };
+        let mut settings = base.clone();
+        settings.root = config_path.parent().unwrap_or(Path::new("")).join(dir);
+        if let Some(extension) = string("extension")? {
+            settings.extension = extension;
+        }
+        if let Some(schema) = string("schema")? {
+            settings.schema = Some(schema);
+        }
+        if let Some(rules) = section.get("schema_rule") {
+            let rules: Vec<String> = serde_json::from_value(rules.clone())
+                .map_err(|_| format!("target {name:?}: schema_rule must be an array of strings"))?;
+            settings.schema_rules = SchemaRules::new(&rules)?;
+        }
+        Ok(Target {
+            name: name.to_owned(),
+            settings,
+            fail_on: string("fail_on")?
+                .map(|list| FailOn::parse(&list))
+                .transpose()?,
+        })
+    }
+}
+
+/// Glob patterns mapped to the schema that matching files must use.
+#[derive(Clone)]
+struct SchemaRules {
+    globs: GlobSet,
+    schemas: Vec<String>,
+}
+
+impl SchemaRules {
+    /// Parse `GLOB=SCHEMA` rules.
+    fn new(rules: &[String]) -> Result<SchemaRules, String> {
+        let mut builder = GlobSetBuilder::new();
+        let mut schemas = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let (pattern, schema) = rule
+                .split_once('=')
+                .ok_or_else(|| format!("schema rule {rule:?} is not of the form GLOB=SCHEMA"))?;
+            let glob = Glob::new(pattern).map_err(|e| format!("schema rule {rule:?}: {e}"))?;
+            builder.add(glob);
+            schemas.push(schema.to_owned());
+        }
+        let globs = builder.build().map_err(|e| e.to_string())?;
+        Ok(SchemaRules { globs, schemas })
+    }
+
+    /// Return the schema of the first rule matching `relative_path`, if any.
+    fn schema_for(&self, relative_path: &Path) -> Option<String> {
+        self.globs
+            .matches(relative_path)
+            .first()
+            .map(|&i| self.schemas[i].clone())
+    }
+}
+
+/// Errors to leave out of reports, from `--suppress`.
+#[derive(Clone)]
+struct Suppression {
+    /// An error code such as "VX2006".
+    code: String,
+    /// The files it applies to, relative to the root directory, if not all.
+    path: Option<GlobMatcher>,
+    /// A pattern that the message must contain, if any.
+    message: Option<Regex>,
+}
+
+impl Suppression {
+    /// Parse `CODE`, `CODE:GLOB` or `CODE:GLOB:REGEX`.
+    fn parse(rule: &str) -> Result<Suppression, String> {
+        let mut parts = rule.splitn(3, ':');
+        let code = parts.next().unwrap_or_default();
+        if !codes::CATALOG.iter().any(|known| known.id == code) {
+            return Err(format!("suppression {rule:?}: unknown error code {code:?}"));
+        }
+        let path = parts
+            .next()
+            .filter(|glob| !glob.is_empty())
+            .map(|glob| Glob::new(glob).map(|glob| glob.compile_matcher()))
+            .transpose()
+            .map_err(|e| format!("suppression {rule:?}: {e}"))?;
+        let message = parts
+            .next()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| format!("suppression {rule:?}: {e}"))?;
+        Ok(Suppression {
+            code: code.to_owned(),
+            path,
+            message,
+        })
+    }
+
+    fn matches(&self, relative_path: &Path, error: &ErrorDetail) -> bool {
+        self.code == error.error_code
+            && self
+                .path
+                .as_ref()
+                .is_none_or(|path| path.is_match(relative_path))
+            && self
+                .message
+                .as_ref()
+                .is_none_or(|message| message.is_match(&error.message))
+    }
+}
+
+/// Outcome of validating a single file.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Valid,
+    Invalid,
+    InternalError,
+    /// The file is not well-formed XML, so it was not validated.
+    Malformed,
+    /// The file has no schema reference to validate against.
+    NoSchema,
+    /// The file was removed after it was found and before it was checked.
+    Removed,
+}
+
+impl Status {
+    /// The name used in reports, e.g. "no_schema".
+    fn name(self) -> &'static str {
+        match self {
+            Status::Valid => "valid",
+            Status::Invalid => "invalid",
+            Status::InternalError => "internal_error",
+            Status::Malformed => "malformed",
+            Status::NoSchema => "no_schema",
+            Status::Removed => "removed",
+        }
+    }
+
+    /// Whether this outcome should make the whole run fail.
+    fn is_failure(self, require_schema: bool) -> bool {
+        match self {
+            Status::Valid | Status::Removed => false,
+            Status::Invalid | Status::InternalError | Status::Malformed => true,
+            Status::NoSchema => require_schema,
+        }
+    }
+}
+
+/// Which outcomes make the run exit with a failure status.
+#[derive(Clone, Copy, Default)]
+struct FailOn {
+    invalid: bool,
+    malformed: bool,
+    error: bool,
+    skipped: bool,
+    warning: bool,
+}
+
+impl FailOn {
+    /// Parse a comma-separated list of outcomes, e.g. "invalid,warning".
+    fn parse(list: &str) -> Result<FailOn, String> {
+        let mut fail_on = FailOn::default();
+        for outcome in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match outcome {
+                "invalid" => fail_on.invalid = true,
+                "malformed" => fail_on.malformed = true,
+                "error" => fail_on.error = true,
+                "skipped" => fail_on.skipped = true,
+                "warning" => fail_on.warning = true,
+                _ => return Err(format!("unknown --fail-on outcome {outcome:?}")),
+            }
+        }
+        Ok(fail_on)
+    }
+
+    /// What fails a run unless told otherwise: every failure in the reports.
+    fn default_for(require_schema: bool) -> FailOn {
+        FailOn {
+            invalid: true,
+            malformed: true,
+            error: true,
+            skipped: require_schema,
+            warning: false,
+        }
+    }
+
+    fn breaks(self, result: &FileResult) -> bool {
+        let by_status = match result.status {
+            Status::Valid | Status::Removed => false,
+            Status::Invalid => self.invalid,
+            Status::Malformed => self.malformed,
+            Status::InternalError => self.error,
+            Status::NoSchema => self.skipped,
+        };
+        by_status
+            || self.warning
+                && result
+                    .errors
+                    .iter()
+                    .any(|error| error.severity == Severity::Warning)
+    }
+}
+
+/// Everything known about one checked file, for reporting.
+#[derive(Clone, Serialize, Deserialize)]
+struct FileResult {
+    path: String,
+    status: Status,
+    /// Local name of the root element, if the document was parsed.
+    root_element: Option<String>,
+    /// Namespace URI of the root element, if it has one.
+    root_namespace: Option<String>,
+    /// The schemas the document was validated against.
+    schemas: Vec<String>,
+    errors: Vec<ErrorDetail>,
+    /// Time taken to check the file, including loading its schemas the first time.
+    duration_ms: f64,
+    /// Whether this is a known failure recorded in the baseline, which does not fail the run.
+    baselined: bool,
+    /// How many errors were left out by `--suppress`.
+    suppressed: usize,
+    /// How many errors were left out by `--max-error-details`.
+    #[serde(default)]
+    omitted_errors: usize,
+    /// Size of the file in bytes, as stored (compressed for .gz files).
+    size: Option<u64>,
+    /// The encoding the document declares, or UTF-8 if it declares none.
+    encoding: Option<String>,
+    /// When the file was last modified, in seconds since the Unix epoch.
+    modified: Option<f64>,
+}
+
+impl FileResult {
+    fn new(path: &str, status: Status) -> FileResult {
+        FileResult {
+            path: path.to_owned(),
+            status,
+            root_element: None,
+            root_namespace: None,
+            schemas: Vec::new(),
+            errors: Vec::new(),
+            duration_ms: 0.0,
+            baselined: false,
+            suppressed: 0,
+            omitted_errors: 0,
+            size: None,
+            encoding: None,
+            modified: None,
+        }
+    }
+
+    /// Keep only the first `max` errors, and replace the rest with one that says how
+    /// many were left out, to bound the size of reports.
+    fn omit_errors_after(&mut self, max: usize) {
+        if self.errors.len() <= max {
+            return;
+        }
+        let omitted = self.errors.split_off(max);
+        let count: usize = omitted.iter().map(|error| error.occurrences).sum();
+        self.omitted_errors = count;
+        let only_warnings = omitted
+            .iter()
+            .all(|error| error.severity == Severity::Warning);
+        self.errors.push(ErrorDetail {
+            file: Some(self.path.clone()),
+            severity: if only_warnings {
+                Severity::Warning
+            } else {
+                Severity::Error
+            },
+            ..ErrorDetail::new(
+                format!("{count} more errors omitted"),
+                &codes::ERRORS_OMITTED,
+            )
+        });
+    }
+
+    /// Record the size and modification time of the checked file.
+    fn set_file_metadata(&mut self, metadata: &std::fs::Metadata) {
+        self.size = Some(metadata.len());
+        self.modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_secs_f64());
+    }
+}
+
+/// Severity of a libxml2 diagnostic, from `xmlErrorLevel`.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Severity {
+    Warning,
+    Error,
+    Fatal,
+}
+
+impl fmt::Display for Severity {
+    /// The alternate form `{:#}` is colored for a terminal.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, ansi) = match self {
+            Severity::Warning => ("warning", output::YELLOW),
+            Severity::Error => ("error", output::RED),
+            Severity::Fatal => ("fatal error", output::BOLD_RED),
+        };
+        if f.alternate() {
+            write!(f, "{ansi}{name}{}", output::RESET)
+        } else {
+            f.write_str(name)
+        }
+    }
+}
+
+/// A diagnostic reported by libxml2 while validating a document.
+#[derive(Clone, Serialize, Deserialize)]
+struct ErrorDetail {
+    message: String,
+    file: Option<String>,
+    /// 1-based, or 0 if not available.
+    line: i32,
+    /// 1-based, or 0 if not available.
+    column: i32,
+    severity: Severity,
+    /// The stable code of the kind of error, e.g. "VX2003".
+    #[serde(deserialize_with = "codes::deserialize_id")]
+    error_code: codes::Id,
+    /// An explanation of a common error and how to fix it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<String>,
+    /// libxml2's `xmlErrorDomain`, i.e. which part of libxml2 reported it.
+    domain: i32,
+    /// libxml2's `xmlParserErrors` code.
+    code: i32,
+    /// The schema being validated against, if any.
+    schema: Option<String>,
+    /// How many times this error was reported, if identical ones were folded together.
+    occurrences: usize,
+}
+
+impl ErrorDetail {
+    /// An error found by validate-xml itself rather than libxml2.
+    fn new(message: String, code: &codes::Code) -> ErrorDetail {
+        ErrorDetail {
+            hint: hints::hint(&message),
+            message,
+            file: None,
+            line: 0,
+            column: 0,
+            severity: Severity::Error,
+            error_code: code.id,
+            domain: 0,
+            code: 0,
+            schema: None,
+            occurrences: 1,
+        }
+    }
+
+    /// Copy the interesting parts of a libxml2 error.
+    ///
+    /// # Safety
+    ///
+    /// The strings in `error` must be null or valid C strings.
+    unsafe fn from_xml_error(error: &XmlError) -> ErrorDetail {
+        let message = from_c_string(error.message)
+            .map(|message| message.trim_end().to_owned())
+            .unwrap_or_default();
+        ErrorDetail {
+            hint: hints::hint(&message),
+            message,
+            file: from_c_string(error.file),
+            line: error.line,
+            column: error.int2,
+            severity: match error.level {
+                1 => Severity::Warning,
+                3 => Severity::Fatal,
+                _ => Severity::Error,
+            },
+            error_code: codes::for_libxml2(error.domain, error.code).id,
+            domain: error.domain,
+            code: error.code,
+            schema: None,
+            occurrences: 1,
+        }
+    }
+}
+
+/// Copy a C string, which may be null.
 ///
-/// Panics on I/O error.
-#[cached(sync_writes = true)]
-fn get_schema(url: String) -> XmlSchemaPtr {
-    lazy_static! {
-        static ref CLIENT: Client = Client::new();
+/// # Safety
+///
+/// `s` must be null or a valid C string.
+unsafe fn from_c_string(s: *const c_char) -> Option<String> {
+    (!s.is_null()).then(|| CStr::from_ptr(s).to_string_lossy().into_owned())
+}
+
+/// Fold errors with the same code and message into their first occurrence.
+fn deduplicate(errors: Vec<ErrorDetail>) -> Vec<ErrorDetail> {
+    let mut first_index: HashMap<(i32, String), usize> = HashMap::new();
+    let mut unique: Vec<ErrorDetail> = Vec::new();
+    for error in errors {
+        match first_index.entry((error.code, error.message.clone())) {
+            Entry::Occupied(entry) => unique[*entry.get()].occurrences += 1,
+            Entry::Vacant(entry) => {
+                entry.insert(unique.len());
+                unique.push(error);
+            }
+        }
+    }
+    unique
+}
+
+impl fmt::Display for ErrorDetail {
+    /// Format like a compiler diagnostic: `file:line:column: severity: message`.
+    /// The alternate form `{:#}` colors the severity.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{file}:")?;
+        }
+        if self.line > 0 {
+            write!(f, "{}:", self.line)?;
+            if self.column > 0 {
+                write!(f, "{}:", self.column)?;
+            }
+        }
+        if f.alternate() {
+            write!(f, " {:#}:", self.severity)?;
+        } else {
+            write!(f, " {}:", self.severity)?;
+        }
+        write!(f, " {} ({}", self.message, self.error_code)?;
+        // Errors of validate-xml's own have no libxml2 code.
+        if self.code != 0 {
+            write!(f, ", libxml2 code {}", self.code)?;
+        }
+        f.write_str(")")?;
+        if self.occurrences > 1 {
+            write!(f, " (repeated {} times)", self.occurrences)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collect libxml2 errors into the `Vec<ErrorDetail>` passed as `user_data`.
+extern "C" fn collect_error(user_data: *mut c_void, error: *const XmlError) {
+    unsafe {
+        let errors = &mut *(user_data as *mut Vec<ErrorDetail>);
+        errors.push(ErrorDetail::from_xml_error(&*error));
     }
+}
 
-    // DEBUG to show that download happens only once.
-    println!("Downloading now {url}...");
+/// Log libxml2 errors that no caller collects, rather than letting libxml2 print
+/// them on stderr.
+extern "C" fn log_error(_user_data: *mut c_void, error: *const XmlError) {
+    let error = unsafe { ErrorDetail::from_xml_error(&*error) };
+    match error.severity {
+        Severity::Warning => tracing::warn!("{error}"),
+        Severity::Error | Severity::Fatal => tracing::error!("{error}"),
+    }
+}
 
-    let response = CLIENT.get(url.as_str()).send().unwrap().bytes().unwrap();
+/// Route libxml2's errors that are not collected to the log, on this thread.
+fn log_errors() {
+    unsafe { xmlSetStructuredErrorFunc(std::ptr::null_mut(), log_error) };
+}
 
+/// Run libxml2 code, collecting the errors it reports on this thread into `errors`
+/// instead of the log. libxml2's error handlers are per thread.
+fn collecting_errors<T>(errors: &mut Vec<ErrorDetail>, f: impl FnOnce() -> T) -> T {
     unsafe {
-        let schema_parser_ctxt =
-            xmlSchemaNewMemParserCtxt(response.as_ptr() as *const c_char, response.len() as i32);
+        xmlSetStructuredErrorFunc(
+            errors as *mut Vec<ErrorDetail> as *mut c_void,
+            collect_error,
+        )
+    };
+    let result = f();
+    log_errors();
+    result
+}
 
-        // Use default callbacks rather than overriding.
-        //xmlSchemaSetParserErrors();
+/// The namespace of `schemaLocation`, whatever prefix a document binds it to.
+const XSI_NAMESPACE: &[u8] = b"http://www.w3.org/2001/XMLSchema-instance";
 
-        let schema = xmlSchemaParse(schema_parser_ctxt);
-        xmlSchemaFreeParserCtxt(schema_parser_ctxt);
+/// Return the Schema URLs of the schemaLocation of the root element, if any.
+/// A schemaLocation holds pairs of namespace and Schema URL.
+fn extract_schema_urls(document: &[u8]) -> Vec<String> {
+    let document = as_utf8(document);
+    let attributes = attributes(root_start_tag(&document));
+    // The prefix is usually xsi, but any other bound on the root element will do.
+    let binds_xsi = |prefix: &[u8]| {
+        attributes.iter().any(|&(name, value)| {
+            name.strip_prefix(b"xmlns:") == Some(prefix) && value == XSI_NAMESPACE
+        })
+    };
+    let schema_location = attributes.iter().find(|&&(name, _)| {
+        name.strip_suffix(b":schemaLocation")
+            .is_some_and(&binds_xsi)
+    });
+    match schema_location {
+        Some((_, value)) => String::from_utf8_lossy(value)
+            .split_whitespace()
+            .skip(1)
+            .step_by(2)
+            .map(str::to_owned)
+            .collect(),
+        None => Vec::new(),
+    }
+}
 
-        XmlSchemaPtr(schema)
+/// The local name and namespace URI of a document's root element, read from its
+/// start tag rather than by parsing the document. None if it has no start tag.
+fn peek_root_element(document: &[u8]) -> Option<(String, Option<String>)> {
+    lazy_static! {
+        static ref NAME: regex::bytes::Regex = regex::bytes::Regex::new(r"^<([^\s/>?!][^\s/>]*)")
+            .expect("failed to compile element name regex");
     }
+
+    let document = as_utf8(document);
+    let tag = root_start_tag(&document);
+    let qualified_name = NAME.captures(tag)?.get(1)?.as_bytes();
+    let (prefix, name) = match memchr::memchr(b':', qualified_name) {
+        Some(colon) => (Some(&qualified_name[..colon]), &qualified_name[colon + 1..]),
+        None => (None, qualified_name),
+    };
+    let namespace = attributes(tag)
+        .into_iter()
+        .find(|&(attribute, _)| match prefix {
+            Some(prefix) => attribute.strip_prefix(b"xmlns:") == Some(prefix),
+            None => attribute == b"xmlns",
+        })
+        .map(|(_, uri)| String::from_utf8_lossy(uri).into_owned())
+        .filter(|uri| !uri.is_empty());
+    Some((String::from_utf8_lossy(name).into_owned(), namespace))
 }
 
-/// Copy the behavior of [`xmllint`](https://github.com/GNOME/libxml2/blob/master/xmllint.c)
-fn validate(path_buf: PathBuf) {
-    let url = extract_schema_url(path_buf.as_path()).unwrap();
-    let schema = get_schema(url);
+/// The attributes of a start tag, as names and values.
+fn attributes(tag: &[u8]) -> Vec<(&[u8], &[u8])> {
+    lazy_static! {
+        static ref ATTRIBUTE: regex::bytes::Regex =
+            regex::bytes::Regex::new(r#"\s([^\s=/>]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+                .expect("failed to compile attribute regex");
+    }
 
-    let path_str = path_buf.to_str().unwrap();
-    let c_path = CString::new(path_str).unwrap();
+    ATTRIBUTE
+        .captures_iter(tag)
+        .filter_map(|caps| {
+            Some((
+                caps.get(1)?.as_bytes(),
+                caps.get(2).or(caps.get(3))?.as_bytes(),
+            ))
+        })
+        .collect()
+}
 
-    unsafe {
-        // Have to create new validation context for each parse.
-        let schema_valid_ctxt = xmlSchemaNewValidCtxt(schema.0);
+/// A document in UTF-8, for finding its schemaLocation. UTF-16 is recognized by its
+/// byte order mark or by how its first `<?` is encoded, and ISO-8859-1 by the XML
+/// declaration. Other encodings agree with UTF-8 on the markup of interest and are
+/// left as they are.
+fn as_utf8(document: &[u8]) -> Cow<'_, [u8]> {
+    lazy_static! {
+        static ref DECLARATION: regex::bytes::Regex = regex::bytes::Regex::new(
+            r#"^(?:\xEF\xBB\xBF)?<\?xml\s[^>]*?encoding\s*=\s*["']([A-Za-z0-9._-]+)["']"#
+        )
+        .expect("failed to compile XML declaration regex");
+    }
 
-        // TODO better error message with integrated path using callback.
-        //xmlSchemaSetValidErrors();
+    let utf16 = |document: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+        let units = document
+            .chunks_exact(2)
+            .map(|pair| from_bytes([pair[0], pair[1]]));
+        let text: String = char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        Cow::Owned(text.into_bytes())
+    };
+    match document {
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        [b'<', 0, b'?', 0, ..] => utf16(document, u16::from_le_bytes),
+        [0, b'<', 0, b'?', ..] => utf16(document, u16::from_be_bytes),
+        _ => match DECLARATION.captures(document) {
+            Some(caps)
+                if ["iso-8859-1", "latin1", "latin-1"]
+                    .iter()
+                    .any(|name| caps[1].eq_ignore_ascii_case(name.as_bytes())) =>
+            {
+                // Each byte is the code point of the same value.
+                Cow::Owned(
+                    document
+                        .iter()
+                        .map(|&byte| byte as char)
+                        .collect::<String>()
+                        .into_bytes(),
+                )
+            }
+            _ => Cow::Borrowed(document),
+        },
+    }
+}
 
-        // This reads the file and validates it.
-        let result = xmlSchemaValidateFile(schema_valid_ctxt, c_path.as_ptr(), 0);
-        if result == 0 {
-            eprintln!("{path_str} validates");
-        } else if result > 0 {
-            // Note: the message is output after the validation messages.
-            eprintln!("{path_str} fails to validate");
+/// The start tag of a document's root element, found by skipping the XML declaration,
+/// processing instructions, comments and the DOCTYPE. The whole document if it has
+/// no complete start tag.
+fn root_start_tag(document: &[u8]) -> &[u8] {
+    let mut position = 0;
+    while let Some(offset) = memchr::memchr(b'<', &document[position..]) {
+        let start = position + offset;
+        let markup = &document[start..];
+        let len = if markup.starts_with(b"<?") {
+            memchr::memmem::find(markup, b"?>").map(|end| end + 2)
+        } else if markup.starts_with(b"<!--") {
+            memchr::memmem::find(markup, b"-->").map(|end| end + 3)
+        } else if markup.starts_with(b"<!") {
+            markup_len(markup)
         } else {
-            eprintln!("{path_str} validation generated an internal error");
+            return markup_len(markup).map_or(document, |len| &markup[..len]);
+        };
+        match len {
+            Some(len) => position = start + len,
+            None => break,
         }
+    }
+    document
+}
 
-        xmlSchemaFreeValidCtxt(schema_valid_ctxt);
+/// Whether the start of a document holds the whole start tag of its root element.
+fn has_root_start_tag(start: &[u8]) -> bool {
+    let start = as_utf8(start);
+    root_start_tag(&start).len() < start.len()
+}
+
+/// The length of a tag or DOCTYPE up to its closing `>`, which may not be quoted or,
+/// in a DOCTYPE, in the internal subset.
+fn markup_len(markup: &[u8]) -> Option<usize> {
+    let mut quote = None;
+    let mut depth = 0;
+    for (i, &byte) in markup.iter().enumerate() {
+        match (quote, byte) {
+            (Some(open), _) if byte == open => quote = None,
+            (Some(_), _) => {}
+            (None, b'"' | b'\'') => quote = Some(byte),
+            (None, b'[') => depth += 1,
+            (None, b']') => depth -= 1,
+            (None, b'>') if depth == 0 => return Some(i + 1),
+            _ => {}
+        }
     }
+    None
 }
 
-fn main() {
-    let args: Args = Docopt::new(USAGE)
-        .and_then(|d| d.deserialize())
-        .unwrap_or_else(|e| e.exit());
-    let extension_str = &(args.flag_extension);
+/// Whether a schema reference is a remote URL rather than a local path.
+fn is_remote(url: &str) -> bool {
+    let scheme = |scheme: &str| {
+        url.get(..scheme.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+    };
+    scheme("http://") || scheme("https://")
+}
+
+/// The file of a local schema reference, which is a path or a `file:` URL. A path
+/// may be written with backslashes, as on Windows, and a Windows path may start
+/// with a drive letter, which is not taken for a URL scheme.
+fn local_path(url: &str) -> PathBuf {
+    let is_file_url = url
+        .get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("file:"));
+    if is_file_url {
+        if let Some(path) = url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+        {
+            return path;
+        }
+    }
+    if cfg!(windows) {
+        PathBuf::from(url)
+    } else {
+        PathBuf::from(url.replace('\\', "/"))
+    }
+}
+
+/// Where a schema was loaded from.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SchemaSource {
+    Local,
+    Remote,
+}
+
+/// How long loading one schema took, to attribute slow cold starts to schema hosts.
+#[derive(Clone, Serialize, Deserialize)]
+struct SchemaLoad {
+    url: String,
+    source: SchemaSource,
+    /// Size of the schema as downloaded or read.
+    bytes: usize,
+    /// Time to download or read the schema.
+    fetch_ms: f64,
+    /// Time for libxml2 to parse and compile it.
+    parse_ms: f64,
+    /// How long ago the schema was last changed, from the file's modification time
+    /// or the server's Last-Modified header, if known.
+    age_seconds: Option<f64>,
+}
+
+lazy_static! {
+    static ref SCHEMA_LOADS: Mutex<Vec<SchemaLoad>> = Mutex::new(Vec::new());
+}
 
+/// The schemas loaded so far, by URL.
+fn schema_loads() -> Vec<SchemaLoad> {
+    let mut loads = SCHEMA_LOADS.lock().unwrap().clone();
+    loads.sort_by(|a, b| a.url.cmp(&b.url));
+    loads
+}
+
+/// A schema loaded by `get_schema`, null if it failed to compile, with the errors
+/// that made it fail.
+#[derive(Clone)]
+struct LoadedSchema {
+    schema: XmlSchemaPtr,
+    errors: Vec<ErrorDetail>,
+}
+
+/// Held while parsing a schema: libxml2's schema parser is not thread-safe, unlike
+/// validation against parsed schemas.
+///
+/// The cache of `load_schema` also runs one load at a time today, but that is a
+/// property of `sync_writes` rather than a guarantee.
+static SCHEMA_PARSER: Mutex<()> = Mutex::new(());
+
+/// Cache schema into memory after downloading from Web once and stashing into memory.
+/// Schemas given as local paths are read from disk instead, once per file however
+/// they are referred to. A schema that cannot be fetched is null, with a VX4001
+/// error saying why.
+fn get_schema(url: String) -> LoadedSchema {
+    load_schema(schema_key(url))
+}
+
+/// The key a schema is loaded and cached under: a remote URL as it is, and a local
+/// schema by its canonical path, so that relative paths, `file:` URLs and links to
+/// the same file share one load.
+///
+/// Relative paths are those of `--schema` and the schema rules, relative to the
+/// current directory. Those of hints are resolved against their document by
+/// `hinted_schema_urls` before they get here, and those of imports and includes
+/// against their schema as it is parsed, so that neither depends on where
+/// validate-xml runs.
+#[cached]
+fn schema_key(url: String) -> String {
+    if is_remote(&url) {
+        return url;
+    }
+    match std::fs::canonicalize(local_path(&url)).map(PathBuf::into_os_string) {
+        Ok(path) => path.into_string().unwrap_or(url),
+        // Left for the load to report.
+        Err(_) => url,
+    }
+}
+
+#[cached(sync_writes = true)]
+fn load_schema(url: String) -> LoadedSchema {
+    let _span = tracing::info_span!("load_schema", url = url.as_str()).entered();
+
+    let start = Instant::now();
+    let source = if is_remote(&url) {
+        SchemaSource::Remote
+    } else {
+        SchemaSource::Local
+    };
+    // Cached like a schema that does not compile, so that it is not fetched again
+    // for each file; every file using it reports the error.
+    let (response, modified) = match fetch_schema(&url, start) {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            return LoadedSchema {
+                schema: XmlSchemaPtr(std::ptr::null_mut()),
+                errors: vec![ErrorDetail {
+                    file: Some(url.clone()),
+                    ..ErrorDetail::new(
+                        format!("Failed to load the schema: {e}"),
+                        &codes::SCHEMA_NOT_LOADED,
+                    )
+                }],
+            }
+        }
+    };
+    let fetched = Instant::now();
+
+    let mut errors: Vec<ErrorDetail> = Vec::new();
+    let schema = unsafe {
+        let _span = tracing::info_span!("parse_schema").entered();
+        let _parsing = SCHEMA_PARSER.lock().unwrap_or_else(|e| e.into_inner());
+        SCHEMA_IMPORTS.with(|imports| *imports.borrow_mut() = Some(Vec::new()));
+        // Parsed under its URL, so that relative imports and includes are resolved
+        // against it rather than the current directory.
+        let c_url = CString::new(url.as_str()).unwrap_or_default();
+        let doc = collecting_errors(&mut errors, || {
+            xmlReadMemory(
+                response.as_ptr() as *const c_char,
+                response.len() as c_int,
+                c_url.as_ptr(),
+                std::ptr::null(),
+                XML_PARSE_NOENT,
+            )
+        });
+        let mut schema = std::ptr::null_mut();
+        if !doc.is_null() {
+            let schema_parser_ctxt = xmlSchemaNewDocParserCtxt(doc);
+            xmlSchemaSetParserStructuredErrors(
+                schema_parser_ctxt,
+                collect_error,
+                &mut errors as *mut Vec<ErrorDetail> as *mut c_void,
+            );
+            schema = xmlSchemaParse(schema_parser_ctxt);
+            xmlSchemaFreeParserCtxt(schema_parser_ctxt);
+        }
+        let imports = SCHEMA_IMPORTS
+            .with(|imports| imports.take())
+            .unwrap_or_default();
+        // A schema missing an import it may not download fails, like one that may
+        // not be downloaded itself.
+        if !imports.is_empty() && !schema.is_null() {
+            xmlSchemaFree(schema);
+            schema = std::ptr::null_mut();
+        }
+        errors.splice(0..0, imports);
+        // A compiled schema may point into its document, which is kept as long as
+        // the schema, i.e. for the rest of the run.
+        if schema.is_null() && !doc.is_null() {
+            xmlFreeDoc(doc);
+        }
+
+        XmlSchemaPtr(schema)
+    };
+    for error in &mut errors {
+        if error.file.is_none() {
+            error.file = Some(url.clone());
+        }
+    }
+    if !schema.0.is_null() {
+        for error in errors.drain(..) {
+            tracing::warn!("{error}");
+        }
+    }
+
+    let load = SchemaLoad {
+        url,
+        source,
+        bytes: response.len(),
+        fetch_ms: (fetched - start).as_secs_f64() * 1000.0,
+        parse_ms: fetched.elapsed().as_secs_f64() * 1000.0,
+        age_seconds: modified
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .map(|age| age.as_secs_f64()),
+    };
+    tracing::debug!(
+        fetch_ms = load.fetch_ms,
+        parse_ms = load.parse_ms,
+        "Loaded schema {}",
+        load.url
+    );
+    SCHEMA_LOADS.lock().unwrap().push(load);
+    LoadedSchema { schema, errors }
+}
+
+thread_local! {
+    /// The errors of loading the imports and includes of the schema being parsed on
+    /// this thread, if one is.
+    static SCHEMA_IMPORTS: RefCell<Option<Vec<ErrorDetail>>> = const { RefCell::new(None) };
+}
+
+/// libxml2's own entity loader, which `load_entity` falls back on.
+static DEFAULT_ENTITY_LOADER: OnceLock<XmlExternalEntityLoader> = OnceLock::new();
+
+/// Make libxml2 load external resources with `load_entity`. Called once, before
+/// anything is parsed.
+fn install_entity_loader() {
     unsafe {
-        xmlInitParser();
-        xmlInitGlobals();
+        DEFAULT_ENTITY_LOADER.get_or_init(|| xmlGetExternalEntityLoader());
+        xmlSetExternalEntityLoader(load_entity);
     }
+}
 
-    // No real point in using WalkParallel.
-    rayon::scope(|scope| {
-        for result in ignore::Walk::new(&args.arg_dir) {
-            scope.spawn(move |_| {
-                if let Ok(entry) = result {
-                    let path = entry.path().to_owned();
-                    if let Some(extension) = path.extension() {
-                        if extension.to_str().unwrap() == extension_str {
-                            validate(path);
-                        }
-                    }
-                }
-            });
+/// Load the external resources of libxml2. The remote imports and includes of a
+/// schema being parsed are downloaded like schemas are, by the host policy, rather
+/// than by libxml2 itself; anything else is left to libxml2's own loader.
+unsafe extern "C" fn load_entity(
+    url: *const c_char,
+    id: *const c_char,
+    ctxt: *mut XmlParserCtxt,
+) -> *mut XmlParserInput {
+    let default = || DEFAULT_ENTITY_LOADER.get().unwrap()(url, id, ctxt);
+    let parsing_schema = SCHEMA_IMPORTS.with(|imports| imports.borrow().is_some());
+    let location = from_c_string(url).filter(|location| is_remote(location));
+    let Some(location) = location.filter(|_| parsing_schema) else {
+        return default();
+    };
+    let refused = |error: ErrorDetail| {
+        SCHEMA_IMPORTS.with(|imports| {
+            imports.borrow_mut().as_mut().unwrap().push(ErrorDetail {
+                file: Some(location.clone()),
+                ..error
+            })
+        });
+        std::ptr::null_mut()
+    };
+    if let Some(reason) = network::refusal(&location) {
+        return refused(ErrorDetail::new(
+            format!("Not downloading the schema: {reason}"),
+            &codes::BLOCKED_HOST,
+        ));
+    }
+    let content = match download_schema(&location, Instant::now()) {
+        Ok((content, _)) => content,
+        Err(e) => {
+            return refused(ErrorDetail::new(
+                format!("Failed to load the schema: {e}"),
+                &codes::SCHEMA_NOT_LOADED,
+            ))
+        }
+    };
+    let buffer =
+        xmlParserInputBufferCreateMem(content.as_ptr() as *const c_char, content.len() as c_int, 0);
+    if buffer.is_null() {
+        return std::ptr::null_mut();
+    }
+    let input = xmlNewIOInputStream(ctxt, buffer, 0);
+    if input.is_null() {
+        xmlFreeParserInputBuffer(buffer);
+        return input;
+    }
+    // So that the includes of the download are resolved against its URL.
+    (*input).filename = xmlCharStrdup(url);
+    input
+}
+
+/// Download a remote schema or read a local one, as Bytes so that neither the
+/// download nor the file is copied, with when it was last changed if known.
+fn fetch_schema(url: &str, start: Instant) -> io::Result<(Bytes, Option<SystemTime>)> {
+    if is_remote(url) {
+        return download_schema(url, start);
+    }
+    let path = local_path(url);
+    std::fs::read(&path).map(|content| {
+        let modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        (Bytes::from(content), modified)
+    })
+}
+
+/// A schema loaded for the native backend, with the schemas it includes and
+/// imports, or the error that made it fail. Cached like `load_schema`, by the key
+/// of `schema_key`.
+#[cfg(feature = "native")]
+#[cached(sync_writes = true)]
+fn load_native_schema(url: String) -> Result<Arc<native::Schema>, ErrorDetail> {
+    let _span = tracing::info_span!("load_schema", url = url.as_str()).entered();
+
+    let start = Instant::now();
+    let schema = native::Schema::load(&url, |location| {
+        // Imports and includes are downloaded by the host policy too.
+        if let Some(reason) = network::refusal(location) {
+            return Err(format!("{location}: not downloading the schema: {reason}"));
         }
+        fetch_schema(location, start)
+            .map(|(content, _)| content.to_vec())
+            .map_err(|e| match location == url {
+                true => e.to_string(),
+                false => format!("{location}: {e}"),
+            })
     });
+    tracing::debug!(
+        load_ms = start.elapsed().as_secs_f64() * 1000.0,
+        "Loaded schema {url}"
+    );
+    schema.map(Arc::new).map_err(|e| ErrorDetail {
+        file: Some(url),
+        ..ErrorDetail::new(
+            format!("Failed to load the schema: {e}"),
+            &codes::SCHEMA_NOT_LOADED,
+        )
+    })
+}
+
+/// Download a remote schema, with when it was last changed if the server says.
+fn download_schema(url: &str, start: Instant) -> io::Result<(Bytes, Option<SystemTime>)> {
+    lazy_static! {
+        static ref CLIENT: Client = network::client();
+    }
+
+    // Shows that download happens only once.
+    tracing::info!("Downloading now {url}...");
+
+    let _span = tracing::info_span!("download").entered();
+    let mut download = progress::spinner("download", format!("Downloading {url}"));
+    network::wait_for_host(url);
+    let response = CLIENT.get(url).send();
+    let status = response
+        .as_ref()
+        .ok()
+        .map(|response| response.status().as_u16());
+    let modified = response
+        .as_ref()
+        .ok()
+        .and_then(|response| response.headers().get(reqwest::header::LAST_MODIFIED))
+        .and_then(|value| value.to_str().ok())
+        .and_then(network::parse_http_date);
+    let body = response
+        .and_then(Response::error_for_status)
+        .map_err(io::Error::other)
+        .and_then(|response| {
+            download.set_download_size(response.content_length());
+            read_body(response, &download)
+        });
+    audit::record(&audit::Fetch {
+        url,
+        status,
+        bytes: body.as_ref().ok().map(Bytes::len),
+        duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+        outcome: if body.is_ok() { "downloaded" } else { "failed" },
+    })
+    .unwrap_or_else(|e| tracing::error!("Failed to log the download of {url}: {e}"));
+    download.finish(Some(url));
+    Ok((body?, modified))
+}
+
+/// Read a response's body, counting its bytes in the download's progress.
+fn read_body(mut response: Response, download: &progress::Phase) -> io::Result<Bytes> {
+    let mut body = Vec::with_capacity(response.content_length().unwrap_or(0) as usize);
+    let mut chunk = [0; 64 << 10];
+    loop {
+        match response.read(&mut chunk) {
+            Ok(0) => return Ok(body.into()),
+            Ok(len) => {
+                body.extend_from_slice(&chunk[..len]);
+                download.add_bytes(len as u64);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The schemas to validate a file against, and where they were found: an override,
+/// `--schema`, a schema rule, or the file's schemaLocation hint.
+fn schema_urls(path: &Path, settings: &Settings) -> io::Result<(Vec<String>, &'static str)> {
+    let relative_path = path.strip_prefix(&settings.root).unwrap_or(path);
+    if let Some((url, source)) = settings.configured_schema(relative_path) {
+        Ok((vec![url], source))
+    } else {
+        let urls = if gzip::is_compressed(path) {
+            hinted_schema_urls(&gzip::read(path)?, path)
+        } else {
+            hinted_schema_urls(&prefix::read(path, has_root_start_tag)?, path)
+        };
+        Ok((urls, "schemaLocation"))
+    }
+}
+
+/// The schemas of the schemaLocation hint of a document at `path`, with relative
+/// paths resolved against the document's directory, as relative URIs are.
+fn hinted_schema_urls(document: &[u8], path: &Path) -> Vec<String> {
+    let directory = path.parent().unwrap_or(Path::new(""));
+    extract_schema_urls(document)
+        .into_iter()
+        .map(|url| {
+            let local = local_path(&url);
+            if is_remote(&url) || local.is_absolute() {
+                url
+            } else {
+                directory.join(local).to_string_lossy().into_owned()
+            }
+        })
+        .collect()
+}
+
+/// Load the schemas of files as they are found, so that they are loaded, or being
+/// loaded, by the time validation needs them rather than all at its start.
+fn warm_up(found: mpsc::Receiver<PathBuf>, settings: &Settings) {
+    let _span = tracing::info_span!("warm_up").entered();
+    log_errors();
+    let mut seen = HashSet::new();
+    for path in found {
+        let Ok((urls, _)) = schema_urls(&path, settings) else {
+            continue;
+        };
+        for url in urls {
+            if seen.insert(url.clone()) && settings.hosts.refusal(&url).is_none() {
+                // A schema that fails to load is reported by the files using it.
+                get_schema(url);
+            }
+        }
+    }
+}
+
+/// Print libxml2's compiled view of a schema, or its errors if it does not compile.
+fn dump_schema(url: String) {
+    let loaded = get_schema(url.clone());
+    if loaded.schema.0.is_null() {
+        for error in &loaded.errors {
+            eprintln!("{error}");
+        }
+        eprintln!("{url} does not compile");
+        process::exit(1);
+    }
+    io::stdout().flush().unwrap();
+    unsafe {
+        let stdout = libc::fdopen(libc::STDOUT_FILENO, c"w".as_ptr());
+        xmlSchemaDump(stdout, loaded.schema.0);
+        libc::fflush(stdout);
+    }
+}
+
+/// Print each file that would be checked with the schemas it would use, without
+/// loading any schema or validating anything.
+fn dry_run(settings: &Settings) -> io::Result<()> {
+    let mut paths = discover(settings);
+    paths.sort();
+    let mut stdout = io::stdout().lock();
+    for path in paths {
+        let plan = match schema_urls(&path, settings) {
+            Ok((urls, source)) if !urls.is_empty() => {
+                format!("would validate against {} ({source})", urls.join(", "))
+            }
+            Ok(_) if settings.check_well_formed => {
+                "would only check well-formedness: no schema reference".to_owned()
+            }
+            Ok(_) if settings.require_schema => "would fail: no schema reference".to_owned(),
+            Ok(_) => "would skip: no schema reference".to_owned(),
+            Err(e) => format!("cannot be read: {e}"),
+        };
+        writeln!(stdout, "{} {plan}", path.display())?;
+    }
+    Ok(())
+}
+
+/// Print each distinct schema the files would be validated against, with how many
+/// files use it, most used first.
+fn list_schemas(settings: &Settings) -> io::Result<()> {
+    let per_file: Vec<Vec<String>> = discover(settings)
+        .into_par_iter()
+        .filter_map(|path| match schema_urls(&path, settings) {
+            Ok((urls, _)) => Some(urls),
+            Err(e) => {
+                tracing::warn!("Cannot read {}: {e}", path.display());
+                None
+            }
+        })
+        .collect();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut without_schema = 0;
+    for urls in &per_file {
+        if urls.is_empty() {
+            without_schema += 1;
+        }
+        for url in urls {
+            *counts.entry(url).or_default() += 1;
+        }
+    }
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let mut stdout = io::stdout().lock();
+    for (url, count) in counts {
+        writeln!(stdout, "{count:8}  {url}")?;
+    }
+    if without_schema > 0 {
+        writeln!(stdout, "{without_schema:8}  (no schema reference)")?;
+    }
+    Ok(())
+}
+
+/// Replace each `@FILE` argument with the arguments in the file, one per line.
+/// Blank lines and lines starting with `#` are ignored.
+fn expand_args_files(args: Vec<String>) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg.strip_prefix('@') {
+            Some(path) if !path.is_empty() => {
+                let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+                expanded.extend(
+                    text.lines()
+                        .map(str::trim)
+                        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                        .map(str::to_owned),
+                );
+            }
+            _ => expanded.push(arg),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Combine json reports, e.g. of several shards, into one written to `output` or
+/// else stdout.
+fn merge_reports(paths: &[String], output: Option<&str>) -> Result<(), String> {
+    let mut merged = output::Report::default();
+    for path in paths {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let report = serde_json::from_str(&text).map_err(|e| format!("{path}: {e}"))?;
+        merged.merge(report);
+    }
+    let text = serde_json::to_string_pretty(&merged).unwrap() + "\n";
+    match output {
+        Some(path) => output::temp_file_for(Path::new(path))
+            .and_then(|mut file| {
+                file.write_all(text.as_bytes())?;
+                file.persist(path).map_err(|e| e.error)?;
+                Ok(())
+            })
+            .map_err(|e| format!("{path}: {e}")),
+        None => io::stdout()
+            .write_all(text.as_bytes())
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Exit after failing to write the report, e.g. to a full disk or a closed pipe,
+/// removing the report files that will not be complete.
+fn report_failed(output: &mut OutputWriter, e: io::Error) -> ! {
+    output.discard();
+    eprintln!("failed to write the report: {e}");
+    process::exit(1);
+}
+
+/// Check a file, recording how long it took.
+fn timed(check: impl FnOnce() -> FileResult) -> FileResult {
+    let start = Instant::now();
+    let mut result = check();
+    result.duration_ms = start.elapsed().as_secs_f64() * 1000.0;
+    result
+}
+
+/// Find the files to validate under the root directory.
+fn discover(settings: &Settings) -> Vec<PathBuf> {
+    discover_each(settings, |_| {})
+}
+
+/// Find the files to validate under the root directory, passing each to `found` as
+/// soon as it is found.
+fn discover_each(settings: &Settings, mut found: impl FnMut(&Path)) -> Vec<PathBuf> {
+    let _span = tracing::info_span!("discover").entered();
+    let paths: Vec<PathBuf> = walk(settings, ignore::WalkBuilder::new(&settings.root))
+        .inspect(|path| {
+            if settings.sample.is_none() {
+                found(path);
+            }
+        })
+        .collect();
+    // Only the files picked are passed on, which needs all of them first.
+    match settings.sample {
+        Some(sample) => {
+            let paths = sample.choose(&settings.root, paths);
+            paths.iter().for_each(|path| found(path));
+            paths
+        }
+        None => paths,
+    }
+}
+
+/// The files to check among `changed` paths under the root directory, e.g. files
+/// and directories changed during `--watch`. Only the directories leading to them
+/// are read, and ignore files apply as they do to a whole walk.
+fn discover_changed(settings: &Settings, changed: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut walker = ignore::WalkBuilder::new(&settings.root);
+    walker.filter_entry(move |entry| {
+        changed
+            .iter()
+            .any(|path| path.starts_with(entry.path()) || entry.path().starts_with(path))
+    });
+    walk(settings, walker).collect()
+}
+
+/// The files that a walk finds and that are to be checked, but for `--sample`.
+fn walk(settings: &Settings, walker: ignore::WalkBuilder) -> impl Iterator<Item = PathBuf> + '_ {
+    // No real point in using WalkParallel.
+    walker
+        .build()
+        .filter_map(Result::ok)
+        .map(|entry| entry.into_path())
+        .filter(|path| settings.has_extension(path))
+        .filter(|path| {
+            settings
+                .shard
+                .is_none_or(|shard| shard.contains(&settings.root, path))
+        })
+        .filter(|path| settings.has_root_element(path))
+}
+
+/// The files to check that are staged for the next git commit.
+fn staged(settings: &Settings) -> io::Result<Vec<PathBuf>> {
+    let output = process::Command::new("git")
+        .args([
+            "diff",
+            "--cached",
+            "--name-only",
+            "--relative",
+            "--diff-filter=ACMR",
+            "-z",
+        ])
+        .current_dir(&settings.root)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    let paths = output
+        .stdout
+        .split(|&byte| byte == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| settings.root.join(String::from_utf8_lossy(name).as_ref()))
+        .filter(|path| settings.has_extension(path))
+        .filter(|path| {
+            settings
+                .shard
+                .is_none_or(|shard| shard.contains(&settings.root, path))
+        })
+        .filter(|path| settings.has_root_element(path))
+        .collect();
+    Ok(match settings.sample {
+        Some(sample) => sample.choose(&settings.root, paths),
+        None => paths,
+    })
+}
+
+/// One of several parts of the files, so that CI runners can each check one.
+#[derive(Clone, Copy)]
+struct Shard {
+    /// 1-based.
+    index: u64,
+    count: u64,
+}
+
+impl Shard {
+    /// Parse "I/N", e.g. "2/8".
+    fn parse(text: &str) -> Result<Shard, String> {
+        let invalid = || format!("shard {text:?} is not of the form I/N with 1 <= I <= N");
+        let (index, count) = text.split_once('/').ok_or_else(invalid)?;
+        let index = index.parse().map_err(|_| invalid())?;
+        let count = count.parse().map_err(|_| invalid())?;
+        if index < 1 || index > count {
+            return Err(invalid());
+        }
+        Ok(Shard { index, count })
+    }
+
+    /// Whether a file is in this shard. Files are assigned by a hash of their path
+    /// relative to the root, so each keeps its shard as other files come and go.
+    fn contains(self, root: &Path, path: &Path) -> bool {
+        let relative_path = path.strip_prefix(root).unwrap_or(path);
+        let digest = Sha256::digest(relative_path.to_string_lossy().as_bytes());
+        let hash = u64::from_be_bytes(digest[..8].try_into().unwrap());
+        hash % self.count == self.index - 1
+    }
+}
+
+/// A root element that files must have to be checked.
+#[derive(Clone)]
+struct RootElement {
+    name: String,
+    namespace: Option<String>,
+}
+
+impl RootElement {
+    /// Parse "NAME" or "NAME,NAMESPACE", e.g. "note,urn:note".
+    fn parse(text: &str) -> Result<RootElement, String> {
+        let (name, namespace) = match text.split_once(',') {
+            Some((name, namespace)) => (name, Some(namespace.to_owned())),
+            None => (text, None),
+        };
+        if name.is_empty() {
+            return Err(format!("root element {text:?} has no name"));
+        }
+        Ok(RootElement {
+            name: name.to_owned(),
+            namespace,
+        })
+    }
+
+    /// Whether a root element has this name, and this namespace if one was given.
+    fn matches(&self, name: &str, namespace: Option<&str>) -> bool {
+        self.name == name
+            && self
+                .namespace
+                .as_deref()
+                .is_none_or(|expected| Some(expected) == namespace)
+    }
+}
+
+/// A pseudo-random choice of some of the files, for a quick check of a large corpus.
+#[derive(Clone, Copy)]
+struct Sample {
+    count: usize,
+    /// The same seed picks the same files again, as long as the files are the same.
+    seed: u64,
+}
+
+impl Sample {
+    /// Pick `count` of the files, ranking them by a hash of the seed and their path
+    /// relative to the root.
+    fn choose(self, root: &Path, mut paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        paths.sort_by_cached_key(|path| {
+            let relative_path = path.strip_prefix(root).unwrap_or(path);
+            let mut hasher = Sha256::new();
+            hasher.update(self.seed.to_be_bytes());
+            hasher.update(relative_path.to_string_lossy().as_bytes());
+            hasher.finalize()
+        });
+        paths.truncate(self.count);
+        paths.sort();
+        paths
+    }
+}
+
+/// Check one document, turning a panic into an internal error for that document
+/// alone rather than aborting the whole run.
+fn guarded(name: &str, check: impl FnOnce() -> FileResult) -> FileResult {
+    panic::catch_unwind(AssertUnwindSafe(check)).unwrap_or_else(|_| {
+        tracing::error!("Checking {name} panicked");
+        FileResult::new(name, Status::InternalError)
+    })
+}
+
+/// Validate a file, finding its schemas from `--schema`, the schema rules or its schemaLocation.
+fn validate(path_buf: PathBuf, settings: &Settings) -> FileResult {
+    let name = path_buf.to_string_lossy().into_owned();
+    let metadata = match std::fs::metadata(&path_buf) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return removed(name),
+        metadata => metadata,
+    };
+    let mut result = guarded(&name, || match validate_path(path_buf.clone(), settings) {
+        Ok(result) => result,
+        // Removed while it was being checked.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => removed(name.clone()),
+        Err(e) => FileResult {
+            errors: vec![ErrorDetail {
+                file: Some(name.clone()),
+                ..ErrorDetail::new(
+                    format!("Failed to read the file: {e}"),
+                    &codes::INTERNAL_ERROR,
+                )
+            }],
+            ..FileResult::new(&name, Status::InternalError)
+        },
+    });
+    if let Ok(metadata) = metadata {
+        result.set_file_metadata(&metadata);
+    }
+    result
+}
+
+/// The result of a file that was found but no longer exists, e.g. a temporary file
+/// of another process, which is skipped rather than failing the run.
+fn removed(name: String) -> FileResult {
+    tracing::warn!("{name} was removed during the run");
+    FileResult::new(&name, Status::Removed)
+}
+
+/// Validate a file, failing if it cannot be read.
+fn validate_path(path_buf: PathBuf, settings: &Settings) -> io::Result<FileResult> {
+    let path_string = path_buf.to_string_lossy();
+    let path_str = path_string.as_ref();
+    let _span = tracing::info_span!("validate", path = path_str).entered();
+
+    let (urls, _) = schema_urls(&path_buf, settings)?;
+    if urls.is_empty() && !settings.check_well_formed {
+        return Ok(FileResult::new(path_str, Status::NoSchema));
+    }
+    #[cfg(feature = "native")]
+    if settings.backend == Backend::Native {
+        let buffer = gzip::read(&path_buf)?;
+        return Ok(validate_native(path_str, &buffer, urls, settings));
+    }
+
+    let c_path = CString::new(path_str).unwrap();
+
+    // Read the document ourselves, because xmlSchemaValidateFile ignores its parser options.
+    let mut errors = Vec::new();
+    let mut doc = if gzip::is_compressed(&path_buf) || path_buf.to_str().is_none() {
+        // Decompressed here, since libxml2 may be built without zlib, and read here
+        // if the path is not UTF-8, which is how libxml2 takes file names.
+        let buffer = gzip::read(&path_buf)?;
+        read_memory(&buffer, &c_path, settings, &mut errors)
+    } else {
+        collecting_errors(&mut errors, || unsafe {
+            xmlReadFile(c_path.as_ptr(), std::ptr::null(), settings.parse_options)
+        })
+    };
+    // If libxml2 could not open a file that can still be read here, e.g. because
+    // of how the platform's C library takes its name.
+    if doc.is_null()
+        && errors
+            .iter()
+            .any(|error| error.domain == codes::FROM_IO && error.code == codes::XML_IO_LOAD_ERROR)
+    {
+        if let Ok(buffer) = std::fs::read(&path_buf) {
+            tracing::debug!("Reading {path_str} from memory, since libxml2 could not open it");
+            errors.clear();
+            doc = read_memory(&buffer, &c_path, settings, &mut errors);
+        }
+    }
+    // Removed since its schemas were looked up.
+    if doc.is_null() && !path_buf.exists() {
+        return Err(io::ErrorKind::NotFound.into());
+    }
+    Ok(validate_doc(path_str, doc, urls, errors, settings))
+}
+
+/// Parse a document held in memory, naming it `name` in its errors.
+fn read_memory(
+    buffer: &[u8],
+    name: &CStr,
+    settings: &Settings,
+    errors: &mut Vec<ErrorDetail>,
+) -> *mut XmlDoc {
+    collecting_errors(errors, || unsafe {
+        xmlReadMemory(
+            buffer.as_ptr() as *const c_char,
+            buffer.len() as c_int,
+            name.as_ptr(),
+            std::ptr::null(),
+            settings.parse_options,
+        )
+    })
+}
+
+/// Validate a file, unless the cache has its result for its current content and
+/// schemas.
+fn validate_cached(path_buf: PathBuf, settings: &Settings, cache: &Mutex<Cache>) -> FileResult {
+    let Ok(content) = std::fs::read(&path_buf) else {
+        return validate(path_buf, settings);
+    };
+    let Ok((urls, _)) = schema_urls(&path_buf, settings) else {
+        return validate(path_buf, settings);
+    };
+    let Some(digest) = cache::digest(&content, &urls) else {
+        return validate(path_buf, settings);
+    };
+    let relative_path = path_buf
+        .strip_prefix(&settings.root)
+        .unwrap_or(&path_buf)
+        .to_owned();
+    if let Some(mut result) = cache.lock().unwrap().get(&relative_path, &digest) {
+        tracing::debug!("Reusing the cached result of {}", path_buf.display());
+        result.path = path_buf.to_string_lossy().into_owned();
+        // The content is unchanged, but the file may have been touched since.
+        if let Ok(metadata) = std::fs::metadata(&path_buf) {
+            result.set_file_metadata(&metadata);
+        }
+        return result;
+    }
+    let result = validate(path_buf, settings);
+    cache
+        .lock()
+        .unwrap()
+        .record(&relative_path, digest, &result);
+    result
+}
+
+/// Validate a single document read from standard input.
+fn validate_stdin(settings: &Settings) -> FileResult {
+    let name = "-";
+    let _span = tracing::info_span!("validate", path = name).entered();
+
+    let mut buffer = Vec::new();
+    io::stdin().read_to_end(&mut buffer).unwrap();
+    let schemas = settings.schema.clone().map(|schema| vec![schema]);
+    validate_memory(name, &buffer, schemas, settings)
+}
+
+/// Validate a document held in memory, against `schemas` if given or else the
+/// document's schemaLocation hint, whose relative paths are taken as relative to the
+/// current directory.
+fn validate_memory(
+    name: &str,
+    buffer: &[u8],
+    schemas: Option<Vec<String>>,
+    settings: &Settings,
+) -> FileResult {
+    guarded(name, || validate_buffer(name, buffer, schemas, settings))
+}
+
+fn validate_buffer(
+    name: &str,
+    buffer: &[u8],
+    schemas: Option<Vec<String>>,
+    settings: &Settings,
+) -> FileResult {
+    let urls = schemas.unwrap_or_else(|| extract_schema_urls(buffer));
+    if urls.is_empty() && !settings.check_well_formed {
+        return FileResult::new(name, Status::NoSchema);
+    }
+    #[cfg(feature = "native")]
+    if settings.backend == Backend::Native {
+        return validate_native(name, buffer, urls, settings);
+    }
+
+    let c_name = CString::new(name).unwrap();
+    let mut errors = Vec::new();
+    let doc = read_memory(buffer, &c_name, settings, &mut errors);
+    validate_doc(name, doc, urls, errors, settings)
+}
+
+/// Copy the behavior of [`xmllint`](https://github.com/GNOME/libxml2/blob/master/xmllint.c)
+///
+/// The document is parsed once and validated against each schema in turn.
+/// Takes ownership of `doc`, which is null if the document is not well-formed.
+/// Malformed documents are reported as such without loading any schema.
+fn validate_doc(
+    name: &str,
+    doc: *mut XmlDoc,
+    urls: Vec<String>,
+    mut errors: Vec<ErrorDetail>,
+    settings: &Settings,
+) -> FileResult {
+    let mut result = FileResult::new(name, Status::Malformed);
+    if doc.is_null() {
+        set_errors(&mut result, errors, settings);
+        return result;
+    }
+
+    (result.root_element, result.root_namespace) = unsafe { root_element(doc) };
+    result.encoding = unsafe { from_c_string((*(doc as *const XmlDocFields)).encoding) }
+        .or_else(|| Some("UTF-8".to_owned()));
+
+    if urls.is_empty() {
+        result.status = Status::NoSchema;
+        set_errors(&mut result, errors, settings);
+        check_rules(doc, &mut result, settings);
+        unsafe { xmlFreeDoc(doc) };
+        return result;
+    }
+
+    let mut internal_error = false;
+    let mut invalid = false;
+    for url in &urls {
+        let first_new_error = errors.len();
+        let loaded = match settings.hosts.refusal(url) {
+            Some(reason) => LoadedSchema {
+                schema: XmlSchemaPtr(std::ptr::null_mut()),
+                errors: vec![ErrorDetail {
+                    file: Some(url.clone()),
+                    ..ErrorDetail::new(
+                        format!("Not downloading the schema: {reason}"),
+                        &codes::BLOCKED_HOST,
+                    )
+                }],
+            },
+            None => get_schema(url.clone()),
+        };
+        let code = if loaded.schema.0.is_null() {
+            errors.extend(loaded.errors);
+            -1
+        } else {
+            errors.extend(namespace_mismatch(&result, loaded.schema));
+            validate_against(doc, loaded.schema, &mut errors)
+        };
+        if settings.check_ids {
+            recode_duplicate_ids(doc, &mut errors[first_new_error..]);
+        }
+        for error in &mut errors[first_new_error..] {
+            error.schema = Some(url.clone());
+        }
+        internal_error |= code < 0;
+        invalid |= code > 0;
+    }
+    if settings.check_ids && !internal_error {
+        invalid |= dangling_idrefs(doc, &mut errors);
+    }
+
+    // A document whose every error is suppressed validates.
+    let only_suppressed = set_errors(&mut result, errors, settings);
+    result.status = if internal_error {
+        Status::InternalError
+    } else if invalid && !only_suppressed {
+        Status::Invalid
+    } else {
+        Status::Valid
+    };
+    result.schemas = urls;
+    check_rules(doc, &mut result, settings);
+    unsafe { xmlFreeDoc(doc) };
+    result
+}
+
+/// Validate a document with the native backend, like `validate_doc` does with
+/// libxml2.
+#[cfg(feature = "native")]
+fn validate_native(
+    name: &str,
+    buffer: &[u8],
+    urls: Vec<String>,
+    settings: &Settings,
+) -> FileResult {
+    let mut result = FileResult::new(name, Status::Malformed);
+    let in_file = |error: ErrorDetail| ErrorDetail {
+        file: Some(name.to_owned()),
+        ..error
+    };
+    let utf8 = as_utf8(buffer);
+    let document = match std::str::from_utf8(&utf8) {
+        Ok(text) => native::parse_document(text).map_err(native::not_well_formed),
+        Err(e) => Err(ErrorDetail {
+            severity: Severity::Fatal,
+            line: 1 + utf8[..e.valid_up_to()]
+                .iter()
+                .filter(|&&b| b == b'\n')
+                .count() as i32,
+            ..ErrorDetail::new(
+                "Input is not proper UTF-8, or its encoding is not supported.".to_owned(),
+                &codes::NOT_WELL_FORMED,
+            )
+        }),
+    };
+    let document = match document {
+        Ok(document) => document,
+        Err(error) => {
+            set_errors(&mut result, vec![in_file(error)], settings);
+            return result;
+        }
+    };
+
+    let root = document.root_element();
+    result.root_element = Some(root.tag_name().name().to_owned());
+    result.root_namespace = root.tag_name().namespace().map(str::to_owned);
+    result.encoding = Some("UTF-8".to_owned());
+    if urls.is_empty() {
+        result.status = Status::NoSchema;
+        return result;
+    }
+
+    let mut errors = Vec::new();
+    let mut internal_error = false;
+    let mut invalid = false;
+    for url in &urls {
+        let loaded = match settings.hosts.refusal(url) {
+            Some(reason) => Err(ErrorDetail {
+                file: Some(url.clone()),
+                ..ErrorDetail::new(
+                    format!("Not downloading the schema: {reason}"),
+                    &codes::BLOCKED_HOST,
+                )
+            }),
+            None => load_native_schema(schema_key(url.clone())),
+        };
+        match loaded {
+            Ok(schema) => {
+                let found = native::validate(&document, &schema);
+                invalid |= !found.is_empty();
+                errors.extend(found.into_iter().map(|error| ErrorDetail {
+                    schema: Some(url.clone()),
+                    ..in_file(error)
+                }));
+            }
+            Err(error) => {
+                internal_error = true;
+                errors.push(ErrorDetail {
+                    schema: Some(url.clone()),
+                    ..error
+                });
+            }
+        }
+    }
+
+    // A document whose every error is suppressed validates.
+    let only_suppressed = set_errors(&mut result, errors, settings);
+    result.status = if internal_error {
+        Status::InternalError
+    } else if invalid && !only_suppressed {
+        Status::Invalid
+    } else {
+        Status::Valid
+    };
+    result.schemas = urls;
+    result
+}
+
+/// libxml2 reports a duplicate ID as a value that is not a valid xs:ID, like one
+/// that is not a name: tell the two apart by looking the ID up in the document.
+fn recode_duplicate_ids(doc: *mut XmlDoc, errors: &mut [ErrorDetail]) {
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"^(.*): '([^']*)' is not a valid value of the atomic type 'xs:ID'\.$")
+                .expect("failed to compile duplicate ID regex");
+    }
+
+    for error in errors {
+        let Some(caps) = RE.captures(&error.message) else {
+            continue;
+        };
+        let Ok(id) = CString::new(&caps[2]) else {
+            continue;
+        };
+        let attribute = unsafe { xmlGetID(doc, id.as_ptr()) };
+        if attribute.is_null() {
+            continue;
+        }
+        let line = unsafe { xmlGetLineNo((*attribute).parent) };
+        error.message = format!(
+            "{}: the ID '{}' is already defined on line {line}.",
+            &caps[1], &caps[2]
+        );
+        error.hint = hints::hint(&error.message);
+        error.error_code = codes::DUPLICATE_ID.id;
+    }
+}
+
+/// Check that every IDREF of a validated document refers to an ID it defines,
+/// adding an error for each that does not. Returns whether any did not.
+fn dangling_idrefs(doc: *mut XmlDoc, errors: &mut Vec<ErrorDetail>) -> bool {
+    collecting_errors(errors, || unsafe {
+        let ctxt = xmlNewValidCtxt();
+        let valid = xmlValidateDocumentFinal(ctxt, doc);
+        xmlFreeValidCtxt(ctxt);
+        valid == 0
+    })
+}
+
+/// Run the rules on a parsed document, adding the errors they find to its result.
+fn check_rules(doc: *mut XmlDoc, result: &mut FileResult, settings: &Settings) {
+    if settings.rules.is_empty() {
+        return;
+    }
+    let document = unsafe { rules::Document::new(doc) };
+    let mut errors = Vec::new();
+    for rule in &settings.rules {
+        match rule.check(&document, result) {
+            Ok(found) => errors.extend(found),
+            Err(e) => errors.push(ErrorDetail {
+                file: Some(result.path.clone()),
+                ..ErrorDetail::new(e, &codes::RULE_FAILED)
+            }),
+        }
+    }
+
+    let path = Path::new(&result.path);
+    let relative_path = path.strip_prefix(&settings.root).unwrap_or(path);
+    let before = errors.len();
+    errors.retain(|error| !settings.is_suppressed(relative_path, error));
+    result.suppressed += before - errors.len();
+    if errors
+        .iter()
+        .any(|error| error.error_code == codes::RULE_FAILED.id)
+    {
+        result.status = Status::InternalError;
+    } else if result.status == Status::Valid
+        && errors
+            .iter()
+            .any(|error| error.severity != Severity::Warning)
+    {
+        result.status = Status::Invalid;
+    }
+    if settings.deduplicate_errors {
+        errors = deduplicate(errors);
+    }
+    result.errors.extend(errors);
+}
+
+/// Give a result its errors, leaving out suppressed ones and folding duplicates if
+/// wanted. Returns whether errors were suppressed and only warnings are left.
+fn set_errors(result: &mut FileResult, mut errors: Vec<ErrorDetail>, settings: &Settings) -> bool {
+    let path = Path::new(&result.path);
+    let relative_path = path.strip_prefix(&settings.root).unwrap_or(path);
+    let before = errors.len();
+    errors.retain(|error| !settings.is_suppressed(relative_path, error));
+    result.suppressed = before - errors.len();
+    let only_suppressed = result.suppressed > 0
+        && errors
+            .iter()
+            .all(|error| error.severity == Severity::Warning);
+
+    if settings.deduplicate_errors {
+        errors = deduplicate(errors);
+    }
+    result.errors = errors;
+    only_suppressed
+}
+
+/// Return the local name and namespace URI of the document's root element.
+///
+/// # Safety
+///
+/// `doc` must be a valid document.
+unsafe fn root_element(doc: *mut XmlDoc) -> (Option<String>, Option<String>) {
+    let root = xmlDocGetRootElement(doc);
+    if root.is_null() {
+        return (None, None);
+    }
+    let namespace = if (*root).ns.is_null() {
+        None
+    } else {
+        from_c_string((*(*root).ns).href)
+    };
+    (from_c_string((*root).name), namespace)
+}
+
+/// A warning if the document's root element is not in the schema's target
+/// namespace, in which case the schema cannot declare it.
+fn namespace_mismatch(result: &FileResult, schema: XmlSchemaPtr) -> Option<ErrorDetail> {
+    result.root_element.as_ref()?;
+    let target_namespace = unsafe { from_c_string((*schema.0).target_namespace) };
+    if result.root_namespace == target_namespace {
+        return None;
+    }
+    let describe = |namespace: &Option<String>| match namespace {
+        Some(namespace) => format!("'{namespace}'"),
+        None => "absent".to_owned(),
+    };
+    Some(ErrorDetail {
+        file: Some(result.path.clone()),
+        severity: Severity::Warning,
+        ..ErrorDetail::new(
+            format!(
+                "The namespace of the root element is {}, but the schema's target namespace is {}.",
+                describe(&result.root_namespace),
+                describe(&target_namespace)
+            ),
+            &codes::NAMESPACE_MISMATCH,
+        )
+    })
+}
+
+/// Validate a parsed document against one schema, collecting its errors.
+///
+/// Returns libxml2's result: 0 if valid, positive if invalid, negative on internal error.
+fn validate_against(
+    doc: *mut XmlDoc,
+    schema: XmlSchemaPtr,
+    errors: &mut Vec<ErrorDetail>,
+) -> c_int {
+    unsafe {
+        // Have to create new validation context for each parse.
+        let schema_valid_ctxt = xmlSchemaNewValidCtxt(schema.0);
+
+        xmlSchemaSetValidStructuredErrors(
+            schema_valid_ctxt,
+            collect_error,
+            errors as *mut Vec<ErrorDetail> as *mut c_void,
+        );
+
+        let result = xmlSchemaValidateDoc(schema_valid_ctxt, doc);
+        xmlSchemaFreeValidCtxt(schema_valid_ctxt);
+        result
+    }
+}
+
+/// The file descriptor of `--progress-fd`.
+#[cfg(unix)]
+fn progress_fd(fd: i32) -> Box<dyn Write + Send> {
+    use std::fs::File;
+    use std::os::fd::FromRawFd;
+
+    // SAFETY: the caller handed us this descriptor for progress and nothing else uses it.
+    Box::new(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(not(unix))]
+fn progress_fd(_fd: i32) -> Box<dyn Write + Send> {
+    eprintln!("--progress-fd is only supported on Unix");
+    process::exit(1);
+}
+
+fn main() {
+    let started = Instant::now();
+    let specs = config::options(USAGE);
+    let command_line = expand_args_files(std::env::args().collect()).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(1);
+    });
+    // Only the commands checking files read configuration files and the
+    // environment.
+    let ignores_config = !config::applies_to(&command_line);
+    let config_file = config::find(&command_line)
+        .filter(|_| !ignores_config)
+        .map(|path| {
+            config::open(&path, &command_line).unwrap_or_else(|e| {
+                eprintln!("invalid configuration: {e}");
+                process::exit(1);
+            })
+        });
+    let environment = if ignores_config {
+        Default::default()
+    } else {
+        config::environment(&specs).unwrap_or_else(|e| {
+            eprintln!("invalid environment: {e}");
+            process::exit(1);
+        })
+    };
+    // The command line takes precedence over the environment, which takes
+    // precedence over the configuration file. The environment replaces settings
+    // rather than adding to them, so that e.g. a flag set to false there is off
+    // whatever the file says.
+    let mut settings = config_file
+        .as_ref()
+        .map(|config| config.settings.clone())
+        .unwrap_or_default();
+    settings.extend(environment.clone());
+    let argv = config::apply(command_line.clone(), &settings, &specs).unwrap_or_else(|e| {
+        eprintln!("invalid configuration: {e}");
+        process::exit(1);
+    });
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.argv(argv).deserialize())
+        .unwrap_or_else(|e| e.exit());
+    if args.cmd_merge_reports {
+        merge_reports(&args.arg_json_report, args.flag_output.as_deref()).unwrap_or_else(|e| {
+            eprintln!("failed to merge reports: {e}");
+            process::exit(1);
+        });
+        return;
+    }
+    if args.cmd_diff {
+        let worse = diff::run(
+            &args.arg_old_report,
+            &args.arg_new_report,
+            &mut io::stdout().lock(),
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("failed to compare reports: {e}");
+            process::exit(1);
+        });
+        if worse {
+            process::exit(1);
+        }
+        return;
+    }
+    if args.cmd_history {
+        history::report(&args.arg_db, args.flag_last, &mut io::stdout().lock()).unwrap_or_else(
+            |e| {
+                eprintln!("failed to read history {}: {e}", args.arg_db);
+                process::exit(1);
+            },
+        );
+        return;
+    }
+    if args.cmd_config && args.cmd_show {
+        print!(
+            "{}",
+            config::show(
+                &command_line[1..],
+                &environment,
+                config_file.as_ref(),
+                &specs
+            )
+        );
+        return;
+    }
+    if args.cmd_config && args.cmd_schema {
+        let schema = config::schema(&specs);
+        println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+        return;
+    }
+    if args.flag_generate_man {
+        man::write(&mut io::stdout().lock(), USAGE).unwrap();
+        return;
+    }
+    if args.cmd_config && args.cmd_init {
+        let format = args.flag_format.as_deref().unwrap_or("toml");
+        let text = config::init(&specs, format).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            process::exit(1);
+        });
+        let path = format!("validate-xml.{format}");
+        if Path::new(&path).exists() {
+            eprintln!("{path} already exists");
+            process::exit(1);
+        }
+        std::fs::write(&path, text).unwrap_or_else(|e| {
+            eprintln!("failed to write {path}: {e}");
+            process::exit(1);
+        });
+        eprintln!("Wrote {path}");
+        return;
+    }
+    let log_level = args.flag_log_level.parse().unwrap_or_else(|_| {
+        eprintln!("unknown log level {:?}", args.flag_log_level);
+        process::exit(1);
+    });
+    let log_file = args.flag_log_file.as_ref().map(|path| {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| {
+                eprintln!("failed to open log file {path}: {e}");
+                process::exit(1);
+            });
+        Box::new(file) as Box<dyn Write + Send>
+    });
+    logging::init(log_level, log_file);
+    network::set_host_delay(Duration::from_millis(args.flag_host_delay));
+    if let Some(path) = &args.flag_fetch_log {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| {
+                eprintln!("failed to open fetch log {path}: {e}");
+                process::exit(1);
+            });
+        audit::enable(file);
+    }
+
+    let schema_rules = SchemaRules::new(&args.flag_schema_rule).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(1);
+    });
+    let overrides = config_file
+        .iter()
+        .flat_map(|config| &config.overrides)
+        .map(Override::new)
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| {
+            eprintln!("invalid configuration: {e}");
+            process::exit(1);
+        });
+    let root_elements = args
+        .flag_root_element
+        .iter()
+        .map(|text| RootElement::parse(text))
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            process::exit(1);
+        });
+    let suppressions = args
+        .flag_suppress
+        .iter()
+        .map(|rule| Suppression::parse(rule))
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            process::exit(1);
+        });
+    let rules = args
+        .flag_rule
+        .iter()
+        .map(|rule| rules::parse(rule))
+        .chain(args.flag_plugin.iter().map(|path| rules::load_plugin(path)))
+        .chain(args.flag_script.iter().map(|path| rules::load_script(path)))
+        .map(|rule| rule.map(Arc::from))
+        .chain(
+            config_file
+                .iter()
+                .flat_map(|config| &config.assertions)
+                .map(|section| {
+                    rules::Assertion::new(section)
+                        .map(|assertion| Arc::new(assertion) as Arc<dyn rules::Rule>)
+                        .map_err(|e| format!("invalid configuration: {e}"))
+                }),
+        )
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            process::exit(1);
+        });
+    let parser_options = args
+        .flag_parser_options
+        .as_deref()
+        .map_or(Ok(0), parse_parser_options)
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            process::exit(1);
+        });
+    let settings = &Settings {
+        root: PathBuf::from(&args.arg_dir),
+        extension: args.flag_extension,
+        case_insensitive_extensions: args.flag_case_insensitive_extensions,
+        require_schema: args.flag_require_schema,
+        check_well_formed: args.flag_check_well_formed,
+        check_ids: args.flag_check_ids,
+        backend: args.flag_backend,
+        schema: args.flag_schema,
+        schema_rules,
+        // XML_PARSE_HUGE only on request, since it also lifts libxml2's entity
+        // amplification limits.
+        parse_options: if args.flag_allow_external_entities {
+            XML_PARSE_NOENT | XML_PARSE_DTDLOAD
+        } else {
+            XML_PARSE_NONET
+        } | parser_options,
+        deduplicate_errors: !args.flag_keep_duplicate_errors,
+        shard: args.flag_shard.as_deref().map(|shard| {
+            Shard::parse(shard).unwrap_or_else(|e| {
+                eprintln!("{e}");
+                process::exit(1);
+            })
+        }),
+        sample: args.flag_sample.map(|count| {
+            let seed = args.flag_seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos() as u64
+            });
+            tracing::info!("Checking a sample of {count} files with --seed={seed}");
+            Sample { count, seed }
+        }),
+        overrides,
+        root_elements,
+        suppressions,
+        rules,
+        hosts: HostPolicy::new(
+            args.flag_allowed_hosts.as_deref(),
+            args.flag_blocked_hosts.as_deref(),
+            // A server validates whatever documents it is sent.
+            args.flag_block_private_addresses
+                || args.cmd_serve && !args.flag_allow_private_addresses,
+            args.flag_no_remote_schemas,
+        ),
+    };
+    network::set_policy(settings.hosts.clone());
+    let format = match &args.flag_format {
+        Some(format) => format.parse().unwrap_or_else(|e| {
+            eprintln!("{e}");
+            process::exit(1);
+        }),
+        None => Format::Human,
+    };
+
+    if args.flag_history.is_some() && !cfg!(feature = "history") {
+        eprintln!("--history needs validate-xml to be built with the history feature");
+        process::exit(1);
+    }
+    if settings.backend == Backend::Native {
+        if !cfg!(feature = "native") {
+            eprintln!("--backend=native needs validate-xml to be built with the native feature");
+            process::exit(1);
+        }
+        if settings.check_ids || !settings.rules.is_empty() {
+            eprintln!("--backend=native does not support --check-ids, rules, plugins, scripts or assertions");
+            process::exit(1);
+        }
+    }
+
+    // Validation runs on rayon's global pool, by default with one thread per core.
+    // libxml2's error handlers are per thread, so each worker sets its own.
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.flag_jobs.unwrap_or(0))
+        .start_handler(|_| log_errors())
+        .build_global()
+        .unwrap();
+
+    match (args.flag_progress, args.flag_progress_format) {
+        (_, Some(ProgressFormat::Jsonl)) => progress::enable_events(match args.flag_progress_fd {
+            Some(fd) => progress_fd(fd),
+            None => Box::new(io::stderr()),
+        }),
+        (true, _) | (_, Some(ProgressFormat::Bars)) => progress::enable_bars(),
+        (false, None) => {}
+    }
+
+    unsafe {
+        xmlInitParser();
+        xmlInitGlobals();
+    }
+    install_entity_loader();
+    log_errors();
+
+    if let Some(url) = args.flag_dump_schema {
+        dump_schema(url);
+        return;
+    }
+
+    if args.flag_list_schemas {
+        list_schemas(settings).unwrap_or_else(|e| {
+            eprintln!("failed to list schemas: {e}");
+            process::exit(1);
+        });
+        return;
+    }
+
+    if args.flag_dry_run {
+        dry_run(settings).unwrap_or_else(|e| {
+            eprintln!("failed to print the plan: {e}");
+            process::exit(1);
+        });
+        return;
+    }
+
+    if args.cmd_lsp {
+        lsp::run(settings).unwrap_or_else(|e| {
+            eprintln!("language server failed: {e}");
+            process::exit(1);
+        });
+        return;
+    }
+
+    if args.cmd_serve {
+        serve::run(settings, &args.flag_listen, !args.arg_dir.is_empty()).unwrap_or_else(|e| {
+            eprintln!("failed to serve on {}: {e}", args.flag_listen);
+            process::exit(1);
+        });
+        return;
+    }
+
+    if args.cmd_bench {
+        bench::run(settings, args.flag_runs).unwrap();
+        return;
+    }
+
+    let fail_on = match &args.flag_fail_on {
+        Some(list) => FailOn::parse(list).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            process::exit(1);
+        }),
+        None => FailOn::default_for(settings.require_schema),
+    };
+    let targets = if args.flag_all_targets {
+        if args.flag_cache.is_some()
+            || args.flag_history.is_some()
+            || args.flag_watch
+            || args.flag_staged
+        {
+            eprintln!(
+                "--all-targets cannot be combined with --cache, --history, --watch or --staged"
+            );
+            process::exit(1);
+        }
+        let Some(config) = config_file
+            .as_ref()
+            .filter(|config| !config.targets.is_empty())
+        else {
+            eprintln!("--all-targets needs [targets.<name>] sections in the configuration file");
+            process::exit(1);
+        };
+        config
+            .targets
+            .iter()
+            .map(|(name, section)| Target::new(name, section, &config.path, settings))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|e| {
+                eprintln!("invalid configuration: {e}");
+                process::exit(1);
+            })
+    } else {
+        Vec::new()
+    };
+    let failed = &AtomicBool::new(false);
+    let mut output = OutputWriter::new(settings.require_schema, args.flag_verbose, args.flag_color);
+    output.set_quiet(args.flag_quiet);
+    output.set_context(args.flag_context);
+    if let Some(depth) = args.flag_by_directory {
+        output.summarize_directories(&settings.root, depth);
+    }
+    let parse_template = |text: &Option<String>, fields| {
+        text.as_deref().map(|text| {
+            Template::parse(text, fields).unwrap_or_else(|e| {
+                eprintln!("{e}");
+                process::exit(1);
+            })
+        })
+    };
+    let templates = Templates {
+        file: parse_template(&args.flag_format_template, output::FILE_FIELDS),
+        summary: parse_template(&args.flag_summary_template, output::SUMMARY_FIELDS),
+    };
+    let has_templates = templates.file.is_some() || templates.summary.is_some();
+    output.set_templates(templates);
+    // A template implies the template format for the main report.
+    let format = if has_templates {
+        Format::Template
+    } else {
+        format
+    };
+    let mut sinks = vec![(format, args.flag_output.clone())];
+    for report in &args.flag_report {
+        let (format, path) = report
+            .split_once(':')
+            .ok_or_else(|| format!("report {report:?} is not of the form FORMAT:PATH"))
+            .and_then(|(format, path)| Ok((format.parse()?, Some(path.to_owned()))))
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                process::exit(1);
+            });
+        sinks.push((format, path));
+    }
+    if !has_templates && sinks.iter().any(|(format, _)| *format == Format::Template) {
+        eprintln!("the template format needs --format-template or --summary-template");
+        process::exit(1);
+    }
+    let baseline = match &args.flag_baseline {
+        Some(path) => Baseline::load(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("failed to read baseline {path}: {e}");
+            process::exit(1);
+        }),
+        None => Baseline::default(),
+    };
+    let new_baseline = Mutex::new(Baseline::default());
+    // Results also depend on these settings, besides a file and its schemas.
+    let cache = args
+        .flag_cache
+        .as_ref()
+        .filter(|_| !args.cmd__)
+        .map(|path| {
+            let description = format!(
+                "{:?}",
+                (
+                    env!("CARGO_PKG_VERSION"),
+                    settings.parse_options,
+                    settings.check_well_formed,
+                    settings.check_ids,
+                    settings.backend,
+                    settings.deduplicate_errors,
+                    &args.flag_suppress,
+                    // Rules, whose plugins and scripts are only known by path.
+                    (
+                        &args.flag_rule,
+                        &args.flag_plugin,
+                        &args.flag_script,
+                        config_file.as_ref().map(|config| &config.assertions),
+                    ),
+                    &args.flag_allowed_hosts,
+                    &args.flag_blocked_hosts,
+                    args.flag_block_private_addresses,
+                    args.flag_no_remote_schemas,
+                )
+            );
+            let cache = Cache::load(Path::new(path), cache::settings_digest(&description))
+                .unwrap_or_else(|e| {
+                    eprintln!("failed to read cache {path}: {e}");
+                    process::exit(1);
+                });
+            Mutex::new(cache)
+        });
+    let metrics = Mutex::new(Metrics::default());
+    let history = Mutex::new(History::default());
+
+    if args.flag_watch && (args.cmd__ || !format.is_streaming()) {
+        eprintln!("--watch needs a directory and a report format written as files are checked");
+        process::exit(1);
+    }
+    // Started before the first run, so that files changed during it are checked again.
+    let watch = args.flag_watch.then(|| {
+        watch::start(settings).unwrap_or_else(|e| {
+            eprintln!("cannot watch {}: {e}", settings.root.display());
+            process::exit(1);
+        })
+    });
+
+    // Created last, so that the checks above exit before any report file exists.
+    for (format, path) in sinks {
+        if let Err(e) = output.add_sink(format, path.as_deref().map(Path::new)) {
+            eprintln!("failed to create report file: {e}");
+            output.discard();
+            process::exit(1);
+        }
+    }
+    let output = Mutex::new(output);
+
+    let report = |settings: &Settings, fail_on: FailOn, mut result: FileResult| {
+        let relative_path = settings.relative_path(&result);
+        let fail_on = settings.fail_on_for(&relative_path).unwrap_or(fail_on);
+        // The baseline records the failures that fail the run.
+        let breaks = fail_on.breaks(&result);
+        if breaks {
+            result.baselined = baseline.contains(&relative_path, &result);
+            new_baseline.lock().unwrap().record(&relative_path, &result);
+        }
+        let fails = breaks && !result.baselined;
+        if fails {
+            failed.store(true, Ordering::Relaxed);
+        }
+        metrics.lock().unwrap().record(&result, fails);
+        history.lock().unwrap().record(&relative_path, &result);
+        if let Some(max) = args.flag_max_error_details {
+            result.omit_errors_after(max);
+        }
+        let mut output = output.lock().unwrap();
+        if let Err(e) = output.add(result, fails) {
+            report_failed(&mut output, e);
+        }
+    };
+
+    if args.cmd__ {
+        report(settings, fail_on, timed(|| validate_stdin(settings)));
+    } else {
+        // Without --all-targets, the directory given on the command line is the one
+        // target.
+        let runs: Vec<(Option<&str>, &Settings, FailOn)> = if targets.is_empty() {
+            vec![(None, settings, fail_on)]
+        } else {
+            targets
+                .iter()
+                .map(|target| {
+                    let fail_on = target.fail_on.unwrap_or(fail_on);
+                    (Some(target.name.as_str()), &target.settings, fail_on)
+                })
+                .collect()
+        };
+        for (name, settings, fail_on) in runs {
+            if let Some(name) = name {
+                tracing::info!("Checking target {name} in {}", settings.root.display());
+            }
+            thread::scope(|scope| {
+                let (found, discovered) = mpsc::channel();
+                // A cache may not need the schemas at all.
+                if cache.is_none() {
+                    scope.spawn(|| warm_up(discovered, settings));
+                }
+                let discovery = progress::spinner("discovery", "Discovering files".to_owned());
+                let paths = if args.flag_staged {
+                    let paths = staged(settings).unwrap_or_else(|e| {
+                        eprintln!("failed to list staged files: {e}");
+                        output.lock().unwrap().discard();
+                        process::exit(1);
+                    });
+                    for path in &paths {
+                        let _ = found.send(path.clone());
+                    }
+                    paths
+                } else {
+                    discover_each(settings, |path| {
+                        let _ = found.send(path.to_owned());
+                    })
+                };
+                drop(found);
+                discovery.finish(None);
+
+                let validation = progress::files(paths.len());
+                let check = |path| {
+                    let result = timed(|| match &cache {
+                        Some(cache) => validate_cached(path, settings, cache),
+                        None => validate(path, settings),
+                    });
+                    validation.inc(&result.path);
+                    report(settings, fail_on, result);
+                };
+                if args.flag_largest_first {
+                    let mut paths = paths;
+                    paths.sort_by_cached_key(|path| {
+                        Reverse(std::fs::metadata(path).map_or(0, |metadata| metadata.len()))
+                    });
+                    // Bridged rather than split, so that threads take files in this order.
+                    paths.into_iter().par_bridge().for_each(check);
+                } else {
+                    paths.into_par_iter().for_each(check);
+                }
+                validation.finish(None);
+            });
+        }
+    }
+
+    output.into_inner().unwrap().finish().unwrap_or_else(|e| {
+        eprintln!("failed to write the report: {e}");
+        process::exit(1);
+    });
+
+    if let Some(path) = &args.flag_write_baseline {
+        new_baseline
+            .into_inner()
+            .unwrap()
+            .save(Path::new(path))
+            .unwrap_or_else(|e| {
+                eprintln!("failed to write baseline {path}: {e}");
+                process::exit(1);
+            });
+    }
+
+    if let (Some(path), Some(cache)) = (&args.flag_cache, cache) {
+        cache
+            .into_inner()
+            .unwrap()
+            .save(Path::new(path), &settings.root)
+            .unwrap_or_else(|e| {
+                eprintln!("failed to write cache {path}: {e}");
+                process::exit(1);
+            });
+    }
+
+    if let Some(path) = &args.flag_history {
+        history
+            .into_inner()
+            .unwrap()
+            .save(path, &settings.root, started.elapsed())
+            .unwrap_or_else(|e| {
+                eprintln!("failed to write history {path}: {e}");
+                process::exit(1);
+            });
+    }
+
+    if let Some(path) = &args.flag_metrics_file {
+        metrics
+            .into_inner()
+            .unwrap()
+            .save(Path::new(path), started.elapsed())
+            .unwrap_or_else(|e| {
+                eprintln!("failed to write metrics {path}: {e}");
+                process::exit(1);
+            });
+    }
+
+    if let Some(watch) = watch {
+        // Later rounds report the changed files in the main format only.
+        watch::run(settings, watch, |paths| {
+            let mut output = OutputWriter::new(settings.require_schema, false, args.flag_color);
+            output.set_templates(Templates {
+                file: parse_template(&args.flag_format_template, output::FILE_FIELDS),
+                summary: parse_template(&args.flag_summary_template, output::SUMMARY_FIELDS),
+            });
+            output.add_sink(format, None).unwrap();
+            let output = Mutex::new(output);
+            paths.into_par_iter().for_each(|path| {
+                let mut result = timed(|| validate(path, settings));
+                let relative_path = settings.relative_path(&result);
+                let breaks = settings
+                    .fail_on_for(&relative_path)
+                    .unwrap_or(fail_on)
+                    .breaks(&result);
+                result.baselined = breaks && baseline.contains(&relative_path, &result);
+                let fails = breaks && !result.baselined;
+                let mut output = output.lock().unwrap();
+                if let Err(e) = output.add(result, fails) {
+                    report_failed(&mut output, e);
+                }
+            });
+            output.into_inner().unwrap().finish().unwrap_or_else(|e| {
+                eprintln!("failed to write the report: {e}");
+                process::exit(1);
+            });
+        });
+    }
+
+    if failed.load(Ordering::Relaxed) && !args.flag_no_fail {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Once;
+
+    /// Initialize libxml2 as `main` does, once for all tests.
+    pub fn init() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            unsafe { xmlInitParser() };
+            install_entity_loader();
+        });
+        log_errors();
+    }
+
+    /// The settings of a run with default options on `root`.
+    pub fn settings(root: &Path) -> Settings {
+        Settings {
+            root: root.to_owned(),
+            extension: "xml".to_owned(),
+            case_insensitive_extensions: false,
+            require_schema: false,
+            check_well_formed: false,
+            check_ids: false,
+            backend: Backend::Libxml2,
+            schema: None,
+            schema_rules: SchemaRules::new(&[]).unwrap(),
+            parse_options: XML_PARSE_NONET,
+            deduplicate_errors: true,
+            shard: None,
+            sample: None,
+            overrides: Vec::new(),
+            root_elements: Vec::new(),
+            suppressions: Vec::new(),
+            rules: Vec::new(),
+            hosts: HostPolicy::default(),
+        }
+    }
+
+    #[test]
+    fn imports_are_downloaded_by_the_host_policy() {
+        init();
+        network::set_policy(HostPolicy::new(None, Some("blocked.invalid"), true, false));
+        let dir = tempfile::tempdir().unwrap();
+        // The first error of a schema importing `location`.
+        let refusal = |name: &str, location: &str| {
+            let schema = dir.path().join(name);
+            let text = format!(
+                r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema" targetNamespace="urn:main">
+                    <xs:import namespace="urn:note" schemaLocation="{location}"/>
+                    <xs:element name="main" type="xs:string"/>
+                </xs:schema>"#
+            );
+            std::fs::write(&schema, text).unwrap();
+            let loaded = get_schema(schema.to_str().unwrap().to_owned());
+            assert!(loaded.schema.0.is_null());
+            let error = loaded.errors[0].clone();
+            assert_eq!(error.error_code, codes::BLOCKED_HOST.id);
+            assert_eq!(error.file.as_deref(), Some(location));
+            error.message
+        };
+
+        assert!(refusal("blocked.xsd", "http://blocked.invalid/note.xsd")
+            .contains("blocked.invalid is blocked by --blocked-hosts"));
+        assert!(refusal("private.xsd", "http://127.0.0.1/note.xsd")
+            .contains("127.0.0.1 is a private address"));
+    }
+
+    #[test]
+    fn hints_are_relative_to_their_document() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a", "b"] {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+            std::fs::write(dir.path().join(name).join("schema.xsd"), "").unwrap();
+        }
+        let document = br#"<note xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
+            xsi:schemaLocation="urn:note schema.xsd urn:list https://example.org/list.xsd"/>"#;
+
+        for name in ["a", "b"] {
+            let path = dir.path().join(name).join("note.xml");
+            let urls = hinted_schema_urls(document, &path);
+            assert_eq!(urls[1], "https://example.org/list.xsd");
+            let expected = dir.path().join(name).join("schema.xsd").canonicalize();
+            assert_eq!(
+                PathBuf::from(schema_key(urls[0].clone())),
+                expected.unwrap()
+            );
+        }
+        assert_eq!(
+            hinted_schema_urls(document, Path::new("note.xml"))[0],
+            "schema.xsd"
+        );
+    }
+
+    #[test]
+    fn includes_are_relative_to_their_schema() {
+        init();
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("types")).unwrap();
+        std::fs::write(
+            dir.path().join("note.xsd"),
+            r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema" xmlns="urn:note" targetNamespace="urn:note">
+                <xs:include schemaLocation="types/code.xsd"/>
+                <xs:element name="note" type="code"/>
+            </xs:schema>"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("types").join("code.xsd"),
+            r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema" targetNamespace="urn:note">
+                <xs:include schemaLocation="../string.xsd"/>
+            </xs:schema>"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("string.xsd"),
+            r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema" targetNamespace="urn:note">
+                <xs:simpleType name="code"><xs:restriction base="xs:string"/></xs:simpleType>
+            </xs:schema>"#,
+        )
+        .unwrap();
+
+        let loaded = get_schema(dir.path().join("note.xsd").to_str().unwrap().to_owned());
+        assert!(loaded.errors.is_empty(), "{}", loaded.errors[0]);
+        assert!(!loaded.schema.0.is_null());
+    }
+
+    #[test]
+    fn local_paths() {
+        assert!(!is_remote("schemas/note.xsd"));
+        assert!(is_remote("HTTPS://example.org/note.xsd"));
+        assert_eq!(
+            local_path("schemas/note.xsd"),
+            Path::new("schemas/note.xsd")
+        );
+        if cfg!(unix) {
+            assert_eq!(
+                local_path(r"schemas\note.xsd"),
+                Path::new("schemas/note.xsd")
+            );
+            assert_eq!(
+                local_path("file:///schemas/note%20v2.xsd"),
+                Path::new("/schemas/note v2.xsd")
+            );
+        }
+    }
+}
+
+/// Windows paths, which only Windows takes for absolute paths, drive letters and
+/// network shares.
+#[cfg(all(test, windows))]
+mod windows_tests {
+    use super::*;
+
+    #[test]
+    fn drive_letters() {
+        for url in [
+            r"C:\schemas\note.xsd",
+            "C:/schemas/note.xsd",
+            r"C:\schemas/note.xsd",
+        ] {
+            assert!(!is_remote(url), "{url}");
+            let path = local_path(url);
+            assert!(path.is_absolute(), "{url}");
+            assert_eq!(path, Path::new(r"C:\schemas\note.xsd"), "{url}");
+        }
+        // Relative to the current directory of drive C:.
+        assert!(local_path("C:note.xsd").is_relative());
+    }
+
+    #[test]
+    fn unc_paths() {
+        let path = local_path(r"\\server\share\schemas\note.xsd");
+        assert!(path.is_absolute());
+        assert_eq!(path, Path::new(r"\\server\share\schemas\note.xsd"));
+        assert_eq!(
+            local_path("file://server/share/schemas/note.xsd"),
+            Path::new(r"\\server\share\schemas\note.xsd")
+        );
+    }
+
+    #[test]
+    fn file_urls() {
+        for url in ["file:///C:/schemas/note.xsd", "FILE:///C:/schemas/note.xsd"] {
+            assert_eq!(local_path(url), Path::new(r"C:\schemas\note.xsd"), "{url}");
+        }
+        assert_eq!(
+            local_path("file:///C:/schemas/note%20v2.xsd"),
+            Path::new(r"C:\schemas\note v2.xsd")
+        );
+    }
+
+    #[test]
+    fn hints_with_drive_letters_stay_absolute() {
+        let document = br#"<note xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
+            xsi:schemaLocation="urn:a C:\schemas\a.xsd urn:b file:///D:/b.xsd urn:c c.xsd"/>"#;
+        let urls = hinted_schema_urls(document, Path::new(r"E:\data\note.xml"));
+        assert_eq!(urls[0], r"C:\schemas\a.xsd");
+        assert_eq!(urls[1], "file:///D:/b.xsd");
+        assert_eq!(urls[2], r"E:\data\c.xsd");
+    }
 }
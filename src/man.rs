@@ -0,0 +1,57 @@
+//! A man page generated from the docopt usage text, for packagers.
+
+use std::io::{self, Write};
+
+/// Escape text for roff: backslashes, hyphens and leading control characters.
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!("\\&{text}")
+    } else {
+        text
+    }
+}
+
+/// Write a man page in roff format, built from the sections of `usage`.
+pub fn write(out: &mut dyn Write, usage: &str) -> io::Result<()> {
+    let name = env!("CARGO_PKG_NAME");
+    let mut lines = usage.trim().lines();
+    let description = lines.next().unwrap_or_default();
+
+    writeln!(
+        out,
+        ".TH {} 1 \"\" \"{name} {}\"",
+        name.to_uppercase(),
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(out, ".SH NAME")?;
+    writeln!(out, "{} \\- {}", escape(name), escape(description))?;
+
+    let mut in_options = false;
+    for line in lines {
+        let trimmed = line.trim();
+        if trimmed == "Usage:" {
+            writeln!(out, ".SH SYNOPSIS")?;
+        } else if trimmed == "Options:" {
+            writeln!(out, ".SH OPTIONS")?;
+            in_options = true;
+        } else if trimmed.is_empty() {
+            continue;
+        } else if !in_options {
+            writeln!(out, ".br")?;
+            writeln!(out, "{}", escape(trimmed))?;
+        } else if trimmed.starts_with('-') {
+            // An option, then its description after a gap of two or more spaces.
+            let (flags, text) = trimmed.split_once("  ").unwrap_or((trimmed, ""));
+            writeln!(out, ".TP")?;
+            writeln!(out, "\\fB{}\\fR", escape(flags))?;
+            if !text.trim().is_empty() {
+                writeln!(out, "{}", escape(text.trim()))?;
+            }
+        } else {
+            // The continuation of an option's description.
+            writeln!(out, "{}", escape(trimmed))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,137 @@
+//! Prometheus metrics of a run, for monitoring recurring validation jobs.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use cached::Cached;
+
+use crate::{schema_loads, FileResult, SchemaSource, LOAD_SCHEMA};
+
+/// Counts and durations gathered as files are checked.
+#[derive(Default)]
+pub struct Metrics {
+    /// Checked files by status name.
+    files: BTreeMap<&'static str, u64>,
+    /// Files that fail the run.
+    failures: u64,
+    /// Total time spent checking files, over all threads.
+    validation_seconds: f64,
+}
+
+impl Metrics {
+    /// Count a checked file, which fails the run if `failed`.
+    pub fn record(&mut self, result: &FileResult, failed: bool) {
+        *self.files.entry(result.status.name()).or_default() += 1;
+        if failed {
+            self.failures += 1;
+        }
+        self.validation_seconds += result.duration_ms / 1000.0;
+    }
+
+    /// Write the metrics in the node exporter's textfile collector format.
+    ///
+    /// The file is written under a temporary name and renamed into place, so the
+    /// collector never reads a partial file.
+    pub fn save(&self, path: &Path, run: Duration) -> io::Result<()> {
+        let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+        temp_name.push(format!(".{}.tmp", process::id()));
+        let temp_path = path.with_file_name(temp_name);
+        fs::write(&temp_path, self.render(run))?;
+        fs::rename(temp_path, path)
+    }
+
+    /// The metrics in Prometheus' text format, given how long the run or server has
+    /// been going.
+    pub fn render(&self, run: Duration) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, help: &str, samples: &[(String, f64)]| {
+            let _ = writeln!(text, "# HELP validate_xml_{name} {help}");
+            let _ = writeln!(text, "# TYPE validate_xml_{name} gauge");
+            for (labels, value) in samples {
+                let _ = writeln!(text, "validate_xml_{name}{labels} {value}");
+            }
+        };
+
+        let files: Vec<(String, f64)> = self
+            .files
+            .iter()
+            .map(|(status, count)| (format!("{{status=\"{status}\"}}"), *count as f64))
+            .collect();
+        metric("files", "Files checked, by status.", &files);
+        metric(
+            "failures",
+            "Files that failed.",
+            &[(String::new(), self.failures as f64)],
+        );
+
+        let (hits, misses) = {
+            let cache = LOAD_SCHEMA.lock().unwrap();
+            (cache.cache_hits(), cache.cache_misses())
+        };
+        metric(
+            "schema_cache_hits",
+            "Schema lookups served from the in-memory cache.",
+            &[(String::new(), hits.unwrap_or_default() as f64)],
+        );
+        metric(
+            "schema_cache_misses",
+            "Schema lookups that had to load the schema.",
+            &[(String::new(), misses.unwrap_or_default() as f64)],
+        );
+        let loads = schema_loads();
+        metric(
+            "schema_fetch_seconds",
+            "Time spent downloading or reading schemas.",
+            &[(
+                String::new(),
+                loads.iter().map(|load| load.fetch_ms).sum::<f64>() / 1000.0,
+            )],
+        );
+        let downloads: Vec<(String, f64)> = loads
+            .iter()
+            .filter(|load| matches!(load.source, SchemaSource::Remote))
+            .map(|load| {
+                let url = load.url.replace('\\', "\\\\").replace('"', "\\\"");
+                (format!("{{url=\"{url}\"}}"), load.bytes as f64)
+            })
+            .collect();
+        metric(
+            "schema_download_bytes",
+            "Size of each downloaded schema.",
+            &downloads,
+        );
+        metric(
+            "schema_parse_seconds",
+            "Time spent parsing schemas.",
+            &[(
+                String::new(),
+                loads.iter().map(|load| load.parse_ms).sum::<f64>() / 1000.0,
+            )],
+        );
+
+        metric(
+            "validation_seconds",
+            "Time spent checking files, summed over all threads.",
+            &[(String::new(), self.validation_seconds)],
+        );
+        metric(
+            "run_seconds",
+            "Wall-clock duration of the run, or uptime of the server.",
+            &[(String::new(), run.as_secs_f64())],
+        );
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        metric(
+            "timestamp_seconds",
+            "When the metrics were taken, in seconds since the Unix epoch.",
+            &[(String::new(), now.as_secs_f64())],
+        );
+        text
+    }
+}
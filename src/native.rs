@@ -0,0 +1,2061 @@
+//! An experimental validator for a subset of XML Schema written in Rust, used
+//! instead of libxml2 with `--backend=native` when validate-xml is built with the
+//! `native` feature.
+//!
+//! It covers element and attribute declarations, named and anonymous types,
+//! sequences, choices, `all` groups, wildcards, model and attribute groups,
+//! derivation by extension and restriction, simple content, and simple types that
+//! restrict, list or unite the built-in types with the usual facets, across
+//! included and imported schemas. Identity constraints are not checked, and
+//! schemas with substitution groups, abstract elements or `xs:redefine` are not
+//! loaded. Documents are parsed with roxmltree, which reads no external DTD.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use roxmltree::{Document, Node, ParsingOptions};
+
+use crate::{as_utf8, codes, is_remote, local_path, ErrorDetail, Severity};
+
+const XS: &str = "http://www.w3.org/2001/XMLSchema";
+const XSI: &str = "http://www.w3.org/2001/XMLSchema-instance";
+const XML: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// The built-in simple types, besides `anySimpleType`.
+const BUILTINS: &[&str] = &[
+    "string",
+    "normalizedString",
+    "token",
+    "language",
+    "Name",
+    "NCName",
+    "ID",
+    "IDREF",
+    "IDREFS",
+    "ENTITY",
+    "ENTITIES",
+    "NMTOKEN",
+    "NMTOKENS",
+    "QName",
+    "NOTATION",
+    "anyURI",
+    "boolean",
+    "decimal",
+    "integer",
+    "nonPositiveInteger",
+    "negativeInteger",
+    "long",
+    "int",
+    "short",
+    "byte",
+    "nonNegativeInteger",
+    "unsignedLong",
+    "unsignedInt",
+    "unsignedShort",
+    "unsignedByte",
+    "positiveInteger",
+    "float",
+    "double",
+    "duration",
+    "dateTime",
+    "date",
+    "time",
+    "gYear",
+    "gYearMonth",
+    "gMonth",
+    "gMonthDay",
+    "gDay",
+    "hexBinary",
+    "base64Binary",
+];
+
+/// A name in a namespace, shown as libxml2 does, e.g. `{urn:note}to`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct QName {
+    namespace: Option<String>,
+    name: String,
+}
+
+impl QName {
+    fn new(namespace: Option<&str>, name: &str) -> QName {
+        QName {
+            namespace: namespace.map(str::to_owned),
+            name: name.to_owned(),
+        }
+    }
+
+    fn of(node: Node) -> QName {
+        QName::new(node.tag_name().namespace(), node.tag_name().name())
+    }
+
+    fn xs(name: &str) -> QName {
+        QName::new(Some(XS), name)
+    }
+
+    /// The built-in type it names, if it names one.
+    fn builtin(&self) -> Option<&str> {
+        (self.namespace.as_deref() == Some(XS)).then_some(self.name.as_str())
+    }
+}
+
+impl fmt::Display for QName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.namespace {
+            Some(namespace) => write!(f, "{{{namespace}}}{}", self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+/// A schema and the schemas it includes and imports.
+pub struct Schema {
+    elements: HashMap<QName, Element>,
+    attributes: HashMap<QName, Attribute>,
+    types: HashMap<QName, Type>,
+    groups: HashMap<QName, Particle>,
+    attribute_groups: HashMap<QName, AttributeGroup>,
+    /// A reference to each built-in and defined type, for `xsi:type`.
+    type_refs: HashMap<QName, TypeRef>,
+}
+
+struct Element {
+    name: QName,
+    type_: TypeRef,
+    nillable: bool,
+    fixed: Option<String>,
+}
+
+struct Attribute {
+    name: QName,
+    type_: TypeRef,
+    fixed: Option<String>,
+}
+
+enum TypeRef {
+    Named(QName),
+    Anonymous(Box<Type>),
+}
+
+enum Type {
+    Simple(SimpleType),
+    Complex(ComplexType),
+}
+
+struct SimpleType {
+    variety: Variety,
+    facets: Facets,
+}
+
+enum Variety {
+    Restriction(TypeRef),
+    List(TypeRef),
+    Union(Vec<TypeRef>),
+}
+
+#[derive(Default)]
+struct Facets {
+    enumeration: Vec<String>,
+    /// The patterns of one derivation step, any of which may match, and their
+    /// source.
+    pattern: Option<(Regex, String)>,
+    length: Option<usize>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    min_inclusive: Option<String>,
+    max_inclusive: Option<String>,
+    min_exclusive: Option<String>,
+    max_exclusive: Option<String>,
+    total_digits: Option<usize>,
+    fraction_digits: Option<usize>,
+}
+
+struct ComplexType {
+    /// The type it is derived from, if any.
+    base: Option<(Derivation, QName)>,
+    content: Content,
+    attributes: AttributeGroup,
+    mixed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Derivation {
+    Extension,
+    Restriction,
+}
+
+enum Content {
+    /// Text of a simple type.
+    Simple(TypeRef),
+    /// Child elements, following the base type's when extending it.
+    Elements(Vec<Particle>),
+}
+
+#[derive(Default)]
+struct AttributeGroup {
+    uses: Vec<AttributeUse>,
+    /// Whether any other attribute is allowed, by `xs:anyAttribute`.
+    any: bool,
+}
+
+enum AttributeUse {
+    Declared(Attribute, bool),
+    /// A reference to a global attribute, and whether it is required.
+    Ref(QName, bool),
+    Group(QName),
+}
+
+struct Particle {
+    term: Term,
+    min: usize,
+    /// None if unbounded.
+    max: Option<usize>,
+}
+
+enum Term {
+    Element(Box<Element>),
+    ElementRef(QName),
+    Sequence(Vec<Particle>),
+    Choice(Vec<Particle>),
+    All(Vec<Particle>),
+    Any(Wildcard),
+    Group(QName),
+}
+
+struct Wildcard {
+    namespaces: Namespaces,
+    process: Process,
+}
+
+enum Namespaces {
+    Any,
+    /// Any namespace but the target namespace, and not no namespace.
+    Other(Option<String>),
+    List(Vec<Option<String>>),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Process {
+    Skip,
+    Lax,
+    Strict,
+}
+
+impl Wildcard {
+    fn allows(&self, namespace: Option<&str>) -> bool {
+        match &self.namespaces {
+            Namespaces::Any => true,
+            Namespaces::Other(target) => namespace.is_some() && namespace != target.as_deref(),
+            Namespaces::List(namespaces) => namespaces.iter().any(|n| n.as_deref() == namespace),
+        }
+    }
+}
+
+fn parsing_options<'input>() -> ParsingOptions<'input> {
+    ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    }
+}
+
+/// Parse a document, allowing an internal DTD subset.
+pub fn parse_document(text: &str) -> Result<Document<'_>, roxmltree::Error> {
+    Document::parse_with_options(text, parsing_options())
+}
+
+/// The error of a document that is not well-formed.
+pub fn not_well_formed(e: roxmltree::Error) -> ErrorDetail {
+    let position = e.pos();
+    ErrorDetail {
+        line: position.row as i32,
+        column: position.col as i32,
+        severity: Severity::Fatal,
+        ..ErrorDetail::new(e.to_string(), &codes::NOT_WELL_FORMED)
+    }
+}
+
+/// The location of an included or imported schema, relative to the schema
+/// referring to it.
+fn resolve(base: &str, location: &str) -> Result<String, String> {
+    if is_remote(location) {
+        return Ok(location.to_owned());
+    }
+    if is_remote(base) {
+        return url::Url::parse(base)
+            .and_then(|base| base.join(location))
+            .map(String::from)
+            .map_err(|e| format!("cannot resolve {location} against {base}: {e}"));
+    }
+    let path = local_path(location);
+    let path = match local_path(base).parent() {
+        Some(directory) if path.is_relative() => directory.join(path),
+        _ => path,
+    };
+    path.into_os_string()
+        .into_string()
+        .map_err(|_| format!("{location} is not a UTF-8 path"))
+}
+
+fn required<'a>(node: Node<'a, '_>, attribute: &str) -> Result<&'a str, String> {
+    node.attribute(attribute).ok_or_else(|| {
+        format!(
+            "xs:{} without the {attribute} attribute",
+            node.tag_name().name()
+        )
+    })
+}
+
+/// The name of a schema element, which must be in the XML Schema namespace.
+fn xs_name<'a>(node: Node<'a, '_>) -> Result<&'a str, String> {
+    match node.tag_name().namespace() {
+        Some(XS) => Ok(node.tag_name().name()),
+        _ => Err(format!(
+            "unexpected element {} in a schema",
+            QName::of(node)
+        )),
+    }
+}
+
+fn unsupported(node: Node) -> String {
+    format!(
+        "xs:{} is not supported by the native backend",
+        node.tag_name().name()
+    )
+}
+
+/// Resolve a QName written in a schema or document, e.g. `tns:item`, with the
+/// namespaces in scope at `node`.
+fn qname(node: Node, value: &str) -> Result<QName, String> {
+    let (prefix, name) = match value.trim().split_once(':') {
+        Some((prefix, name)) => (Some(prefix), name),
+        None => (None, value.trim()),
+    };
+    match (prefix, node.lookup_namespace_uri(prefix)) {
+        (Some(prefix), None) => Err(format!("the prefix {prefix} of {value} is not bound")),
+        (_, namespace) => Ok(QName::new(namespace, name)),
+    }
+}
+
+fn occurs(node: Node) -> Result<(usize, Option<usize>), String> {
+    let number = |value: &str| {
+        value
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("invalid number of occurrences {value:?}"))
+    };
+    let min = node.attribute("minOccurs").map_or(Ok(1), number)?;
+    let max = match node.attribute("maxOccurs") {
+        Some("unbounded") => None,
+        Some(value) => Some(number(value)?),
+        None => Some(1),
+    };
+    Ok((min, max))
+}
+
+/// Turn an XML Schema regular expression, which matches whole values and has no
+/// anchors, into one of the regex crate.
+fn translate_pattern(pattern: &str) -> String {
+    let mut translated = String::from("^(?:");
+    let mut in_class = false;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('i') => translated.push_str(if in_class { "_:A-Za-z" } else { "[_:A-Za-z]" }),
+                Some('I') => translated.push_str("[^_:A-Za-z]"),
+                Some('c') => translated.push_str(if in_class {
+                    "\\-._:A-Za-z0-9"
+                } else {
+                    "[\\-._:A-Za-z0-9]"
+                }),
+                Some('C') => translated.push_str("[^\\-._:A-Za-z0-9]"),
+                Some(other) => {
+                    translated.push('\\');
+                    translated.push(other);
+                }
+                None => translated.push('\\'),
+            },
+            '[' => {
+                in_class = true;
+                translated.push(c);
+            }
+            ']' => {
+                in_class = false;
+                translated.push(c);
+            }
+            '^' | '$' if !in_class => {
+                translated.push('\\');
+                translated.push(c);
+            }
+            _ => translated.push(c),
+        }
+    }
+    translated.push_str(")$");
+    translated
+}
+
+/// How the elements of one schema document are named.
+struct Context {
+    target: Option<String>,
+    elements_qualified: bool,
+    attributes_qualified: bool,
+}
+
+impl Context {
+    fn global_name(&self, node: Node) -> Result<QName, String> {
+        Ok(QName::new(self.target.as_deref(), required(node, "name")?))
+    }
+
+    /// The type of an element or attribute: its `type` attribute, or else its
+    /// anonymous type, or else `default`.
+    fn type_of(&self, node: Node, default: &str) -> Result<TypeRef, String> {
+        if let Some(name) = node.attribute("type") {
+            return Ok(TypeRef::Named(qname(node, name)?));
+        }
+        for child in node.children().filter(Node::is_element) {
+            match xs_name(child)? {
+                "complexType" => {
+                    return Ok(TypeRef::Anonymous(Box::new(Type::Complex(
+                        self.complex_type(child)?,
+                    ))))
+                }
+                "simpleType" => {
+                    return Ok(TypeRef::Anonymous(Box::new(Type::Simple(
+                        self.simple_type(child)?,
+                    ))))
+                }
+                // Identity constraints are not checked.
+                "annotation" | "key" | "keyref" | "unique" => {}
+                _ => return Err(unsupported(child)),
+            }
+        }
+        Ok(TypeRef::Named(QName::xs(default)))
+    }
+
+    fn element(&self, node: Node, global: bool) -> Result<Element, String> {
+        if node.attribute("substitutionGroup").is_some() {
+            return Err("substitution groups are not supported by the native backend".to_owned());
+        }
+        if node.attribute("abstract") == Some("true") {
+            return Err("abstract elements are not supported by the native backend".to_owned());
+        }
+        let qualified = global
+            || node
+                .attribute("form")
+                .map_or(self.elements_qualified, |form| form == "qualified");
+        let namespace = if qualified {
+            self.target.as_deref()
+        } else {
+            None
+        };
+        Ok(Element {
+            name: QName::new(namespace, required(node, "name")?),
+            type_: self.type_of(node, "anyType")?,
+            nillable: node.attribute("nillable") == Some("true"),
+            fixed: node.attribute("fixed").map(str::to_owned),
+        })
+    }
+
+    fn attribute(&self, node: Node, global: bool) -> Result<Attribute, String> {
+        let qualified = global
+            || node
+                .attribute("form")
+                .map_or(self.attributes_qualified, |form| form == "qualified");
+        let namespace = if qualified {
+            self.target.as_deref()
+        } else {
+            None
+        };
+        Ok(Attribute {
+            name: QName::new(namespace, required(node, "name")?),
+            type_: self.type_of(node, "anySimpleType")?,
+            fixed: node.attribute("fixed").map(str::to_owned),
+        })
+    }
+
+    /// Add an attribute, attribute group or attribute wildcard to `group`.
+    fn attribute_use(&self, node: Node, group: &mut AttributeGroup) -> Result<(), String> {
+        match xs_name(node)? {
+            "attribute" => {
+                let required = match node.attribute("use") {
+                    Some("prohibited") => return Ok(()),
+                    use_ => use_ == Some("required"),
+                };
+                group.uses.push(match node.attribute("ref") {
+                    Some(name) => AttributeUse::Ref(qname(node, name)?, required),
+                    None => AttributeUse::Declared(self.attribute(node, false)?, required),
+                });
+            }
+            "attributeGroup" => group
+                .uses
+                .push(AttributeUse::Group(qname(node, required(node, "ref")?)?)),
+            "anyAttribute" => group.any = true,
+            _ => return Err(unsupported(node)),
+        }
+        Ok(())
+    }
+
+    fn attribute_group(&self, node: Node) -> Result<AttributeGroup, String> {
+        let mut group = AttributeGroup::default();
+        for child in node.children().filter(Node::is_element) {
+            if xs_name(child)? != "annotation" {
+                self.attribute_use(child, &mut group)?;
+            }
+        }
+        Ok(group)
+    }
+
+    /// The particle of an element, model group, wildcard or group reference. None
+    /// for an annotation.
+    fn particle(&self, node: Node) -> Result<Option<Particle>, String> {
+        let (min, max) = occurs(node)?;
+        let term = match xs_name(node)? {
+            "annotation" => return Ok(None),
+            "element" => match node.attribute("ref") {
+                Some(name) => Term::ElementRef(qname(node, name)?),
+                None => Term::Element(Box::new(self.element(node, false)?)),
+            },
+            "sequence" => Term::Sequence(self.particles(node)?),
+            "choice" => Term::Choice(self.particles(node)?),
+            "all" => Term::All(self.particles(node)?),
+            "any" => Term::Any(self.wildcard(node)?),
+            "group" => Term::Group(qname(node, required(node, "ref")?)?),
+            _ => return Err(unsupported(node)),
+        };
+        Ok(Some(Particle { term, min, max }))
+    }
+
+    fn particles(&self, node: Node) -> Result<Vec<Particle>, String> {
+        let mut particles = Vec::new();
+        for child in node.children().filter(Node::is_element) {
+            particles.extend(self.particle(child)?);
+        }
+        Ok(particles)
+    }
+
+    fn wildcard(&self, node: Node) -> Result<Wildcard, String> {
+        let namespaces = match node.attribute("namespace").unwrap_or("##any") {
+            "##any" => Namespaces::Any,
+            "##other" => Namespaces::Other(self.target.clone()),
+            list => Namespaces::List(
+                list.split_whitespace()
+                    .map(|namespace| match namespace {
+                        "##targetNamespace" => self.target.clone(),
+                        "##local" => None,
+                        namespace => Some(namespace.to_owned()),
+                    })
+                    .collect(),
+            ),
+        };
+        let process = match node.attribute("processContents").unwrap_or("strict") {
+            "skip" => Process::Skip,
+            "lax" => Process::Lax,
+            "strict" => Process::Strict,
+            other => return Err(format!("invalid processContents {other:?}")),
+        };
+        Ok(Wildcard {
+            namespaces,
+            process,
+        })
+    }
+
+    fn complex_type(&self, node: Node) -> Result<ComplexType, String> {
+        let mut complex_type = ComplexType {
+            base: None,
+            content: Content::Elements(Vec::new()),
+            attributes: AttributeGroup::default(),
+            mixed: node.attribute("mixed") == Some("true"),
+        };
+        for child in node.children().filter(Node::is_element) {
+            match xs_name(child)? {
+                "annotation" => {}
+                "sequence" | "choice" | "all" | "group" => {
+                    complex_type.content =
+                        Content::Elements(self.particle(child)?.into_iter().collect())
+                }
+                "attribute" | "attributeGroup" | "anyAttribute" => {
+                    self.attribute_use(child, &mut complex_type.attributes)?
+                }
+                content @ ("simpleContent" | "complexContent") => {
+                    if child.attribute("mixed") == Some("true") {
+                        complex_type.mixed = true;
+                    }
+                    self.derived_content(child, content == "simpleContent", &mut complex_type)?
+                }
+                _ => return Err(unsupported(child)),
+            }
+        }
+        Ok(complex_type)
+    }
+
+    /// The content and attributes of `xs:simpleContent` or `xs:complexContent`.
+    fn derived_content(
+        &self,
+        node: Node,
+        simple: bool,
+        complex_type: &mut ComplexType,
+    ) -> Result<(), String> {
+        for derivation in node.children().filter(Node::is_element) {
+            let kind = match xs_name(derivation)? {
+                "annotation" => continue,
+                "extension" => Derivation::Extension,
+                "restriction" => Derivation::Restriction,
+                _ => return Err(unsupported(derivation)),
+            };
+            let base = qname(derivation, required(derivation, "base")?)?;
+            complex_type.content = match (simple, kind) {
+                (true, Derivation::Extension) => Content::Simple(TypeRef::Named(base.clone())),
+                (true, Derivation::Restriction) => {
+                    Content::Simple(TypeRef::Anonymous(Box::new(Type::Simple(SimpleType {
+                        variety: Variety::Restriction(TypeRef::Named(base.clone())),
+                        facets: self.facets(derivation)?,
+                    }))))
+                }
+                (false, _) => Content::Elements(Vec::new()),
+            };
+            for child in derivation.children().filter(Node::is_element) {
+                match xs_name(child)? {
+                    "sequence" | "choice" | "all" | "group" if !simple => {
+                        complex_type.content =
+                            Content::Elements(self.particle(child)?.into_iter().collect())
+                    }
+                    "attribute" | "attributeGroup" | "anyAttribute" => {
+                        self.attribute_use(child, &mut complex_type.attributes)?
+                    }
+                    _ => {}
+                }
+            }
+            complex_type.base = Some((kind, base));
+        }
+        Ok(())
+    }
+
+    fn simple_type(&self, node: Node) -> Result<SimpleType, String> {
+        // The base or item type, given by name or else inline.
+        let type_of = |node: Node, attribute: &str| -> Result<TypeRef, String> {
+            if let Some(name) = node.attribute(attribute) {
+                return Ok(TypeRef::Named(qname(node, name)?));
+            }
+            match node
+                .children()
+                .find(|child| child.has_tag_name((XS, "simpleType")))
+            {
+                Some(child) => Ok(TypeRef::Anonymous(Box::new(Type::Simple(
+                    self.simple_type(child)?,
+                )))),
+                None => Err(format!("xs:{} without a type", node.tag_name().name())),
+            }
+        };
+        for child in node.children().filter(Node::is_element) {
+            let variety = match xs_name(child)? {
+                "annotation" => continue,
+                "restriction" => Variety::Restriction(type_of(child, "base")?),
+                "list" => Variety::List(type_of(child, "itemType")?),
+                "union" => {
+                    let mut members = Vec::new();
+                    for name in child
+                        .attribute("memberTypes")
+                        .unwrap_or_default()
+                        .split_whitespace()
+                    {
+                        members.push(TypeRef::Named(qname(child, name)?));
+                    }
+                    for member in child
+                        .children()
+                        .filter(|member| member.has_tag_name((XS, "simpleType")))
+                    {
+                        members.push(TypeRef::Anonymous(Box::new(Type::Simple(
+                            self.simple_type(member)?,
+                        ))));
+                    }
+                    Variety::Union(members)
+                }
+                _ => return Err(unsupported(child)),
+            };
+            let facets = if matches!(variety, Variety::Restriction(_)) {
+                self.facets(child)?
+            } else {
+                Facets::default()
+            };
+            return Ok(SimpleType { variety, facets });
+        }
+        Err("xs:simpleType without a restriction, list or union".to_owned())
+    }
+
+    /// The facets of a restriction, ignoring its other children.
+    fn facets(&self, node: Node) -> Result<Facets, String> {
+        let mut facets = Facets::default();
+        let mut patterns = Vec::new();
+        for child in node.children().filter(Node::is_element) {
+            if child.tag_name().namespace() != Some(XS) {
+                continue;
+            }
+            let value = || required(child, "value");
+            let number = || {
+                let value = value()?;
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid {} {value:?}", child.tag_name().name()))
+            };
+            match child.tag_name().name() {
+                "enumeration" => facets.enumeration.push(value()?.to_owned()),
+                "pattern" => patterns.push(value()?),
+                "length" => facets.length = Some(number()?),
+                "minLength" => facets.min_length = Some(number()?),
+                "maxLength" => facets.max_length = Some(number()?),
+                "totalDigits" => facets.total_digits = Some(number()?),
+                "fractionDigits" => facets.fraction_digits = Some(number()?),
+                "minInclusive" => facets.min_inclusive = Some(value()?.trim().to_owned()),
+                "maxInclusive" => facets.max_inclusive = Some(value()?.trim().to_owned()),
+                "minExclusive" => facets.min_exclusive = Some(value()?.trim().to_owned()),
+                "maxExclusive" => facets.max_exclusive = Some(value()?.trim().to_owned()),
+                _ => {}
+            }
+        }
+        if !patterns.is_empty() {
+            let source = patterns.join("|");
+            let alternatives: Vec<String> = patterns.iter().map(|p| translate_pattern(p)).collect();
+            let regex = Regex::new(&alternatives.join("|"))
+                .map_err(|e| format!("unsupported pattern {source:?}: {e}"))?;
+            facets.pattern = Some((regex, source));
+        }
+        Ok(facets)
+    }
+}
+
+impl Schema {
+    /// Load a schema and the schemas it includes and imports, whose content
+    /// `fetch` returns given their URL or path.
+    pub fn load(
+        url: &str,
+        fetch: impl Fn(&str) -> Result<Vec<u8>, String>,
+    ) -> Result<Schema, String> {
+        let mut schema = Schema {
+            elements: HashMap::new(),
+            attributes: HashMap::new(),
+            types: HashMap::new(),
+            groups: HashMap::new(),
+            attribute_groups: HashMap::new(),
+            type_refs: HashMap::new(),
+        };
+        // The attributes of the XML namespace, e.g. xml:lang, whose schema is
+        // usually imported from the W3C's site.
+        for (name, type_) in [
+            ("lang", "string"),
+            ("space", "NCName"),
+            ("base", "anyURI"),
+            ("id", "ID"),
+        ] {
+            let name = QName::new(Some(XML), name);
+            schema.attributes.insert(
+                name.clone(),
+                Attribute {
+                    name,
+                    type_: TypeRef::Named(QName::xs(type_)),
+                    fixed: None,
+                },
+            );
+        }
+
+        // Each schema with the target namespace of the schema including it, if
+        // it is included.
+        let mut queue: Vec<(String, Option<Option<String>>)> = vec![(url.to_owned(), None)];
+        let mut seen = HashSet::new();
+        while let Some((url, includer_target)) = queue.pop() {
+            if !seen.insert(url.clone()) {
+                continue;
+            }
+            let content = fetch(&url)?;
+            let text = String::from_utf8(as_utf8(&content).into_owned())
+                .map_err(|_| format!("{url} is not UTF-8"))?;
+            let document = Document::parse_with_options(&text, parsing_options())
+                .map_err(|e| format!("{url}: {e}"))?;
+            let root = document.root_element();
+            if !root.has_tag_name((XS, "schema")) {
+                return Err(format!("{url} is not an XML Schema"));
+            }
+            let own_target = root.attribute("targetNamespace").map(str::to_owned);
+            let context = Context {
+                // An included schema without a target namespace takes on the
+                // including one's.
+                target: match includer_target {
+                    Some(target) if own_target.is_none() => target,
+                    _ => own_target,
+                },
+                elements_qualified: root.attribute("elementFormDefault") == Some("qualified"),
+                attributes_qualified: root.attribute("attributeFormDefault") == Some("qualified"),
+            };
+            for child in root.children().filter(Node::is_element) {
+                let in_schema = |e: String| format!("{url}: {e}");
+                match xs_name(child).map_err(in_schema)? {
+                    "annotation" | "notation" => {}
+                    "include" => queue.push((
+                        resolve(&url, required(child, "schemaLocation").map_err(in_schema)?)?,
+                        Some(context.target.clone()),
+                    )),
+                    "import" => {
+                        if let (Some(location), false) = (
+                            child.attribute("schemaLocation"),
+                            child.attribute("namespace") == Some(XML),
+                        ) {
+                            queue.push((resolve(&url, location)?, None));
+                        }
+                    }
+                    "element" => {
+                        let element = context.element(child, true).map_err(in_schema)?;
+                        schema.elements.insert(element.name.clone(), element);
+                    }
+                    "attribute" => {
+                        let attribute = context.attribute(child, true).map_err(in_schema)?;
+                        schema.attributes.insert(attribute.name.clone(), attribute);
+                    }
+                    "complexType" => {
+                        let name = context.global_name(child).map_err(in_schema)?;
+                        let type_ = context.complex_type(child).map_err(in_schema)?;
+                        schema.types.insert(name, Type::Complex(type_));
+                    }
+                    "simpleType" => {
+                        let name = context.global_name(child).map_err(in_schema)?;
+                        let type_ = context.simple_type(child).map_err(in_schema)?;
+                        schema.types.insert(name, Type::Simple(type_));
+                    }
+                    "group" => {
+                        let name = context.global_name(child).map_err(in_schema)?;
+                        let particles = context.particles(child).map_err(in_schema)?;
+                        let Some(model) = particles.into_iter().next() else {
+                            return Err(in_schema(format!("group {name} has no content")));
+                        };
+                        schema.groups.insert(name, model);
+                    }
+                    "attributeGroup" => {
+                        let name = context.global_name(child).map_err(in_schema)?;
+                        let group = context.attribute_group(child).map_err(in_schema)?;
+                        schema.attribute_groups.insert(name, group);
+                    }
+                    _ => return Err(in_schema(unsupported(child))),
+                }
+            }
+        }
+        schema.check()?;
+        schema.type_refs = ["anyType", "anySimpleType"]
+            .iter()
+            .chain(BUILTINS)
+            .map(|name| QName::xs(name))
+            .chain(schema.types.keys().cloned())
+            .map(|name| (name.clone(), TypeRef::Named(name)))
+            .collect();
+        Ok(schema)
+    }
+
+    /// Check that every name refers to a definition, and that no definition
+    /// refers back to itself but through an element.
+    fn check(&self) -> Result<(), String> {
+        for element in self.elements.values() {
+            self.check_type_ref(&element.type_)?;
+        }
+        for attribute in self.attributes.values() {
+            self.check_type_ref(&attribute.type_)?;
+        }
+        for type_ in self.types.values() {
+            self.check_type(type_)?;
+        }
+        for group in self.groups.values() {
+            self.check_particle(group)?;
+        }
+        for group in self.attribute_groups.values() {
+            self.check_attributes(group)?;
+        }
+
+        let cycle = |kind: &str, names: Vec<&QName>, next: &dyn Fn(&QName) -> Vec<QName>| {
+            for name in names {
+                if let Some(name) = find_cycle(name, &mut Vec::new(), next) {
+                    return Err(format!("the {kind} {name} is defined by itself"));
+                }
+            }
+            Ok(())
+        };
+        cycle("group", self.groups.keys().collect(), &|name| {
+            let mut refs = Vec::new();
+            if let Some(group) = self.groups.get(name) {
+                group_refs(group, &mut refs);
+            }
+            refs
+        })?;
+        cycle(
+            "attribute group",
+            self.attribute_groups.keys().collect(),
+            &|name| {
+                self.attribute_groups
+                    .get(name)
+                    .map(|group| {
+                        group
+                            .uses
+                            .iter()
+                            .filter_map(|use_| match use_ {
+                                AttributeUse::Group(name) => Some(name.clone()),
+                                _ => None,
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            },
+        )?;
+        cycle("type", self.types.keys().collect(), &|name| {
+            let named = |type_: &TypeRef| match type_ {
+                TypeRef::Named(name) if name.builtin().is_none() => Some(name.clone()),
+                _ => None,
+            };
+            match self.types.get(name) {
+                Some(Type::Complex(complex_type)) => complex_type
+                    .base
+                    .iter()
+                    .map(|(_, base)| base.clone())
+                    .filter(|base| base.builtin().is_none())
+                    .collect(),
+                Some(Type::Simple(simple_type)) => match &simple_type.variety {
+                    Variety::Restriction(base) | Variety::List(base) => {
+                        named(base).into_iter().collect()
+                    }
+                    Variety::Union(members) => members.iter().filter_map(named).collect(),
+                },
+                None => Vec::new(),
+            }
+        })
+    }
+
+    fn check_type_ref(&self, type_: &TypeRef) -> Result<(), String> {
+        match type_ {
+            TypeRef::Named(name) => match name.builtin() {
+                Some("anyType" | "anySimpleType") => Ok(()),
+                Some(builtin) if BUILTINS.contains(&builtin) => Ok(()),
+                Some(builtin) => Err(format!("the built-in type xs:{builtin} is not supported")),
+                None if self.types.contains_key(name) => Ok(()),
+                None => Err(format!("the type {name} is not defined")),
+            },
+            TypeRef::Anonymous(type_) => self.check_type(type_),
+        }
+    }
+
+    fn check_type(&self, type_: &Type) -> Result<(), String> {
+        match type_ {
+            Type::Simple(simple_type) => match &simple_type.variety {
+                Variety::Restriction(base) | Variety::List(base) => self.check_type_ref(base),
+                Variety::Union(members) => members.iter().try_for_each(|m| self.check_type_ref(m)),
+            },
+            Type::Complex(complex_type) => {
+                if let Some((_, base)) = &complex_type.base {
+                    self.check_type_ref(&TypeRef::Named(base.clone()))?;
+                }
+                match &complex_type.content {
+                    Content::Simple(type_) => self.check_type_ref(type_)?,
+                    Content::Elements(particles) => {
+                        particles.iter().try_for_each(|p| self.check_particle(p))?
+                    }
+                }
+                self.check_attributes(&complex_type.attributes)
+            }
+        }
+    }
+
+    fn check_particle(&self, particle: &Particle) -> Result<(), String> {
+        match &particle.term {
+            Term::Element(element) => self.check_type_ref(&element.type_),
+            Term::ElementRef(name) if self.elements.contains_key(name) => Ok(()),
+            Term::ElementRef(name) => Err(format!("the element {name} is not declared")),
+            Term::Sequence(particles) | Term::Choice(particles) | Term::All(particles) => {
+                particles.iter().try_for_each(|p| self.check_particle(p))
+            }
+            Term::Any(_) => Ok(()),
+            Term::Group(name) if self.groups.contains_key(name) => Ok(()),
+            Term::Group(name) => Err(format!("the group {name} is not defined")),
+        }
+    }
+
+    fn check_attributes(&self, group: &AttributeGroup) -> Result<(), String> {
+        group.uses.iter().try_for_each(|use_| match use_ {
+            AttributeUse::Declared(attribute, _) => self.check_type_ref(&attribute.type_),
+            AttributeUse::Ref(name, _) if self.attributes.contains_key(name) => Ok(()),
+            AttributeUse::Ref(name, _) => Err(format!("the attribute {name} is not declared")),
+            AttributeUse::Group(name) if self.attribute_groups.contains_key(name) => Ok(()),
+            AttributeUse::Group(name) => Err(format!("the attribute group {name} is not defined")),
+        })
+    }
+
+    fn resolve<'a>(&'a self, type_: &'a TypeRef) -> Resolved<'a> {
+        let type_ = match type_ {
+            TypeRef::Named(name) => match name.builtin() {
+                Some("anyType") => return Resolved::Any,
+                Some(_) => return Resolved::Simple,
+                None => match self.types.get(name) {
+                    Some(type_) => type_,
+                    None => return Resolved::Any,
+                },
+            },
+            TypeRef::Anonymous(type_) => type_,
+        };
+        match type_ {
+            Type::Simple(_) => Resolved::Simple,
+            Type::Complex(complex_type) => Resolved::Complex(complex_type),
+        }
+    }
+
+    /// The content of a complex type, with its base type's child elements first if
+    /// it extends it.
+    fn content<'a>(&'a self, complex_type: &'a ComplexType) -> Effective<'a> {
+        match &complex_type.content {
+            Content::Simple(type_) => Effective::Simple(type_),
+            Content::Elements(own) => {
+                let mut particles = Vec::new();
+                if let Some((Derivation::Extension, base)) = &complex_type.base {
+                    if let Some(Type::Complex(base)) = self.types.get(base) {
+                        if let Effective::Elements(inherited) = self.content(base) {
+                            particles = inherited;
+                        }
+                    }
+                }
+                particles.extend(own);
+                Effective::Elements(particles)
+            }
+        }
+    }
+
+    /// The attributes of a complex type, with its base type's, and whether any
+    /// other attribute is allowed.
+    fn attributes_of<'a>(
+        &'a self,
+        complex_type: &'a ComplexType,
+        attributes: &mut HashMap<&'a QName, (&'a Attribute, bool)>,
+    ) -> bool {
+        let mut any = false;
+        if let Some((_, base)) = &complex_type.base {
+            if let Some(Type::Complex(base)) = self.types.get(base) {
+                any = self.attributes_of(base, attributes);
+            }
+        }
+        any | self.group_attributes(&complex_type.attributes, attributes)
+    }
+
+    fn group_attributes<'a>(
+        &'a self,
+        group: &'a AttributeGroup,
+        attributes: &mut HashMap<&'a QName, (&'a Attribute, bool)>,
+    ) -> bool {
+        let mut any = group.any;
+        for use_ in &group.uses {
+            match use_ {
+                AttributeUse::Declared(attribute, required) => {
+                    attributes.insert(&attribute.name, (attribute, *required));
+                }
+                AttributeUse::Ref(name, required) => {
+                    if let Some(attribute) = self.attributes.get(name) {
+                        attributes.insert(&attribute.name, (attribute, *required));
+                    }
+                }
+                AttributeUse::Group(name) => {
+                    if let Some(group) = self.attribute_groups.get(name) {
+                        any |= self.group_attributes(group, attributes);
+                    }
+                }
+            }
+        }
+        any
+    }
+
+    /// The declarations of the child elements that a content model allows, and
+    /// its wildcards.
+    fn declarations<'a>(
+        &'a self,
+        particle: &'a Particle,
+        elements: &mut HashMap<&'a QName, &'a Element>,
+        wildcards: &mut Vec<&'a Wildcard>,
+    ) {
+        match &particle.term {
+            Term::Element(element) => {
+                elements.entry(&element.name).or_insert(element);
+            }
+            Term::ElementRef(name) => {
+                if let Some(element) = self.elements.get(name) {
+                    elements.entry(&element.name).or_insert(element);
+                }
+            }
+            Term::Sequence(particles) | Term::Choice(particles) | Term::All(particles) => {
+                for particle in particles {
+                    self.declarations(particle, elements, wildcards);
+                }
+            }
+            Term::Any(wildcard) => wildcards.push(wildcard),
+            Term::Group(name) => {
+                if let Some(group) = self.groups.get(name) {
+                    self.declarations(group, elements, wildcards);
+                }
+            }
+        }
+    }
+
+    /// Whether values of a type keep their whitespace, which only strings do.
+    fn preserves_whitespace(&self, type_: &TypeRef) -> bool {
+        let type_ = match type_ {
+            TypeRef::Named(name) => match name.builtin() {
+                Some(builtin) => {
+                    return matches!(builtin, "string" | "normalizedString" | "anySimpleType")
+                }
+                None => match self.types.get(name) {
+                    Some(type_) => type_,
+                    None => return true,
+                },
+            },
+            TypeRef::Anonymous(type_) => type_,
+        };
+        match type_ {
+            Type::Simple(SimpleType {
+                variety: Variety::Restriction(base),
+                ..
+            }) => self.preserves_whitespace(base),
+            Type::Simple(_) => false,
+            Type::Complex(complex_type) => match self.content(complex_type) {
+                Effective::Simple(type_) => self.preserves_whitespace(type_),
+                Effective::Elements(_) => true,
+            },
+        }
+    }
+
+    /// Whether a type's values are lists, whose length is their number of items.
+    fn is_list(&self, type_: &TypeRef) -> bool {
+        let type_ = match type_ {
+            TypeRef::Named(name) => match name.builtin() {
+                Some(builtin) => return matches!(builtin, "IDREFS" | "ENTITIES" | "NMTOKENS"),
+                None => match self.types.get(name) {
+                    Some(type_) => type_,
+                    None => return false,
+                },
+            },
+            TypeRef::Anonymous(type_) => type_,
+        };
+        match type_ {
+            Type::Simple(simple_type) => match &simple_type.variety {
+                Variety::Restriction(base) => self.is_list(base),
+                Variety::List(_) => true,
+                Variety::Union(_) => false,
+            },
+            Type::Complex(_) => false,
+        }
+    }
+
+    /// Check a value as written in a document, or say why it is not valid.
+    fn check_value(&self, type_: &TypeRef, value: &str) -> Result<(), String> {
+        if self.preserves_whitespace(type_) {
+            self.check_simple(type_, value)
+        } else {
+            self.check_simple(
+                type_,
+                &value.split_whitespace().collect::<Vec<_>>().join(" "),
+            )
+        }
+    }
+
+    fn check_simple(&self, type_: &TypeRef, value: &str) -> Result<(), String> {
+        let type_ = match type_ {
+            TypeRef::Named(name) => match name.builtin() {
+                Some(builtin) if is_builtin_value(builtin, value) => return Ok(()),
+                Some(builtin) => {
+                    return Err(format!(
+                        "'{value}' is not a valid value of the atomic type 'xs:{builtin}'."
+                    ))
+                }
+                None => match self.types.get(name) {
+                    Some(type_) => type_,
+                    None => return Ok(()),
+                },
+            },
+            TypeRef::Anonymous(type_) => type_,
+        };
+        let simple_type = match type_ {
+            Type::Simple(simple_type) => simple_type,
+            Type::Complex(complex_type) => {
+                return match self.content(complex_type) {
+                    Effective::Simple(type_) => self.check_simple(type_, value),
+                    Effective::Elements(_) => Ok(()),
+                }
+            }
+        };
+        let length = match &simple_type.variety {
+            Variety::Restriction(base) => {
+                self.check_simple(base, value)?;
+                if self.is_list(base) {
+                    value.split_whitespace().count()
+                } else {
+                    value.chars().count()
+                }
+            }
+            Variety::List(item) => {
+                for item_value in value.split_whitespace() {
+                    self.check_simple(item, item_value)?;
+                }
+                value.split_whitespace().count()
+            }
+            Variety::Union(members) => {
+                if !members
+                    .iter()
+                    .any(|member| self.check_value(member, value).is_ok())
+                {
+                    return Err(format!(
+                        "'{value}' is not a valid value of the local union type."
+                    ));
+                }
+                value.chars().count()
+            }
+        };
+        simple_type.facets.check(value, length)
+    }
+}
+
+impl Facets {
+    fn check(&self, value: &str, length: usize) -> Result<(), String> {
+        if !self.enumeration.is_empty() && !self.enumeration.iter().any(|e| e == value) {
+            let set: Vec<String> = self.enumeration.iter().map(|e| format!("'{e}'")).collect();
+            return Err(format!(
+                "[facet 'enumeration'] The value '{value}' is not an element of the set {{{}}}.",
+                set.join(", ")
+            ));
+        }
+        if let Some((pattern, source)) = &self.pattern {
+            if !pattern.is_match(value) {
+                return Err(format!(
+                    "[facet 'pattern'] The value '{value}' is not accepted by the pattern '{source}'."
+                ));
+            }
+        }
+        let lengths = [
+            (
+                self.length,
+                "length",
+                "differs from the allowed length",
+                Ordering::Equal,
+            ),
+            (
+                self.min_length,
+                "minLength",
+                "underruns the allowed minimum length",
+                Ordering::Greater,
+            ),
+            (
+                self.max_length,
+                "maxLength",
+                "exceeds the allowed maximum length",
+                Ordering::Less,
+            ),
+        ];
+        for (limit, facet, problem, allowed) in lengths {
+            if let Some(limit) = limit {
+                let ordering = length.cmp(&limit);
+                if ordering != Ordering::Equal && ordering != allowed {
+                    return Err(format!(
+                        "[facet '{facet}'] The value '{value}' has a length of '{length}'; this {problem} of '{limit}'."
+                    ));
+                }
+            }
+        }
+        let bounds = [
+            (
+                &self.min_inclusive,
+                "minInclusive",
+                "is less than the minimum value allowed",
+                Ordering::Less,
+            ),
+            (
+                &self.max_inclusive,
+                "maxInclusive",
+                "is greater than the maximum value allowed",
+                Ordering::Greater,
+            ),
+        ];
+        for (bound, facet, problem, forbidden) in bounds {
+            if let Some(bound) = bound {
+                if compare(value, bound) == Some(forbidden) {
+                    return Err(format!(
+                        "[facet '{facet}'] The value '{value}' {problem} ('{bound}')."
+                    ));
+                }
+            }
+        }
+        let bounds = [
+            (
+                &self.min_exclusive,
+                "minExclusive",
+                "greater",
+                Ordering::Greater,
+            ),
+            (&self.max_exclusive, "maxExclusive", "less", Ordering::Less),
+        ];
+        for (bound, facet, relation, required) in bounds {
+            if let Some(bound) = bound {
+                if compare(value, bound).is_some_and(|ordering| ordering != required) {
+                    return Err(format!(
+                        "[facet '{facet}'] The value '{value}' must be {relation} than '{bound}'."
+                    ));
+                }
+            }
+        }
+        if self.total_digits.is_some() || self.fraction_digits.is_some() {
+            let unsigned = value.trim_start_matches(['+', '-']);
+            let (integer, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+            let integer = integer.trim_start_matches('0');
+            let fraction = fraction.trim_end_matches('0');
+            if let Some(total) = self.total_digits {
+                if integer.len() + fraction.len() > total {
+                    return Err(format!(
+                        "[facet 'totalDigits'] The value '{value}' has more digits than are allowed ('{total}')."
+                    ));
+                }
+            }
+            if let Some(digits) = self.fraction_digits {
+                if fraction.len() > digits {
+                    return Err(format!(
+                        "[facet 'fractionDigits'] The value '{value}' has more fractional digits than are allowed ('{digits}')."
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare a value with a bound, as numbers if they are, or else as text, which
+/// orders dates and times written the same way.
+fn compare(value: &str, bound: &str) -> Option<Ordering> {
+    match (value.parse::<f64>(), bound.parse::<f64>()) {
+        (Ok(value), Ok(bound)) => value.partial_cmp(&bound),
+        _ => Some(value.cmp(bound)),
+    }
+}
+
+/// Whether a value, with its whitespace already processed, is one of a built-in
+/// type.
+fn is_builtin_value(builtin: &str, value: &str) -> bool {
+    lazy_static! {
+        static ref FORMATS: HashMap<&'static str, Regex> = {
+            let timezone = r"(Z|[+-]\d{2}:\d{2})?";
+            let date = r"-?\d{4,}-(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01])";
+            let time = r"([01]\d|2[0-3]):[0-5]\d:[0-5]\d(\.\d+)?";
+            let name = r"[\p{L}_:][\p{L}\p{N}\p{M}._:-]*";
+            let ncname = r"[\p{L}_][\p{L}\p{N}\p{M}._-]*";
+            [
+                ("boolean", "true|false|1|0".to_owned()),
+                ("decimal", r"[+-]?(\d+(\.\d*)?|\.\d+)".to_owned()),
+                ("integer", r"[+-]?\d+".to_owned()),
+                (
+                    "float",
+                    r"[+-]?(\d+(\.\d*)?|\.\d+)([eE][+-]?\d+)?|-?INF|NaN".to_owned(),
+                ),
+                (
+                    "duration",
+                    r"-?P(\d+Y)?(\d+M)?(\d+D)?(T(\d+H)?(\d+M)?(\d+(\.\d+)?S)?)?".to_owned(),
+                ),
+                ("dateTime", format!("{date}T{time}{timezone}")),
+                ("date", format!("{date}{timezone}")),
+                ("time", format!("{time}{timezone}")),
+                ("gYear", format!(r"-?\d{{4,}}{timezone}")),
+                (
+                    "gYearMonth",
+                    format!(r"-?\d{{4,}}-(0[1-9]|1[0-2]){timezone}"),
+                ),
+                ("gMonth", format!("--(0[1-9]|1[0-2]){timezone}")),
+                (
+                    "gMonthDay",
+                    format!(r"--(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01]){timezone}"),
+                ),
+                ("gDay", format!(r"---(0[1-9]|[12]\d|3[01]){timezone}")),
+                ("hexBinary", "([0-9a-fA-F]{2})*".to_owned()),
+                (
+                    "base64Binary",
+                    "([A-Za-z0-9+/]{4})*([A-Za-z0-9+/]{2}==|[A-Za-z0-9+/]{3}=)?".to_owned(),
+                ),
+                ("language", "[a-zA-Z]{1,8}(-[a-zA-Z0-9]{1,8})*".to_owned()),
+                ("Name", name.to_owned()),
+                ("NCName", ncname.to_owned()),
+                ("QName", format!("({ncname}:)?{ncname}")),
+                ("NMTOKEN", r"[\p{L}\p{N}\p{M}._:-]+".to_owned()),
+            ]
+            .into_iter()
+            .map(|(builtin, format)| {
+                let regex =
+                    Regex::new(&format!("^(?:{format})$")).expect("invalid built-in type format");
+                (builtin, regex)
+            })
+            .collect()
+        };
+    }
+
+    let matches = |format: &str| FORMATS[format].is_match(value);
+    let integer_in = |min: i128, max: i128| {
+        matches("integer")
+            && value
+                .parse::<i128>()
+                .is_ok_and(|n| (min..=max).contains(&n))
+    };
+    // Signed integers without bounds may be too long for i128.
+    let sign = || value.starts_with('-') && value.trim_start_matches(['-', '0']).is_empty();
+    let is_zero = || value.trim_start_matches(['+', '-', '0']).is_empty();
+    match builtin {
+        "string" | "normalizedString" | "token" | "anyURI" | "anySimpleType" | "NOTATION" => true,
+        "float" | "double" => matches("float"),
+        "ID" | "IDREF" | "ENTITY" => matches("NCName"),
+        "IDREFS" | "ENTITIES" => {
+            value.split_whitespace().next().is_some()
+                && value
+                    .split_whitespace()
+                    .all(|item| FORMATS["NCName"].is_match(item))
+        }
+        "NMTOKENS" => {
+            value.split_whitespace().next().is_some()
+                && value
+                    .split_whitespace()
+                    .all(|item| FORMATS["NMTOKEN"].is_match(item))
+        }
+        "duration" => matches("duration") && !value.ends_with(['P', 'T']),
+        "base64Binary" => {
+            let compact: String = value.split_whitespace().collect();
+            FORMATS["base64Binary"].is_match(&compact)
+        }
+        "nonPositiveInteger" => matches("integer") && (value.starts_with('-') || is_zero()),
+        "negativeInteger" => matches("integer") && value.starts_with('-') && !is_zero(),
+        "nonNegativeInteger" => matches("integer") && (!value.starts_with('-') || sign()),
+        "positiveInteger" => matches("integer") && !value.starts_with('-') && !is_zero(),
+        "long" => integer_in(i64::MIN.into(), i64::MAX.into()),
+        "int" => integer_in(i32::MIN.into(), i32::MAX.into()),
+        "short" => integer_in(i16::MIN.into(), i16::MAX.into()),
+        "byte" => integer_in(i8::MIN.into(), i8::MAX.into()),
+        "unsignedLong" => integer_in(0, u64::MAX.into()),
+        "unsignedInt" => integer_in(0, u32::MAX.into()),
+        "unsignedShort" => integer_in(0, u16::MAX.into()),
+        "unsignedByte" => integer_in(0, u8::MAX.into()),
+        format => FORMATS
+            .get(format)
+            .is_some_and(|regex| regex.is_match(value)),
+    }
+}
+
+enum Resolved<'a> {
+    /// `xs:anyType`, which allows anything.
+    Any,
+    Simple,
+    Complex(&'a ComplexType),
+}
+
+enum Effective<'a> {
+    Simple(&'a TypeRef),
+    Elements(Vec<&'a Particle>),
+}
+
+/// A definition that refers back to itself, following `next` from `name`.
+fn find_cycle(
+    name: &QName,
+    path: &mut Vec<QName>,
+    next: &dyn Fn(&QName) -> Vec<QName>,
+) -> Option<QName> {
+    if path.contains(name) {
+        return Some(name.clone());
+    }
+    path.push(name.clone());
+    let found = next(name)
+        .iter()
+        .find_map(|following| find_cycle(following, path, next));
+    path.pop();
+    found
+}
+
+/// The model groups that a particle refers to without going through an element.
+fn group_refs(particle: &Particle, refs: &mut Vec<QName>) {
+    match &particle.term {
+        Term::Group(name) => refs.push(name.clone()),
+        Term::Sequence(particles) | Term::Choice(particles) | Term::All(particles) => {
+            for particle in particles {
+                group_refs(particle, refs);
+            }
+        }
+        Term::Element(_) | Term::ElementRef(_) | Term::Any(_) => {}
+    }
+}
+
+/// Matches child elements against a content model.
+struct Matcher<'a> {
+    schema: &'a Schema,
+    children: &'a [(QName, Node<'a, 'a>)],
+    /// The position after the last child that matched anything, to report the
+    /// first child that does not fit.
+    furthest: usize,
+}
+
+impl Matcher<'_> {
+    /// The positions where matching `particle` from `start` may end.
+    fn particle(&mut self, particle: &Particle, start: usize) -> BTreeSet<usize> {
+        let mut ends = BTreeSet::new();
+        if particle.min == 0 {
+            ends.insert(start);
+        }
+        let mut current = BTreeSet::from([start]);
+        let mut count = 0;
+        while !current.is_empty() && particle.max.is_none_or(|max| count < max) {
+            let mut next = BTreeSet::new();
+            for &position in &current {
+                next.extend(self.term(&particle.term, position));
+            }
+            count += 1;
+            if count >= particle.min {
+                // Only positions not reached before can lead further.
+                next.retain(|position| !ends.contains(position));
+                ends.extend(&next);
+            }
+            current = next;
+        }
+        ends
+    }
+
+    fn sequence<'p>(
+        &mut self,
+        particles: impl IntoIterator<Item = &'p Particle>,
+        start: usize,
+    ) -> BTreeSet<usize> {
+        let mut positions = BTreeSet::from([start]);
+        for particle in particles {
+            let mut next = BTreeSet::new();
+            for &position in &positions {
+                next.extend(self.particle(particle, position));
+            }
+            positions = next;
+        }
+        positions
+    }
+
+    fn one(&mut self, start: usize, accepts: impl Fn(&QName) -> bool) -> BTreeSet<usize> {
+        match self.children.get(start) {
+            Some((name, _)) if accepts(name) => {
+                self.furthest = self.furthest.max(start + 1);
+                BTreeSet::from([start + 1])
+            }
+            _ => BTreeSet::new(),
+        }
+    }
+
+    fn term(&mut self, term: &Term, start: usize) -> BTreeSet<usize> {
+        match term {
+            Term::Element(element) => self.one(start, |name| *name == element.name),
+            Term::ElementRef(reference) => self.one(start, |name| name == reference),
+            Term::Any(wildcard) => {
+                self.one(start, |name| wildcard.allows(name.namespace.as_deref()))
+            }
+            Term::Sequence(particles) => self.sequence(particles, start),
+            Term::Choice(particles) => {
+                let mut ends = BTreeSet::new();
+                for particle in particles {
+                    ends.extend(self.particle(particle, start));
+                }
+                ends
+            }
+            Term::All(particles) => {
+                // Each element at most once, in any order.
+                let mut used = vec![false; particles.len()];
+                let mut position = start;
+                'children: while position < self.children.len() {
+                    for (i, particle) in particles.iter().enumerate() {
+                        if !used[i]
+                            && self
+                                .term(&particle.term, position)
+                                .contains(&(position + 1))
+                        {
+                            used[i] = true;
+                            position += 1;
+                            continue 'children;
+                        }
+                    }
+                    break;
+                }
+                let complete = particles
+                    .iter()
+                    .zip(&used)
+                    .all(|(particle, used)| *used || particle.min == 0);
+                if complete {
+                    BTreeSet::from([position])
+                } else {
+                    BTreeSet::new()
+                }
+            }
+            Term::Group(name) => match self.schema.groups.get(name) {
+                Some(group) => self.particle(group, start),
+                None => BTreeSet::new(),
+            },
+        }
+    }
+}
+
+/// Checks a document against a schema.
+struct Validator<'a, 'input> {
+    schema: &'a Schema,
+    document: &'a Document<'input>,
+    errors: Vec<ErrorDetail>,
+}
+
+impl<'a, 'input> Validator<'a, 'input> {
+    fn error(&mut self, node: Node, message: String, code: &codes::Code) {
+        let position = self.document.text_pos_at(node.range().start);
+        self.errors.push(ErrorDetail {
+            line: position.row as i32,
+            column: position.col as i32,
+            ..ErrorDetail::new(message, code)
+        });
+    }
+
+    fn element(&mut self, node: Node<'a, 'input>, element: &'a Element) {
+        let name = QName::of(node);
+        if matches!(node.attribute((XSI, "nil")), Some("true" | "1")) {
+            if !element.nillable {
+                let message = format!("Element '{name}': The element is not 'nillable'.");
+                self.error(node, message, &codes::INVALID_ELEMENT);
+            } else if node.has_children() {
+                let message = format!(
+                    "Element '{name}': The element cannot have content, since it is nilled."
+                );
+                self.error(node, message, &codes::INVALID_CONTENT);
+            }
+            return;
+        }
+        let type_ = match node.attribute((XSI, "type")) {
+            Some(value) => match qname(node, value)
+                .ok()
+                .and_then(|type_name| self.schema.type_refs.get(&type_name))
+            {
+                Some(type_) => type_,
+                None => {
+                    let message = format!(
+                        "Element '{name}', attribute '{{{XSI}}}type': The QName value '{value}' does not resolve to a type definition."
+                    );
+                    self.error(node, message, &codes::INVALID_ELEMENT);
+                    return;
+                }
+            },
+            None => &element.type_,
+        };
+        self.typed(node, &name, type_);
+        if let Some(fixed) = &element.fixed {
+            let text = text(node);
+            if !node.children().any(|child| child.is_element()) && text.trim() != fixed.trim() {
+                let message = format!(
+                    "Element '{name}': The value '{text}' does not match the fixed value constraint '{fixed}'."
+                );
+                self.error(node, message, &codes::INVALID_VALUE);
+            }
+        }
+    }
+
+    fn typed(&mut self, node: Node<'a, 'input>, name: &QName, type_: &'a TypeRef) {
+        match self.schema.resolve(type_) {
+            Resolved::Any => {}
+            Resolved::Simple => {
+                self.attributes(node, name, &HashMap::new(), false);
+                if node.children().any(|child| child.is_element()) {
+                    let message = format!(
+                        "Element '{name}': Element content is not allowed, because the type definition is simple."
+                    );
+                    self.error(node, message, &codes::INVALID_CONTENT);
+                } else {
+                    self.value(node, name, None, &text(node), type_);
+                }
+            }
+            Resolved::Complex(complex_type) => {
+                let mut attributes = HashMap::new();
+                let any_attribute = self.schema.attributes_of(complex_type, &mut attributes);
+                self.attributes(node, name, &attributes, any_attribute);
+                match self.schema.content(complex_type) {
+                    Effective::Simple(type_) => {
+                        if node.children().any(|child| child.is_element()) {
+                            let message = format!(
+                                "Element '{name}': Element content is not allowed, because the content type is a simple type definition."
+                            );
+                            self.error(node, message, &codes::INVALID_CONTENT);
+                        } else {
+                            self.value(node, name, None, &text(node), type_);
+                        }
+                    }
+                    Effective::Elements(particles) => {
+                        self.children(node, name, &particles, complex_type.mixed)
+                    }
+                }
+            }
+        }
+    }
+
+    fn attributes(
+        &mut self,
+        node: Node<'a, 'input>,
+        name: &QName,
+        declared: &HashMap<&'a QName, (&'a Attribute, bool)>,
+        any_attribute: bool,
+    ) {
+        for attribute in node.attributes() {
+            if attribute.namespace() == Some(XSI) {
+                continue;
+            }
+            let attribute_name = QName::new(attribute.namespace(), attribute.name());
+            match declared.get(&attribute_name) {
+                Some((declaration, _)) => {
+                    if let Some(fixed) = &declaration.fixed {
+                        if attribute.value().trim() != fixed.trim() {
+                            let message = format!(
+                                "Element '{name}', attribute '{attribute_name}': The value '{}' does not match the fixed value constraint '{fixed}'.",
+                                attribute.value()
+                            );
+                            self.error(node, message, &codes::INVALID_VALUE);
+                        }
+                    }
+                    self.value(
+                        node,
+                        name,
+                        Some(&attribute_name),
+                        attribute.value(),
+                        &declaration.type_,
+                    );
+                }
+                None if any_attribute => {}
+                None => {
+                    let message = format!(
+                        "Element '{name}', attribute '{attribute_name}': The attribute '{attribute_name}' is not allowed."
+                    );
+                    self.error(node, message, &codes::UNKNOWN_ATTRIBUTE);
+                }
+            }
+        }
+        let mut missing: Vec<&QName> = declared
+            .iter()
+            .filter(|(attribute_name, (_, required))| {
+                *required
+                    && !node.attributes().any(|attribute| {
+                        attribute.namespace() == attribute_name.namespace.as_deref()
+                            && attribute.name() == attribute_name.name
+                    })
+            })
+            .map(|(attribute_name, _)| *attribute_name)
+            .collect();
+        missing.sort_by_key(|attribute_name| attribute_name.to_string());
+        for attribute_name in missing {
+            let message = format!(
+                "Element '{name}': The attribute '{attribute_name}' is required but missing."
+            );
+            self.error(node, message, &codes::MISSING_ATTRIBUTE);
+        }
+    }
+
+    fn value(
+        &mut self,
+        node: Node,
+        name: &QName,
+        attribute: Option<&QName>,
+        value: &str,
+        type_: &TypeRef,
+    ) {
+        if let Err(reason) = self.schema.check_value(type_, value) {
+            let message = match attribute {
+                Some(attribute) => format!("Element '{name}', attribute '{attribute}': {reason}"),
+                None => format!("Element '{name}': {reason}"),
+            };
+            self.error(node, message, &codes::INVALID_VALUE);
+        }
+    }
+
+    fn children(
+        &mut self,
+        node: Node<'a, 'input>,
+        name: &QName,
+        particles: &[&'a Particle],
+        mixed: bool,
+    ) {
+        if !mixed {
+            let text = node.children().find(|child| {
+                child.is_text() && !child.text().unwrap_or_default().trim().is_empty()
+            });
+            if let Some(text) = text {
+                let message = format!(
+                    "Element '{name}': Character content other than whitespace is not allowed because the content type is 'element-only'."
+                );
+                self.error(text, message, &codes::INVALID_CONTENT);
+            }
+        }
+
+        let children: Vec<(QName, Node)> = node
+            .children()
+            .filter(Node::is_element)
+            .map(|child| (QName::of(child), child))
+            .collect();
+        let mut matcher = Matcher {
+            schema: self.schema,
+            children: &children,
+            furthest: 0,
+        };
+        if !matcher
+            .sequence(particles.iter().copied(), 0)
+            .contains(&children.len())
+        {
+            match children.get(matcher.furthest) {
+                Some((child_name, child)) => {
+                    let message = format!("Element '{child_name}': This element is not expected.");
+                    self.error(*child, message, &codes::INVALID_CONTENT);
+                }
+                None => {
+                    let message = format!("Element '{name}': Missing child element(s).");
+                    self.error(node, message, &codes::INVALID_CONTENT);
+                }
+            }
+        }
+
+        let mut elements = HashMap::new();
+        let mut wildcards = Vec::new();
+        for particle in particles {
+            self.schema
+                .declarations(particle, &mut elements, &mut wildcards);
+        }
+        for (child_name, child) in children {
+            if let Some(element) = elements.get(&child_name) {
+                self.element(child, element);
+                continue;
+            }
+            let Some(wildcard) = wildcards
+                .iter()
+                .find(|wildcard| wildcard.allows(child_name.namespace.as_deref()))
+            else {
+                // Reported as not expected.
+                continue;
+            };
+            match (wildcard.process, self.schema.elements.get(&child_name)) {
+                (Process::Skip, _) | (Process::Lax, None) => {}
+                (_, Some(element)) => self.element(child, element),
+                (Process::Strict, None) => {
+                    let message = format!(
+                        "Element '{child_name}': No matching global element declaration available, but demanded by the strict wildcard."
+                    );
+                    self.error(child, message, &codes::INVALID_ELEMENT);
+                }
+            }
+        }
+    }
+}
+
+/// The text of an element, without that of its descendants.
+fn text(node: Node) -> String {
+    node.children()
+        .filter(Node::is_text)
+        .filter_map(|child| child.text())
+        .collect()
+}
+
+/// Validate a parsed document against a schema, returning its errors.
+pub fn validate(document: &Document, schema: &Schema) -> Vec<ErrorDetail> {
+    let mut validator = Validator {
+        schema,
+        document,
+        errors: Vec::new(),
+    };
+    let root = document.root_element();
+    match schema.elements.get(&QName::of(root)) {
+        Some(element) => validator.element(root, element),
+        None => {
+            let message = format!(
+                "Element '{}': No matching global declaration available for the validation root.",
+                QName::of(root)
+            );
+            validator.error(root, message, &codes::UNDECLARED_ROOT);
+        }
+    }
+    validator.errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE: &str = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema"
+            xmlns="urn:note" targetNamespace="urn:note" elementFormDefault="qualified">
+        <xs:include schemaLocation="types.xsd"/>
+        <xs:element name="note">
+            <xs:complexType>
+                <xs:sequence>
+                    <xs:element name="to" type="xs:string" maxOccurs="3"/>
+                    <xs:element name="priority" type="priority" minOccurs="0"/>
+                    <xs:element name="body" type="xs:string" nillable="true"/>
+                    <xs:element name="meta" minOccurs="0">
+                        <xs:complexType>
+                            <xs:all>
+                                <xs:element name="author" type="xs:NCName"/>
+                                <xs:element name="sent" type="xs:date" minOccurs="0"/>
+                            </xs:all>
+                        </xs:complexType>
+                    </xs:element>
+                </xs:sequence>
+                <xs:attribute name="id" type="code" use="required"/>
+            </xs:complexType>
+        </xs:element>
+    </xs:schema>"#;
+
+    /// Included without a target namespace, so taking on urn:note.
+    const TYPES: &str = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+        <xs:simpleType name="priority">
+            <xs:restriction base="xs:positiveInteger">
+                <xs:maxInclusive value="5"/>
+            </xs:restriction>
+        </xs:simpleType>
+        <xs:simpleType name="code">
+            <xs:restriction base="xs:token">
+                <xs:pattern value="[A-Z]+-\d+"/>
+            </xs:restriction>
+        </xs:simpleType>
+    </xs:schema>"#;
+
+    fn load(schemas: &[(&str, &str)]) -> Result<Schema, String> {
+        let schemas: HashMap<&str, &str> = schemas.iter().copied().collect();
+        Schema::load("/schemas/note.xsd", |location| {
+            schemas
+                .get(location)
+                .map(|schema| schema.as_bytes().to_vec())
+                .ok_or_else(|| format!("{location} not found"))
+        })
+    }
+
+    fn messages(document: &str) -> Vec<String> {
+        let schema = load(&[("/schemas/note.xsd", NOTE), ("/schemas/types.xsd", TYPES)]).unwrap();
+        let document = parse_document(document).unwrap();
+        validate(&document, &schema)
+            .into_iter()
+            .map(|error| error.message)
+            .collect()
+    }
+
+    #[test]
+    fn valid_note() {
+        let document = r#"<note xmlns="urn:note" id=" NOTE-1 ">
+            <to>Tove</to><to>Jani</to><priority>2</priority><body>Hi</body>
+            <meta><sent>2024-02-29</sent><author>Jani</author></meta>
+        </note>"#;
+        assert!(messages(document).is_empty());
+    }
+
+    #[test]
+    fn unexpected_and_missing_elements() {
+        assert_eq!(
+            messages(r#"<note xmlns="urn:note" id="A-1"><body/><to/></note>"#),
+            ["Element '{urn:note}body': This element is not expected."]
+        );
+        assert_eq!(
+            messages(r#"<note xmlns="urn:note" id="A-1"><to/></note>"#),
+            ["Element '{urn:note}note': Missing child element(s)."]
+        );
+        assert_eq!(
+            messages(r#"<note xmlns="urn:note" id="A-1"><to/><to/><to/><to/><body/></note>"#),
+            ["Element '{urn:note}to': This element is not expected."]
+        );
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert_eq!(
+            messages(r#"<note xmlns="urn:note" id="a-1"><to/><priority>6</priority><body/></note>"#),
+            [
+                "Element '{urn:note}note', attribute 'id': [facet 'pattern'] The value 'a-1' is not accepted by the pattern '[A-Z]+-\\d+'.",
+                "Element '{urn:note}priority': [facet 'maxInclusive'] The value '6' is greater than the maximum value allowed ('5').",
+            ]
+        );
+        assert_eq!(
+            messages(r#"<note xmlns="urn:note" id="A-1"><to/><priority>0</priority><body/></note>"#),
+            ["Element '{urn:note}priority': '0' is not a valid value of the atomic type 'xs:positiveInteger'."]
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(
+            messages(r#"<note xmlns="urn:note" lang="en"><to/><body/></note>"#),
+            [
+                "Element '{urn:note}note', attribute 'lang': The attribute 'lang' is not allowed.",
+                "Element '{urn:note}note': The attribute 'id' is required but missing.",
+            ]
+        );
+    }
+
+    #[test]
+    fn nil_and_all() {
+        let xsi = r#"xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance""#;
+        assert!(messages(&format!(
+            r#"<note xmlns="urn:note" {xsi} id="A-1"><to/><body xsi:nil="true"/></note>"#
+        ))
+        .is_empty());
+        assert_eq!(
+            messages(&format!(
+                r#"<note xmlns="urn:note" {xsi} id="A-1"><to xsi:nil="true"/><body/></note>"#
+            )),
+            ["Element '{urn:note}to': The element is not 'nillable'."]
+        );
+        assert_eq!(
+            messages(
+                r#"<note xmlns="urn:note" id="A-1"><to/><body/><meta><sent>2024-01-01</sent></meta></note>"#
+            ),
+            ["Element '{urn:note}meta': Missing child element(s)."]
+        );
+    }
+
+    #[test]
+    fn undeclared_root() {
+        assert_eq!(
+            messages(r#"<note id="A-1"/>"#),
+            ["Element 'note': No matching global declaration available for the validation root."]
+        );
+    }
+
+    #[test]
+    fn schemas_that_are_not_loaded() {
+        assert_eq!(
+            load(&[("/schemas/note.xsd", NOTE)]).err().unwrap(),
+            "/schemas/types.xsd not found"
+        );
+        let substitution = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+            <xs:element name="a"/>
+            <xs:element name="b" substitutionGroup="a"/>
+        </xs:schema>"#;
+        assert!(load(&[("/schemas/note.xsd", substitution)])
+            .err()
+            .unwrap()
+            .contains("substitution groups are not supported"));
+        let cycle = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+            <xs:group name="a"><xs:sequence><xs:group ref="b"/></xs:sequence></xs:group>
+            <xs:group name="b"><xs:choice><xs:group ref="a"/></xs:choice></xs:group>
+        </xs:schema>"#;
+        assert!(load(&[("/schemas/note.xsd", cycle)])
+            .err()
+            .unwrap()
+            .ends_with("is defined by itself"));
+    }
+
+    #[test]
+    fn patterns() {
+        let matches = |pattern: &str, value: &str| {
+            Regex::new(&translate_pattern(pattern))
+                .unwrap()
+                .is_match(value)
+        };
+        assert!(matches(r"\i\c*", "_a-1"));
+        assert!(!matches(r"\i\c*", "1a"));
+        assert!(matches("a$b", "a$b"));
+        assert!(!matches("ab", "xab"));
+        assert!(matches("[^a]b", "cb"));
+    }
+
+    #[test]
+    fn builtin_values() {
+        assert!(is_builtin_value("byte", "-128"));
+        assert!(!is_builtin_value("byte", "128"));
+        assert!(is_builtin_value("nonNegativeInteger", "-0"));
+        assert!(is_builtin_value("dateTime", "2024-02-29T12:00:00.5Z"));
+        assert!(!is_builtin_value("duration", "P"));
+        assert!(is_builtin_value("base64Binary", "aGk="));
+        assert!(!is_builtin_value("boolean", "yes"));
+    }
+}
@@ -0,0 +1,394 @@
+//! Policies for schema downloads: which hosts they may reach, keeping them away from
+//! private networks so that untrusted documents cannot make validate-xml reach
+//! internal services through their schemaLocation, and spacing out requests to the
+//! same host.
+
+use reqwest::blocking::Client;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+lazy_static::lazy_static! {
+    /// The policy of every download, including the imports and includes of schemas.
+    static ref POLICY: RwLock<HostPolicy> = RwLock::new(HostPolicy::default());
+}
+
+/// Download schemas by `policy` from now on. Must be called before the first
+/// download.
+pub fn set_policy(policy: HostPolicy) {
+    *POLICY.write().unwrap() = policy;
+}
+
+/// Why a schema may not be downloaded by the policy of `set_policy`, if it may not.
+pub fn refusal(url: &str) -> Option<String> {
+    POLICY.read().unwrap().refusal(url)
+}
+
+/// Which hosts schemas may be downloaded from. A host matches itself and its
+/// subdomains.
+#[derive(Clone, Default)]
+pub struct HostPolicy {
+    /// Every host is allowed if `None`.
+    allowed: Option<Vec<String>>,
+    blocked: Vec<String>,
+    /// Whether addresses that are not public are blocked.
+    block_private: bool,
+    /// Whether every remote schema is blocked.
+    block_remote: bool,
+}
+
+impl HostPolicy {
+    /// Parse the comma-separated lists of `--allowed-hosts` and `--blocked-hosts`.
+    pub fn new(
+        allowed: Option<&str>,
+        blocked: Option<&str>,
+        block_private: bool,
+        block_remote: bool,
+    ) -> HostPolicy {
+        let hosts = |list: &str| {
+            list.split(',')
+                .map(|host| host.trim().to_ascii_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect()
+        };
+        HostPolicy {
+            allowed: allowed.map(hosts),
+            blocked: blocked.map(hosts).unwrap_or_default(),
+            block_private,
+            block_remote,
+        }
+    }
+
+    /// Why a schema may not be downloaded, if it may not. Local schemas always may.
+    pub fn refusal(&self, url: &str) -> Option<String> {
+        if !crate::is_remote(url) {
+            return None;
+        }
+        if self.block_remote {
+            return Some("remote schemas are disabled by --no-remote-schemas".to_owned());
+        }
+        let Some(host) = url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host().map(|host| host.to_owned()))
+        else {
+            return Some(format!("{url} has no host"));
+        };
+        // Names are checked when they are resolved for the download.
+        let ip = match host {
+            url::Host::Ipv4(ip) => Some(IpAddr::V4(ip)),
+            url::Host::Ipv6(ip) => Some(IpAddr::V6(ip)),
+            url::Host::Domain(_) => None,
+        };
+        if self.block_private && ip.is_some_and(is_private) {
+            return Some(format!("{host} is a private address"));
+        }
+        let is_domain = ip.is_none();
+        let host = host.to_string().to_ascii_lowercase();
+        // IP addresses have no subdomains.
+        let matches = |pattern: &String| {
+            host == *pattern
+                || is_domain
+                    && host
+                        .strip_suffix(pattern.as_str())
+                        .is_some_and(|prefix| prefix.ends_with('.'))
+        };
+        if self.blocked.iter().any(matches) {
+            Some(format!("{host} is blocked by --blocked-hosts"))
+        } else if self
+            .allowed
+            .as_ref()
+            .is_some_and(|allowed| !allowed.iter().any(matches))
+        {
+            Some(format!("{host} is not in --allowed-hosts"))
+        } else {
+            None
+        }
+    }
+}
+
+/// The minimum time between requests to the same host, in milliseconds.
+static HOST_DELAY_MS: AtomicU64 = AtomicU64::new(0);
+
+lazy_static::lazy_static! {
+    /// When each host was last sent a request, or is to be sent the next one.
+    static ref LAST_REQUEST: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+}
+
+/// Wait at least `delay` between requests to the same host from now on.
+pub fn set_host_delay(delay: Duration) {
+    HOST_DELAY_MS.store(delay.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Wait until a request may be sent to the host of `url`, and count it as sent.
+pub fn wait_for_host(url: &str) {
+    let delay = Duration::from_millis(HOST_DELAY_MS.load(Ordering::Relaxed));
+    if delay.is_zero() {
+        return;
+    }
+    let Some(host) = url::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_owned))
+    else {
+        return;
+    };
+    // Each request reserves its time under the lock, so that requests to a host
+    // are spaced out without blocking requests to other hosts while waiting.
+    let now = Instant::now();
+    let start = {
+        let mut last_request = LAST_REQUEST.lock().unwrap();
+        let start = last_request
+            .get(&host)
+            .map_or(now, |&last| (last + delay).max(now));
+        last_request.insert(host.clone(), start);
+        start
+    };
+    let wait = start - now;
+    if !wait.is_zero() {
+        tracing::debug!(
+            "Waiting {} ms before the next request to {host}",
+            wait.as_millis()
+        );
+        thread::sleep(wait);
+    }
+}
+
+/// Parse an HTTP date in the preferred format, e.g. "Sun, 06 Nov 1994 08:49:37 GMT".
+pub fn parse_http_date(text: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let mut fields = text.split_whitespace().skip(1);
+    let day: u64 = fields.next()?.parse().ok()?;
+    let month_name = fields.next()?;
+    let month = MONTHS.iter().position(|&month| month == month_name)? as u64 + 1;
+    let year: u64 = fields.next()?.parse().ok()?;
+    let mut time = fields
+        .next()?
+        .split(':')
+        .map(|field| field.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if fields.next()? != "GMT" || year < 1970 {
+        return None;
+    }
+    // Days since the epoch of a proleptic Gregorian date, counting years from March
+    // so that leap days come last.
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era_year = year % 400;
+    let day_of_era =
+        era_year * 365 + era_year / 4 - era_year / 100 + (153 * month + 2) / 5 + day - 1;
+    let days = (year / 400) * 146_097 + day_of_era - 719_468;
+    let seconds = ((days * 24 + hour) * 60 + minute) * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Whether an address is loopback, private, link-local or otherwise not public.
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || a == 100 && (64..128).contains(&b)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    // Unique local, fc00::/7, and link-local, fe80::/10.
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+/// The most redirects followed for one download, as by default.
+const MAX_REDIRECTS: usize = 10;
+
+/// The client for downloading schemas by the policy of `set_policy`.
+pub fn client() -> Client {
+    client_for(POLICY.read().unwrap().clone())
+}
+
+/// The client for downloading schemas by `policy`. Each redirect is checked like the
+/// first URL, so that it cannot lead to a blocked host or a private address, and
+/// names are checked after they are resolved, at every hop, so that a public name
+/// cannot lead to a private address either.
+///
+/// Hosts reached through a proxy are resolved by the proxy instead.
+fn client_for(policy: HostPolicy) -> Client {
+    let block_private = policy.block_private;
+    let redirects = redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if let Some(reason) = policy.refusal(attempt.url().as_str()) {
+            let error = format!("not following the redirect to {}: {reason}", attempt.url());
+            attempt.error(error)
+        } else {
+            attempt.follow()
+        }
+    });
+    let builder = Client::builder().redirect(redirects);
+    let builder = if block_private {
+        builder.dns_resolver(Arc::new(PublicResolver))
+    } else {
+        builder
+    };
+    builder.build().expect("failed to create the HTTP client")
+}
+
+/// Resolves names to their public addresses only.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        // Resolved right away: downloads run one at a time anyway.
+        let resolved = (name.as_str(), 0).to_socket_addrs().and_then(|addrs| {
+            let public: Vec<SocketAddr> = addrs.filter(|addr| !is_private(addr.ip())).collect();
+            if public.is_empty() {
+                Err(io::Error::other(format!(
+                    "{} resolves to private addresses only",
+                    name.as_str()
+                )))
+            } else {
+                Ok(Box::new(public.into_iter()) as Addrs)
+            }
+        });
+        Box::pin(async move { resolved.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    /// Serve `responses` in turn on a local port, one per connection, returning the
+    /// server's URL.
+    fn serve(responses: Vec<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for (response, stream) in responses.into_iter().zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok_and(|len| len > 2) {
+                    line.clear();
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    fn redirect_to(location: &str) -> String {
+        format!("HTTP/1.1 302 Found\r\nLocation: {location}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+    }
+
+    fn ok(body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[test]
+    fn refusals() {
+        let policy = HostPolicy::new(
+            Some("example.org, 10.1.2.3"),
+            Some("bad.example.org"),
+            true,
+            false,
+        );
+        assert_eq!(policy.refusal("schemas/note.xsd"), None);
+        assert_eq!(policy.refusal("https://example.org/note.xsd"), None);
+        assert_eq!(policy.refusal("https://www.EXAMPLE.org/note.xsd"), None);
+        assert!(policy
+            .refusal("https://bad.example.org/note.xsd")
+            .unwrap()
+            .contains("--blocked-hosts"));
+        assert!(policy
+            .refusal("https://sub.bad.example.org/note.xsd")
+            .unwrap()
+            .contains("--blocked-hosts"));
+        assert!(policy
+            .refusal("https://notexample.org/note.xsd")
+            .unwrap()
+            .contains("--allowed-hosts"));
+        // Private even if allowed.
+        assert!(policy
+            .refusal("http://10.1.2.3/note.xsd")
+            .unwrap()
+            .contains("private address"));
+        assert!(policy
+            .refusal("http://[::ffff:127.0.0.1]/note.xsd")
+            .unwrap()
+            .contains("private address"));
+
+        let no_remote = HostPolicy::new(None, None, false, true);
+        assert_eq!(no_remote.refusal("file:///schemas/note.xsd"), None);
+        assert!(no_remote
+            .refusal("https://example.org/note.xsd")
+            .unwrap()
+            .contains("--no-remote-schemas"));
+    }
+
+    #[test]
+    fn follows_allowed_redirects() {
+        let url = serve(vec![redirect_to("/moved.xsd"), ok("<schema/>")]);
+        let client = client_for(HostPolicy::new(Some("127.0.0.1"), None, false, false));
+        let response = client.get(format!("{url}/note.xsd")).send().unwrap();
+        assert_eq!(response.url().path(), "/moved.xsd");
+        assert_eq!(response.text().unwrap(), "<schema/>");
+    }
+
+    #[test]
+    fn refuses_redirects_to_blocked_hosts() {
+        let url = serve(vec![redirect_to("http://blocked.invalid/note.xsd")]);
+        let client = client_for(HostPolicy::new(None, Some("blocked.invalid"), false, false));
+        let error = client.get(format!("{url}/note.xsd")).send().unwrap_err();
+        assert!(error.is_redirect());
+        assert!(format!("{error:?}").contains("blocked.invalid is blocked by --blocked-hosts"));
+    }
+
+    #[test]
+    fn refuses_redirects_to_private_addresses() {
+        let policy = HostPolicy::new(None, None, true, false);
+        // The test server is private too, but is reached by address, which only
+        // `refusal` checks before a download starts.
+        let url = serve(vec![redirect_to(
+            "http://169.254.169.254/latest/meta-data/",
+        )]);
+        let error = client_for(policy.clone())
+            .get(format!("{url}/note.xsd"))
+            .send()
+            .unwrap_err();
+        assert!(format!("{error:?}").contains("169.254.169.254 is a private address"));
+
+        let port = url.rsplit(':').next().unwrap();
+        let url = serve(vec![redirect_to(&format!("http://localhost:{port}/"))]);
+        let error = client_for(policy)
+            .get(format!("{url}/note.xsd"))
+            .send()
+            .unwrap_err();
+        assert!(format!("{error:?}").contains("resolves to private addresses only"));
+    }
+}
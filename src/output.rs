@@ -0,0 +1,1032 @@
+//! Reporting of validation results.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tempfile::NamedTempFile;
+
+use crate::template::Template;
+use crate::{
+    codes, gzip, progress, schema_key, schema_loads, ErrorDetail, FileResult, SchemaLoad,
+    SchemaSource, Severity, Status,
+};
+
+/// How results are reported.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Format {
+    Human,
+    Json,
+    Ndjson,
+    Sarif,
+    Gitlab,
+    Schemas,
+    /// Lines from `--format-template` and `--summary-template`.
+    Template,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            "sarif" => Ok(Format::Sarif),
+            "gitlab" => Ok(Format::Gitlab),
+            "schemas" => Ok(Format::Schemas),
+            "template" => Ok(Format::Template),
+            _ => Err(format!("unknown report format {s:?}")),
+        }
+    }
+}
+
+impl Format {
+    /// Whether results are written as soon as each file has been checked.
+    pub fn is_streaming(self) -> bool {
+        matches!(self, Format::Human | Format::Ndjson | Format::Template)
+    }
+}
+
+/// Whether the human format uses ANSI colors.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    /// Only on a terminal, and not if the `NO_COLOR` environment variable is set.
+    Auto,
+    Always,
+    Never,
+}
+
+/// The fields of `--format-template`, filled in for each file.
+pub const FILE_FIELDS: &[&str] = &[
+    "path",
+    "status",
+    "error_count",
+    "first_error",
+    "first_error_code",
+    "root_element",
+    "root_namespace",
+    "schemas",
+    "duration_ms",
+];
+
+/// The fields of `--summary-template`, filled in once at the end of the run.
+pub const SUMMARY_FIELDS: &[&str] = &[
+    "files",
+    "valid",
+    "invalid",
+    "malformed",
+    "internal_error",
+    "no_schema",
+    "removed",
+    "error_count",
+    "suppressed",
+];
+
+/// The templates of the template format; either may be left out.
+#[derive(Default)]
+pub struct Templates {
+    pub file: Option<Template>,
+    pub summary: Option<Template>,
+}
+
+/// Writes the report in any number of formats, each to its own destination.
+///
+/// Human, ndjson and template results are written as soon as each file has been
+/// checked; the other formats are written by `finish`, sorted by path.
+pub struct OutputWriter {
+    require_schema: bool,
+    /// Whether the human format shows source lines and ends with a summary of the run.
+    verbose: bool,
+    /// How many lines around each error's line the human format shows when verbose.
+    context: usize,
+    color: Color,
+    templates: Templates,
+    /// The directory results are relative to, and how many levels of it the human
+    /// format's per-directory summary groups by, if wanted.
+    directories: Option<(PathBuf, usize)>,
+    /// Whether the human format leaves out files that do not fail the run.
+    quiet: bool,
+    sinks: Vec<Sink>,
+    /// Results kept for the formats written by `finish`.
+    results: Vec<FileResult>,
+}
+
+/// One report format and where it goes.
+///
+/// A report file is written to a temporary file next to it and renamed into place
+/// by `finish`, so it never holds a partial report. The temporary file is removed
+/// when the sink is dropped without finishing.
+struct Sink {
+    format: Format,
+    /// The report file and the temporary file written in its place.
+    file: Option<(PathBuf, BufWriter<NamedTempFile>)>,
+    /// Whether to write ANSI colors.
+    color: bool,
+}
+
+impl OutputWriter {
+    pub fn new(require_schema: bool, verbose: bool, color: Color) -> Self {
+        OutputWriter {
+            require_schema,
+            verbose,
+            context: 0,
+            color,
+            templates: Templates::default(),
+            directories: None,
+            quiet: false,
+            sinks: Vec::new(),
+            results: Vec::new(),
+        }
+    }
+
+    /// Use these templates for the template format.
+    pub fn set_templates(&mut self, templates: Templates) {
+        self.templates = templates;
+    }
+
+    /// Show this many lines around each error's line in the verbose human format.
+    pub fn set_context(&mut self, lines: usize) {
+        self.context = lines;
+    }
+
+    /// Leave files that do not fail the run out of the human format.
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// End the human format with counts per directory under `root`, `depth` levels deep.
+    pub fn summarize_directories(&mut self, root: &Path, depth: usize) {
+        self.directories = Some((root.to_owned(), depth));
+    }
+
+    /// Also report in `format`, to the given file or else the format's standard stream.
+    /// Creates the file's missing parent directories.
+    pub fn add_sink(&mut self, format: Format, path: Option<&Path>) -> io::Result<()> {
+        let file = match path {
+            None => None,
+            Some(path) => Some((path.to_owned(), BufWriter::new(temp_file_for(path)?))),
+        };
+        let color = format == Format::Human
+            && match self.color {
+                Color::Always => true,
+                Color::Never => false,
+                Color::Auto => {
+                    file.is_none()
+                        && io::stderr().is_terminal()
+                        && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                }
+            };
+        self.sinks.push(Sink {
+            format,
+            file,
+            color,
+        });
+        Ok(())
+    }
+
+    /// Report the result of one checked file, and whether it fails the run by
+    /// `--fail-on` and the baseline, as the exit status does.
+    pub fn add(&mut self, result: FileResult, fails: bool) -> io::Result<()> {
+        for sink in &mut self.sinks {
+            match sink.format {
+                Format::Human if self.quiet && !fails => {}
+                Format::Human => {
+                    let color = sink.color;
+                    let context = self.verbose.then_some(self.context);
+                    sink.write(|out| {
+                        write_human(out, &result, self.require_schema, context, color)
+                    })?
+                }
+                Format::Ndjson => sink.write(|out| write_ndjson(out, &result))?,
+                Format::Template => {
+                    if let Some(template) = &self.templates.file {
+                        sink.write(|out| write_file_template(out, template, &result))?
+                    }
+                }
+                Format::Json | Format::Sarif | Format::Gitlab | Format::Schemas => {}
+            }
+        }
+        if self.sinks.iter().any(|sink| match sink.format {
+            Format::Human => self.verbose || self.directories.is_some(),
+            Format::Template => self.templates.summary.is_some(),
+            format => !format.is_streaming(),
+        }) {
+            self.results.push(result);
+        }
+        Ok(())
+    }
+
+    /// Drop the reports, removing the report files' temporary files, e.g. before
+    /// exiting on an error, which skips destructors.
+    pub fn discard(&mut self) {
+        self.sinks.clear();
+    }
+
+    /// Write the kept results and move the report files into place.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.results.sort_by(|a, b| a.path.cmp(&b.path));
+        let results = &self.results;
+        let require_schema = self.require_schema;
+        for mut sink in self.sinks {
+            match sink.format {
+                Format::Human => {
+                    if self.verbose {
+                        sink.write(|out| write_summary(out, results))?;
+                    }
+                    if let Some((root, depth)) = &self.directories {
+                        sink.write(|out| {
+                            write_directories(out, results, root, *depth, require_schema)
+                        })?;
+                    }
+                }
+                Format::Ndjson => {}
+                Format::Json => sink.write(|out| write_json(out, results))?,
+                Format::Sarif => sink.write(|out| write_sarif(out, results, require_schema))?,
+                Format::Gitlab => sink.write(|out| write_gitlab(out, results, require_schema))?,
+                Format::Schemas => sink.write(|out| write_schemas(out, results))?,
+                Format::Template => {
+                    if let Some(template) = &self.templates.summary {
+                        sink.write(|out| write_summary_template(out, template, results))?
+                    }
+                }
+            }
+            if let Some((path, temp_file)) = sink.file {
+                let temp_file = temp_file.into_inner().map_err(|e| e.into_error())?;
+                temp_file.persist(path).map_err(|e| e.error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A temporary file next to `path`, to be persisted as `path` once it is complete so
+/// that no partial report is ever left there, and removed if it is dropped instead.
+/// Creates the missing parent directories of `path`.
+pub fn temp_file_for(path: &Path) -> io::Result<NamedTempFile> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut prefix = path.file_name().unwrap_or_default().to_owned();
+    prefix.push(".");
+    tempfile::Builder::new()
+        .prefix(&prefix)
+        .suffix(".tmp")
+        .tempfile_in(match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        })
+}
+
+impl Sink {
+    /// Write to the report file, or else to stderr for the human format and stdout for the others.
+    fn write<F: FnOnce(&mut dyn Write) -> io::Result<()>>(&mut self, write: F) -> io::Result<()> {
+        match &mut self.file {
+            Some((_, temp_file)) => write(temp_file),
+            None if self.format == Format::Human => {
+                progress::suspend(|| write(&mut io::stderr().lock()))
+            }
+            None => write(&mut io::stdout().lock()),
+        }
+    }
+}
+
+/// ANSI escape sequences for the human format.
+pub const GREEN: &str = "\x1b[32m";
+pub const YELLOW: &str = "\x1b[33m";
+pub const RED: &str = "\x1b[31m";
+pub const BOLD_RED: &str = "\x1b[1;31m";
+pub const RESET: &str = "\x1b[0m";
+
+/// Write one result as lines of text, optionally colored by outcome. Given a
+/// number of context lines, each error is followed by its source line and as many
+/// lines around it, and by a hint if there is one.
+fn write_human(
+    out: &mut dyn Write,
+    result: &FileResult,
+    require_schema: bool,
+    context: Option<usize>,
+    color: bool,
+) -> io::Result<()> {
+    // Documents read from standard input cannot be read again.
+    let source = context
+        .filter(|_| !result.errors.is_empty() && result.path != "-")
+        .and_then(|_| gzip::read(Path::new(&result.path)).ok())
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+    let lines: Vec<&str> = source
+        .as_deref()
+        .map_or(Vec::new(), |s| s.lines().collect());
+    for error in &result.errors {
+        if color {
+            writeln!(out, "{error:#}")?;
+        } else {
+            writeln!(out, "{error}")?;
+        }
+        let in_document = error.file.as_deref().is_none_or(|file| file == result.path);
+        if let (Some(context), true) = (context, in_document) {
+            write_snippet(out, &lines, error, context, color)?;
+        }
+        if let (Some(hint), Some(_)) = (&error.hint, context) {
+            writeln!(out, "  hint: {hint}")?;
+        }
+    }
+    let (outcome, ansi) = match result.status {
+        Status::Valid => ("validates", GREEN),
+        Status::Invalid => ("fails to validate", RED),
+        Status::InternalError => ("validation generated an internal error", RED),
+        // Note: the message is output after the parser messages.
+        Status::Malformed => ("is not well-formed", RED),
+        Status::NoSchema if require_schema => ("fails: no schema reference", RED),
+        Status::NoSchema => ("skipped: no schema reference", YELLOW),
+        Status::Removed => ("skipped: removed during the run", YELLOW),
+    };
+    let mut known = if result.baselined {
+        " (known failure)".to_owned()
+    } else {
+        String::new()
+    };
+    if result.suppressed > 0 {
+        known += &format!(" ({} suppressed)", result.suppressed);
+    }
+    if color {
+        writeln!(out, "{} {ansi}{outcome}{RESET}{known}", result.path)
+    } else {
+        writeln!(out, "{} {outcome}{known}", result.path)
+    }
+}
+
+/// Write the line of an error with `context` lines around it, and a caret under the
+/// error's column, or under the start of the line if the column is unknown.
+fn write_snippet(
+    out: &mut dyn Write,
+    lines: &[&str],
+    error: &ErrorDetail,
+    context: usize,
+    color: bool,
+) -> io::Result<()> {
+    let line = error.line as usize;
+    if line == 0 || line > lines.len() {
+        return Ok(());
+    }
+    let first = line.saturating_sub(context).max(1);
+    let last = (line + context).min(lines.len());
+    let width = last.to_string().len();
+    for number in first..=last {
+        let text = lines[number - 1];
+        writeln!(out, "{number:>width$} | {text}")?;
+        if number == line {
+            let column = match error.column {
+                column if column > 0 => column as usize - 1,
+                _ => text.len() - text.trim_start().len(),
+            };
+            // Keep tabs so that the caret lines up with the text above it.
+            let indent: String = text
+                .chars()
+                .take(column)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let (start, end) = if color { (BOLD_RED, RESET) } else { ("", "") };
+            writeln!(out, "{:width$} | {indent}{start}^{end}", "")?;
+        }
+    }
+    Ok(())
+}
+
+/// How many files to list as the slowest and as those with the most errors.
+const TOP_FILES: usize = 10;
+
+/// Write counts per status and the files that took longest or had the most errors.
+fn write_summary(out: &mut dyn Write, results: &[FileResult]) -> io::Result<()> {
+    let totals = Totals::of(results);
+    writeln!(
+        out,
+        "Checked {} files: {} valid, {} invalid, {} malformed, {} internal errors, {} without schema",
+        totals.files,
+        totals.valid,
+        totals.invalid,
+        totals.malformed,
+        totals.internal_error,
+        totals.no_schema,
+    )?;
+    if totals.removed > 0 {
+        writeln!(
+            out,
+            "Skipped {} files removed during the run",
+            totals.removed
+        )?;
+    }
+    if totals.suppressed > 0 {
+        writeln!(out, "Suppressed {} errors", totals.suppressed)?;
+    }
+
+    let mut slowest: Vec<&FileResult> = results.iter().collect();
+    slowest.sort_by(|a, b| b.duration_ms.total_cmp(&a.duration_ms));
+    writeln!(out, "Slowest files:")?;
+    for result in slowest.iter().take(TOP_FILES) {
+        writeln!(out, "  {:10.1} ms  {}", result.duration_ms, result.path)?;
+    }
+
+    let mut most_errors: Vec<&FileResult> = results
+        .iter()
+        .filter(|result| !result.errors.is_empty())
+        .collect();
+    most_errors.sort_by_key(|result| std::cmp::Reverse(error_count(result)));
+    if !most_errors.is_empty() {
+        writeln!(out, "Most errors:")?;
+        for result in most_errors.iter().take(TOP_FILES) {
+            writeln!(out, "  {:10}     {}", error_count(result), result.path)?;
+        }
+    }
+
+    let loads = schema_loads();
+    if !loads.is_empty() {
+        writeln!(out, "Schema loads (fetch + parse):")?;
+        for load in &loads {
+            let mut origin = match load.source {
+                SchemaSource::Remote => "from the network".to_owned(),
+                SchemaSource::Local => "from a local file".to_owned(),
+            };
+            if let Some(age) = load.age_seconds {
+                origin += &format!(", changed {} ago", human_age(age));
+            }
+            // The first file that needs a schema loads it; the others reuse it.
+            let files = results
+                .iter()
+                .filter(|result| {
+                    result
+                        .schemas
+                        .iter()
+                        .any(|url| schema_key(url.clone()) == load.url)
+                })
+                .count();
+            if files > 1 {
+                origin += &format!(", reused from memory for {} files", files - 1);
+            }
+            writeln!(
+                out,
+                "  {:10.1} ms + {:.1} ms  {} ({origin})",
+                load.fetch_ms, load.parse_ms, load.url
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// A duration in seconds in its largest whole unit, e.g. "3 days".
+fn human_age(seconds: f64) -> String {
+    const UNITS: [(&str, f64); 4] = [
+        ("day", 86_400.0),
+        ("hour", 3_600.0),
+        ("minute", 60.0),
+        ("second", 1.0),
+    ];
+    let (unit, size) = UNITS
+        .into_iter()
+        .find(|&(_, size)| seconds >= size)
+        .unwrap_or(UNITS[3]);
+    let count = (seconds / size) as u64;
+    format!("{count} {unit}{}", if count == 1 { "" } else { "s" })
+}
+
+/// The number of errors in a result, counting each repeat of a folded error.
+fn error_count(result: &FileResult) -> usize {
+    // Less the marker standing for the omitted errors.
+    let marker = usize::from(result.omitted_errors > 0);
+    result
+        .errors
+        .iter()
+        .map(|error| error.occurrences)
+        .sum::<usize>()
+        - marker
+        + result.omitted_errors
+}
+
+/// Write one result as a line from `--format-template`.
+fn write_file_template(
+    out: &mut dyn Write,
+    template: &Template,
+    result: &FileResult,
+) -> io::Result<()> {
+    let line = template.render(|field| match field {
+        "path" => result.path.clone(),
+        "status" => result.status.name().to_owned(),
+        "error_count" => error_count(result).to_string(),
+        "first_error" => result
+            .errors
+            .first()
+            .map(|error| error.message.clone())
+            .unwrap_or_default(),
+        "first_error_code" => result
+            .errors
+            .first()
+            .map(|error| error.error_code.to_owned())
+            .unwrap_or_default(),
+        "root_element" => result.root_element.clone().unwrap_or_default(),
+        "root_namespace" => result.root_namespace.clone().unwrap_or_default(),
+        "schemas" => result.schemas.join(","),
+        "duration_ms" => format!("{:.1}", result.duration_ms),
+        _ => unreachable!("template field {field} was checked when parsing"),
+    });
+    writeln!(out, "{line}")
+}
+
+/// Write the counts of the run as a line from `--summary-template`.
+fn write_summary_template(
+    out: &mut dyn Write,
+    template: &Template,
+    results: &[FileResult],
+) -> io::Result<()> {
+    let totals = Totals::of(results);
+    let line = template.render(|field| {
+        match field {
+            "files" => totals.files,
+            "valid" => totals.valid,
+            "invalid" => totals.invalid,
+            "malformed" => totals.malformed,
+            "internal_error" => totals.internal_error,
+            "no_schema" => totals.no_schema,
+            "removed" => totals.removed,
+            "error_count" => totals.error_count,
+            "suppressed" => totals.suppressed,
+            _ => unreachable!("template field {field} was checked when parsing"),
+        }
+        .to_string()
+    });
+    writeln!(out, "{line}")
+}
+
+/// Counts of the files in one directory, for the per-directory summary.
+#[derive(Default)]
+struct DirectoryCounts {
+    files: usize,
+    valid: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+/// Write pass and fail counts per directory under `root`, grouping files by the first
+/// `depth` directories of their path.
+fn write_directories(
+    out: &mut dyn Write,
+    results: &[FileResult],
+    root: &Path,
+    depth: usize,
+    require_schema: bool,
+) -> io::Result<()> {
+    let mut directories: BTreeMap<PathBuf, DirectoryCounts> = BTreeMap::new();
+    for result in results {
+        let path = Path::new(&result.path);
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let directory: PathBuf = relative
+            .parent()
+            .map(|parent| parent.components().take(depth).collect())
+            .unwrap_or_default();
+        let counts = directories.entry(directory).or_default();
+        counts.files += 1;
+        if result.status == Status::Valid {
+            counts.valid += 1;
+        } else if result.status.is_failure(require_schema) {
+            counts.failed += 1;
+        } else {
+            counts.skipped += 1;
+        }
+    }
+    writeln!(out, "By directory:")?;
+    writeln!(
+        out,
+        "  {:>8} {:>8} {:>8} {:>8}  directory",
+        "files", "valid", "failed", "skipped"
+    )?;
+    for (directory, counts) in &directories {
+        let name = if directory.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            directory
+        };
+        writeln!(
+            out,
+            "  {:8} {:8} {:8} {:8}  {}",
+            counts.files,
+            counts.valid,
+            counts.failed,
+            counts.skipped,
+            name.display()
+        )?;
+    }
+    Ok(())
+}
+
+/// Write one result as a single line of JSON.
+fn write_ndjson(out: &mut dyn Write, result: &FileResult) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, result)?;
+    writeln!(out)
+}
+
+/// Counts and durations over the files of a run.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Totals {
+    pub files: usize,
+    pub valid: usize,
+    pub invalid: usize,
+    pub malformed: usize,
+    pub internal_error: usize,
+    pub no_schema: usize,
+    /// Files removed after they were found and before they were checked.
+    #[serde(default)]
+    pub removed: usize,
+    pub error_count: usize,
+    pub suppressed: usize,
+    /// Total time spent checking files, over all threads.
+    pub duration_ms: f64,
+}
+
+impl Totals {
+    pub fn of(results: &[FileResult]) -> Totals {
+        let count = |status| results.iter().filter(|r| r.status == status).count();
+        Totals {
+            files: results.len(),
+            valid: count(Status::Valid),
+            invalid: count(Status::Invalid),
+            malformed: count(Status::Malformed),
+            internal_error: count(Status::InternalError),
+            no_schema: count(Status::NoSchema),
+            removed: count(Status::Removed),
+            error_count: results.iter().map(error_count).sum(),
+            suppressed: results.iter().map(|result| result.suppressed).sum(),
+            duration_ms: results.iter().map(|result| result.duration_ms).sum(),
+        }
+    }
+
+    /// Add the totals of another set of files, which must not overlap these.
+    pub fn merge(&mut self, other: &Totals) {
+        self.files += other.files;
+        self.valid += other.valid;
+        self.invalid += other.invalid;
+        self.malformed += other.malformed;
+        self.internal_error += other.internal_error;
+        self.no_schema += other.no_schema;
+        self.removed += other.removed;
+        self.error_count += other.error_count;
+        self.suppressed += other.suppressed;
+        self.duration_ms += other.duration_ms;
+    }
+}
+
+/// The json report, as written at the end of a run or read back to merge reports.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Report<'a> {
+    pub files: Cow<'a, [FileResult]>,
+    /// How long each schema took to load.
+    pub schemas: Vec<SchemaLoad>,
+    /// Missing from the reports of older versions, and then counted from the files.
+    #[serde(default)]
+    pub totals: Option<Totals>,
+}
+
+impl Report<'_> {
+    /// Combine the report of another run into this one, e.g. of another shard of
+    /// the same files or of another directory. Files are kept in order of path, and
+    /// a schema loaded by both runs is listed once, with its slower load.
+    pub fn merge(&mut self, other: Report) {
+        let mut totals = self.totals();
+        totals.merge(&other.totals());
+        self.totals = Some(totals);
+
+        let files = self.files.to_mut();
+        files.extend(other.files.into_owned());
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        for load in other.schemas {
+            match self.schemas.iter_mut().find(|known| known.url == load.url) {
+                Some(known) if known.fetch_ms + known.parse_ms < load.fetch_ms + load.parse_ms => {
+                    *known = load
+                }
+                Some(_) => {}
+                None => self.schemas.push(load),
+            }
+        }
+        self.schemas.sort_by(|a, b| a.url.cmp(&b.url));
+    }
+
+    fn totals(&self) -> Totals {
+        self.totals
+            .clone()
+            .unwrap_or_else(|| Totals::of(&self.files))
+    }
+}
+
+/// Write all results as one JSON document.
+fn write_json(out: &mut dyn Write, results: &[FileResult]) -> io::Result<()> {
+    serde_json::to_writer_pretty(
+        &mut *out,
+        &Report {
+            files: Cow::Borrowed(results),
+            schemas: schema_loads(),
+            totals: Some(Totals::of(results)),
+        },
+    )?;
+    writeln!(out)
+}
+
+/// The rule id and description of a failure that concerns a whole file rather than one error.
+fn file_level_issue(status: Status, require_schema: bool) -> Option<(&'static str, &'static str)> {
+    let code = match status {
+        Status::Malformed => codes::MALFORMED,
+        Status::InternalError => codes::INTERNAL_ERROR,
+        Status::NoSchema if require_schema => codes::NO_SCHEMA,
+        _ => return None,
+    };
+    Some((code.id, code.description))
+}
+
+/// Write all results as a SARIF 2.1.0 log, e.g. for GitHub code scanning.
+fn write_sarif(
+    out: &mut dyn Write,
+    results: &[FileResult],
+    require_schema: bool,
+) -> io::Result<()> {
+    let mut sarif_results = Vec::new();
+    for result in results {
+        for error in &result.errors {
+            sarif_results.push(sarif_error(&result.path, error));
+        }
+        if let Some((rule_id, text)) = file_level_issue(result.status, require_schema) {
+            sarif_results.push(json!({
+                "ruleId": rule_id,
+                "level": "error",
+                "message": { "text": text },
+                "locations": [sarif_location(&result.path, None)],
+            }));
+        }
+    }
+
+    let log = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/FranklinChen/validate-xml-rust",
+                    "rules": codes::CATALOG.iter().map(|code| json!({
+                        "id": code.id,
+                        "name": code.name,
+                        "shortDescription": { "text": code.description },
+                    })).collect::<Vec<_>>(),
+                },
+            },
+            "results": sarif_results,
+        }],
+    });
+    serde_json::to_writer_pretty(&mut *out, &log)?;
+    writeln!(out)
+}
+
+/// A SARIF result for one libxml2 diagnostic, identified by its error code.
+fn sarif_error(path: &str, error: &ErrorDetail) -> Value {
+    json!({
+        "ruleId": error.error_code,
+        "level": match error.severity {
+            Severity::Warning => "warning",
+            Severity::Error | Severity::Fatal => "error",
+        },
+        "message": { "text": error.message },
+        "locations": [sarif_location(path, Some(error))],
+    })
+}
+
+/// A SARIF location in `path`, narrowed to the error's line and column when known.
+fn sarif_location(path: &str, error: Option<&ErrorDetail>) -> Value {
+    let mut location = json!({
+        "physicalLocation": {
+            "artifactLocation": { "uri": path.replace('\\', "/") },
+        },
+    });
+    if let Some(error) = error.filter(|error| error.line > 0) {
+        let mut region = json!({ "startLine": error.line });
+        if error.column > 0 {
+            region["startColumn"] = json!(error.column);
+        }
+        location["physicalLocation"]["region"] = region;
+    }
+    location
+}
+
+/// Write all results as a GitLab Code Quality report, for a `codequality` CI artifact.
+fn write_gitlab(
+    out: &mut dyn Write,
+    results: &[FileResult],
+    require_schema: bool,
+) -> io::Result<()> {
+    let mut issues = Vec::new();
+    for result in results {
+        for error in &result.errors {
+            let severity = match error.severity {
+                Severity::Warning => "minor",
+                Severity::Error => "major",
+                Severity::Fatal => "critical",
+            };
+            issues.push(gitlab_issue(
+                &result.path,
+                error.error_code,
+                &error.message,
+                severity,
+                error.line,
+            ));
+        }
+        if let Some((check_name, description)) = file_level_issue(result.status, require_schema) {
+            issues.push(gitlab_issue(
+                &result.path,
+                check_name,
+                description,
+                "major",
+                0,
+            ));
+        }
+    }
+
+    serde_json::to_writer_pretty(&mut *out, &issues)?;
+    writeln!(out)
+}
+
+/// A Code Quality issue, whose fingerprint identifies it across pipelines.
+fn gitlab_issue(
+    path: &str,
+    check_name: &str,
+    description: &str,
+    severity: &str,
+    line: i32,
+) -> Value {
+    let mut hasher = Sha256::new();
+    for part in [path, check_name, description, &line.to_string()] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    let fingerprint: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    json!({
+        "description": description,
+        "check_name": check_name,
+        "fingerprint": fingerprint,
+        "severity": severity,
+        "location": {
+            "path": path,
+            "lines": { "begin": line.max(1) },
+        },
+    })
+}
+
+/// How the files referencing one schema fared.
+#[derive(Default, Serialize)]
+struct SchemaSummary<'a> {
+    schema: &'a str,
+    files: usize,
+    valid: usize,
+    invalid: usize,
+    internal_error: usize,
+    most_common_errors: Vec<ErrorCount<'a>>,
+}
+
+/// An error message and how often it occurred.
+#[derive(Serialize)]
+struct ErrorCount<'a> {
+    message: &'a str,
+    count: usize,
+}
+
+/// How many of the most frequent errors to list per schema.
+const MOST_COMMON_ERRORS: usize = 5;
+
+/// Write a JSON summary per schema, so schema owners can see which schemas cause failures.
+fn write_schemas(out: &mut dyn Write, results: &[FileResult]) -> io::Result<()> {
+    let mut summaries: BTreeMap<&str, SchemaSummary> = BTreeMap::new();
+    let mut error_counts: HashMap<&str, HashMap<&str, usize>> = HashMap::new();
+    for result in results {
+        for schema in &result.schemas {
+            let summary = summaries.entry(schema).or_default();
+            summary.schema = schema;
+            summary.files += 1;
+
+            let schema_errors = result
+                .errors
+                .iter()
+                .filter(|error| error.schema.as_ref() == Some(schema));
+            if result.status == Status::InternalError {
+                summary.internal_error += 1;
+            } else if schema_errors
+                .clone()
+                .any(|error| error.severity != Severity::Warning)
+            {
+                summary.invalid += 1;
+            } else {
+                summary.valid += 1;
+            }
+
+            let counts = error_counts.entry(schema).or_default();
+            for error in schema_errors {
+                *counts.entry(&error.message).or_default() += error.occurrences;
+            }
+        }
+    }
+
+    for (schema, counts) in error_counts {
+        let mut counts: Vec<ErrorCount> = counts
+            .into_iter()
+            .map(|(message, count)| ErrorCount { message, count })
+            .collect();
+        counts.sort_by(|a, b| b.count.cmp(&a.count).then(a.message.cmp(b.message)));
+        counts.truncate(MOST_COMMON_ERRORS);
+        summaries.get_mut(schema).unwrap().most_common_errors = counts;
+    }
+
+    let summaries: Vec<&SchemaSummary> = summaries.values().collect();
+    serde_json::to_writer_pretty(&mut *out, &summaries)?;
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(url: &str, fetch_ms: f64) -> SchemaLoad {
+        SchemaLoad {
+            url: url.to_owned(),
+            source: SchemaSource::Remote,
+            bytes: 100,
+            fetch_ms,
+            parse_ms: 1.0,
+            age_seconds: None,
+        }
+    }
+
+    #[test]
+    fn merge_reports() {
+        let mut report = Report {
+            files: Cow::Owned(vec![
+                FileResult::new("b.xml", Status::Valid),
+                FileResult::new("d.xml", Status::Invalid),
+            ]),
+            schemas: vec![load("https://a/s.xsd", 5.0), load("https://b/s.xsd", 5.0)],
+            totals: Some(Totals {
+                files: 2,
+                valid: 1,
+                invalid: 1,
+                error_count: 3,
+                duration_ms: 10.0,
+                ..Totals::default()
+            }),
+        };
+        // As written by older versions, without totals.
+        report.merge(Report {
+            files: Cow::Owned(vec![
+                FileResult::new("c.xml", Status::Malformed),
+                FileResult::new("a.xml", Status::Valid),
+            ]),
+            schemas: vec![load("https://b/s.xsd", 9.0), load("https://a/s.xsd", 2.0)],
+            totals: None,
+        });
+
+        let paths: Vec<_> = report.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, ["a.xml", "b.xml", "c.xml", "d.xml"]);
+        let totals = report.totals.as_ref().unwrap();
+        assert_eq!(
+            (totals.files, totals.valid, totals.invalid, totals.malformed),
+            (4, 2, 1, 1)
+        );
+        assert_eq!((totals.error_count, totals.duration_ms), (3, 10.0));
+        let schemas: Vec<_> = report
+            .schemas
+            .iter()
+            .map(|load| (load.url.as_str(), load.fetch_ms))
+            .collect();
+        assert_eq!(
+            schemas,
+            [("https://a/s.xsd", 5.0), ("https://b/s.xsd", 9.0)]
+        );
+    }
+
+    #[test]
+    fn merge_into_empty_report() {
+        let mut report = Report::default();
+        report.merge(Report {
+            files: Cow::Owned(vec![FileResult::new("a.xml", Status::NoSchema)]),
+            ..Report::default()
+        });
+        let totals = report.totals.unwrap();
+        assert_eq!((totals.files, totals.no_schema), (1, 1));
+        assert_eq!(report.files.len(), 1);
+    }
+}
@@ -0,0 +1,23 @@
+//! Reading the start of a file, so that looking for a schema reference or the root
+//! element of a large document does not read all of it.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// How much of a file is read first.
+const FIRST_READ: u64 = 64 * 1024;
+
+/// Read the start of a file, as far as `is_enough` needs, or else all of it. Each
+/// further read doubles what has been read, so that checking what has been read
+/// takes linear time.
+pub fn read(path: &Path, is_enough: impl Fn(&[u8]) -> bool) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut content = Vec::new();
+    loop {
+        let more = (content.len() as u64).max(FIRST_READ);
+        if (&mut file).take(more).read_to_end(&mut content)? == 0 || is_enough(&content) {
+            return Ok(content);
+        }
+    }
+}
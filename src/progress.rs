@@ -0,0 +1,184 @@
+//! Progress on stderr: bars for people, or JSON Lines events for other programs.
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How progress is shown.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressFormat {
+    Bars,
+    Jsonl,
+}
+
+enum Display {
+    Bars(MultiProgress),
+    Events(Mutex<Box<dyn Write + Send>>),
+}
+
+/// Where progress goes, if it is enabled.
+static DISPLAY: OnceLock<Display> = OnceLock::new();
+
+/// Show progress bars from now on, unless stderr is not a terminal.
+pub fn enable_bars() {
+    if io::stderr().is_terminal() {
+        DISPLAY.get_or_init(|| Display::Bars(MultiProgress::new()));
+    }
+}
+
+/// Write a JSON object per progress event to `out` from now on.
+pub fn enable_events(out: Box<dyn Write + Send>) {
+    DISPLAY.get_or_init(|| Display::Events(Mutex::new(out)));
+}
+
+/// Run `write`, which writes to stderr, without garbling the bars.
+pub fn suspend<R>(write: impl FnOnce() -> R) -> R {
+    match DISPLAY.get() {
+        Some(Display::Bars(bars)) => bars.suspend(write),
+        _ => write(),
+    }
+}
+
+/// One progress event, as written by `--progress-format=jsonl`.
+#[derive(Serialize)]
+struct Event<'a> {
+    phase: &'static str,
+    /// Files checked so far, or bytes downloaded.
+    completed: u64,
+    total: Option<u64>,
+    /// The file just checked, or the schema being downloaded.
+    file: Option<&'a str>,
+    done: bool,
+    /// Seconds since the Unix epoch.
+    timestamp: f64,
+}
+
+/// Progress through one phase of the run.
+pub struct Phase {
+    name: &'static str,
+    bar: ProgressBar,
+    completed: AtomicU64,
+    total: Option<u64>,
+}
+
+impl Phase {
+    fn start(name: &'static str, bar: ProgressBar, total: Option<u64>) -> Phase {
+        let phase = Phase {
+            name,
+            bar,
+            completed: AtomicU64::new(0),
+            total,
+        };
+        phase.emit(0, None, false);
+        phase
+    }
+
+    /// Count one more item done.
+    pub fn inc(&self, file: &str) {
+        let completed = self.completed.fetch_add(1, Ordering::Relaxed) + 1;
+        self.bar.inc(1);
+        self.emit(completed, Some(file), false);
+    }
+
+    /// Set the size of a download once it is known from the response headers,
+    /// showing a bar of bytes if the download is large.
+    pub fn set_download_size(&mut self, total: Option<u64>) {
+        self.total = total;
+        if let Some(total) = total.filter(|&total| total >= LARGE_DOWNLOAD) {
+            let style = ProgressStyle::with_template(
+                "{msg} [{bar:40}] {bytes}/{total_bytes}, {bytes_per_sec}",
+            )
+            .unwrap()
+            .progress_chars("=> ");
+            self.bar.set_length(total);
+            self.bar.set_style(style);
+        }
+    }
+
+    /// Count `bytes` more of a download. Its bytes are shown once it turns out to be
+    /// large, even if its size was not known.
+    pub fn add_bytes(&self, bytes: u64) {
+        let completed = self.completed.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.bar.inc(bytes);
+        let before = completed - bytes;
+        if self.total.is_none() && before < LARGE_DOWNLOAD && completed >= LARGE_DOWNLOAD {
+            let style =
+                ProgressStyle::with_template("{spinner} {msg}: {bytes}, {bytes_per_sec}").unwrap();
+            self.bar.set_style(style);
+        }
+        // An event per step rather than per read, to keep the stream small.
+        let large = self.total.unwrap_or(completed) >= LARGE_DOWNLOAD;
+        if large && before / EVENT_STEP != completed / EVENT_STEP {
+            self.emit(completed, None, false);
+        }
+    }
+
+    /// End the phase, removing its bar.
+    pub fn finish(self, file: Option<&str>) {
+        self.bar.finish_and_clear();
+        self.emit(self.completed.load(Ordering::Relaxed), file, true);
+    }
+
+    fn emit(&self, completed: u64, file: Option<&str>, done: bool) {
+        let Some(Display::Events(out)) = DISPLAY.get() else {
+            return;
+        };
+        let event = Event {
+            phase: self.name,
+            completed,
+            total: self.total,
+            file,
+            done,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        };
+        let mut out = out.lock().unwrap();
+        // Progress is best effort: a closed reader must not stop validation.
+        let _ = serde_json::to_writer(&mut *out, &event)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(out))
+            .and_then(|()| out.flush());
+    }
+}
+
+/// Downloads of at least this many bytes show their progress in bytes rather than
+/// just a spinner.
+const LARGE_DOWNLOAD: u64 = 1 << 20;
+
+/// How many bytes of a large download make one progress event.
+const EVENT_STEP: u64 = 256 << 10;
+
+/// A phase of unknown length, such as discovery or a download, shown as a spinner.
+pub fn spinner(name: &'static str, message: String) -> Phase {
+    let bar = match DISPLAY.get() {
+        Some(Display::Bars(bars)) => {
+            let spinner = bars.add(ProgressBar::new_spinner().with_message(message));
+            spinner.enable_steady_tick(Duration::from_millis(100));
+            spinner
+        }
+        _ => ProgressBar::hidden(),
+    };
+    Phase::start(name, bar, None)
+}
+
+/// The validation phase, shown as a bar with the file rate and the estimated time left.
+pub fn files(len: usize) -> Phase {
+    let bar = match DISPLAY.get() {
+        Some(Display::Bars(bars)) => {
+            let style = ProgressStyle::with_template(
+                "Validating [{bar:40}] {pos}/{len} files, {per_sec}, ETA {eta}",
+            )
+            .unwrap()
+            .progress_chars("=> ");
+            bars.add(ProgressBar::new(len as u64).with_style(style))
+        }
+        _ => ProgressBar::hidden(),
+    };
+    Phase::start("validation", bar, Some(len as u64))
+}
@@ -0,0 +1,472 @@
+//! Custom rules, run on each parsed document after schema validation, that add
+//! errors of their own, e.g. that a file's name must match `//record/@id`.
+//!
+//! Built-in rules are given with `--rule`, and XPath assertions with `assert` in the
+//! configuration file. With the `plugins` feature, rules can also
+//! be WebAssembly modules given with `--plugin`, which export:
+//!
+//! - `memory`;
+//! - `alloc(len: i32) -> i32`, returning where to write an input of `len` bytes;
+//! - `check(ptr: i32, len: i32) -> i64`, given a JSON object with the file's
+//!   `result`, as in JSON reports, and its serialized `document`, and returning
+//!   the address of its output in the high 32 bits and its length in the low ones.
+//!
+//! The output is a JSON array of errors, each with a `message` and optionally a
+//! `line` and a `severity`, "error" (the default) or "warning".
+//!
+//! With the `scripts` feature, rules can also be Rhai scripts given with `--script`,
+//! which see the constants `path`, `status` and `root_element` and call:
+//!
+//! - `xpath(expression)`, the string value of an XPath expression;
+//! - `xpath_count(expression)`, the number of nodes it selects;
+//! - `fail(message)` and `warn(message)`, to report an error or a warning.
+
+use std::ffi::{CStr, CString};
+use std::path::Path;
+
+use libc::{c_char, c_void};
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::{
+    codes, xmlFree, xmlXPathCastToString, xmlXPathCompile, xmlXPathEvalExpression,
+    xmlXPathFreeCompExpr, xmlXPathFreeContext, xmlXPathFreeObject, xmlXPathNewContext, ErrorDetail,
+    FileResult, XmlDoc,
+};
+
+/// A check of a parsed document, which may find errors that its schemas do not.
+pub trait Rule: Send + Sync {
+    /// The errors found in a document, given the result of validating it against its
+    /// schemas. An `Err` means that the rule itself failed.
+    fn check(&self, document: &Document, result: &FileResult) -> Result<Vec<ErrorDetail>, String>;
+}
+
+/// A document parsed by libxml2, as seen by rules.
+pub struct Document {
+    doc: *mut XmlDoc,
+}
+
+impl Document {
+    /// # Safety
+    ///
+    /// `doc` must be a valid document that outlives the `Document`.
+    pub unsafe fn new(doc: *mut XmlDoc) -> Document {
+        Document { doc }
+    }
+
+    /// The string value of an XPath expression, e.g. the first attribute it selects.
+    pub fn xpath_string(&self, expression: &str) -> Result<String, String> {
+        let c_expression = CString::new(expression).map_err(|e| e.to_string())?;
+        unsafe {
+            let context = xmlXPathNewContext(self.doc);
+            let object = xmlXPathEvalExpression(c_expression.as_ptr(), context);
+            xmlXPathFreeContext(context);
+            if object.is_null() {
+                return Err(format!("cannot evaluate XPath {expression:?}"));
+            }
+            let value = xmlXPathCastToString(object);
+            xmlXPathFreeObject(object);
+            Ok(take_string(value).unwrap_or_default())
+        }
+    }
+}
+
+/// Copy and free a string allocated by libxml2.
+///
+/// # Safety
+///
+/// `s` must be null or a valid C string allocated by libxml2.
+unsafe fn take_string(s: *mut c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    let string = CStr::from_ptr(s).to_string_lossy().into_owned();
+    xmlFree(s as *mut c_void);
+    Some(string)
+}
+
+/// Parse a built-in rule given as `NAME=ARGUMENT`.
+pub fn parse(rule: &str) -> Result<Box<dyn Rule>, String> {
+    let (name, argument) = rule
+        .split_once('=')
+        .ok_or_else(|| format!("rule {rule:?} is not of the form NAME=ARGUMENT"))?;
+    match name {
+        "filename" => Ok(Box::new(FileNameMatches::new(argument)?)),
+        _ => Err(format!("unknown rule {name:?}")),
+    }
+}
+
+/// Requires a file's name, without its extensions, to equal the string value of an
+/// XPath expression.
+struct FileNameMatches {
+    xpath: String,
+}
+
+impl FileNameMatches {
+    fn new(xpath: &str) -> Result<FileNameMatches, String> {
+        check_xpath(xpath).map_err(|e| format!("rule filename: {e}"))?;
+        Ok(FileNameMatches {
+            xpath: xpath.to_owned(),
+        })
+    }
+}
+
+/// Check that an XPath expression compiles.
+fn check_xpath(xpath: &str) -> Result<(), String> {
+    let c_xpath = CString::new(xpath).map_err(|e| e.to_string())?;
+    let compiled = unsafe { xmlXPathCompile(c_xpath.as_ptr()) };
+    if compiled.is_null() {
+        return Err(format!("invalid XPath {xpath:?}"));
+    }
+    unsafe { xmlXPathFreeCompExpr(compiled) };
+    Ok(())
+}
+
+impl Rule for FileNameMatches {
+    fn check(&self, document: &Document, result: &FileResult) -> Result<Vec<ErrorDetail>, String> {
+        let file_name = Path::new(&result.path)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        // Both extensions of e.g. record.cmdi.gz.
+        let stem = file_name.split('.').next().unwrap_or_default();
+        let value = document.xpath_string(&self.xpath)?;
+        if value == stem {
+            return Ok(Vec::new());
+        }
+        Ok(vec![ErrorDetail {
+            file: Some(result.path.clone()),
+            ..ErrorDetail::new(
+                format!(
+                    "The file name {stem:?} does not match {:?}, which is {value:?}",
+                    self.xpath
+                ),
+                &codes::RULE,
+            )
+        }])
+    }
+}
+
+/// What an `assert` of the configuration file requires of an XPath expression.
+enum Expectation {
+    /// That it selects something, or nothing.
+    Exists(bool),
+    Equals(String),
+    Matches(Regex),
+}
+
+/// An `assert` of the configuration file, e.g.
+/// `{ xpath = "//record/@id", exists = true }`.
+pub struct Assertion {
+    xpath: String,
+    expectation: Expectation,
+    /// The error to report instead of the default one.
+    message: Option<String>,
+}
+
+impl Assertion {
+    pub fn new(section: &Map<String, Value>) -> Result<Assertion, String> {
+        let Some(xpath) = section.get("xpath").and_then(Value::as_str) else {
+            return Err("every assert needs an xpath".to_owned());
+        };
+        let string = |key: &str| match section.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(format!("assert {xpath:?}: {key} must be a string")),
+        };
+        check_xpath(xpath).map_err(|e| format!("assert {xpath:?}: {e}"))?;
+        if let Some(key) = section.keys().find(|key| {
+            !["xpath", "exists", "equals", "matches", "message"].contains(&key.as_str())
+        }) {
+            return Err(format!("assert {xpath:?}: unknown setting {key}"));
+        }
+        let mut expectations = Vec::new();
+        match section.get("exists") {
+            None => {}
+            Some(Value::Bool(exists)) => expectations.push(Expectation::Exists(*exists)),
+            Some(_) => return Err(format!("assert {xpath:?}: exists must be true or false")),
+        }
+        if let Some(value) = string("equals")? {
+            expectations.push(Expectation::Equals(value));
+        }
+        if let Some(pattern) = string("matches")? {
+            let regex = Regex::new(&pattern).map_err(|e| format!("assert {xpath:?}: {e}"))?;
+            expectations.push(Expectation::Matches(regex));
+        }
+        if expectations.len() > 1 {
+            return Err(format!(
+                "assert {xpath:?}: give only one of exists, equals and matches"
+            ));
+        }
+        Ok(Assertion {
+            xpath: xpath.to_owned(),
+            expectation: expectations.pop().unwrap_or(Expectation::Exists(true)),
+            message: string("message")?,
+        })
+    }
+}
+
+impl Rule for Assertion {
+    fn check(&self, document: &Document, result: &FileResult) -> Result<Vec<ErrorDetail>, String> {
+        let xpath = &self.xpath;
+        let failure = match &self.expectation {
+            Expectation::Exists(exists) => {
+                let found = document.xpath_string(&format!("boolean({xpath})"))? == "true";
+                match (found, exists) {
+                    (false, true) => Some(format!("{xpath:?} selects nothing")),
+                    (true, false) => Some(format!("{xpath:?} selects something")),
+                    _ => None,
+                }
+            }
+            Expectation::Equals(expected) => {
+                let value = document.xpath_string(xpath)?;
+                (value != *expected).then(|| format!("{xpath:?} is {value:?}, not {expected:?}"))
+            }
+            Expectation::Matches(regex) => {
+                let value = document.xpath_string(xpath)?;
+                (!regex.is_match(&value)).then(|| {
+                    format!(
+                        "{xpath:?} is {value:?}, which does not match {:?}",
+                        regex.as_str()
+                    )
+                })
+            }
+        };
+        Ok(failure
+            .map(|failure| ErrorDetail {
+                file: Some(result.path.clone()),
+                ..ErrorDetail::new(self.message.clone().unwrap_or(failure), &codes::ASSERTION)
+            })
+            .into_iter()
+            .collect())
+    }
+}
+
+/// Load a WebAssembly rule plugin, if this build supports them.
+pub fn load_plugin(path: &str) -> Result<Box<dyn Rule>, String> {
+    #[cfg(feature = "plugins")]
+    return Ok(Box::new(plugin::Plugin::load(path)?));
+    #[cfg(not(feature = "plugins"))]
+    Err(format!(
+        "cannot load plugin {path}: validate-xml was built without the plugins feature"
+    ))
+}
+
+/// Load a Rhai assertion script, if this build supports them.
+pub fn load_script(path: &str) -> Result<Box<dyn Rule>, String> {
+    #[cfg(feature = "scripts")]
+    return Ok(Box::new(script::Script::load(path)?));
+    #[cfg(not(feature = "scripts"))]
+    Err(format!(
+        "cannot load script {path}: validate-xml was built without the scripts feature"
+    ))
+}
+
+#[cfg(feature = "scripts")]
+mod script {
+    use rhai::{Engine, EvalAltResult, Scope, AST};
+    use std::cell::RefCell;
+    use std::ptr;
+
+    use super::{Document, Rule};
+    use crate::{codes, ErrorDetail, FileResult, Severity};
+
+    /// The operations a script may run per file, so that a looping script fails
+    /// rather than hangs.
+    const MAX_OPERATIONS: u64 = 10_000_000;
+
+    thread_local! {
+        /// The document that the script running on this thread checks, and the
+        /// errors it reported so far. The document is only set while it is alive.
+        static CURRENT: RefCell<(*const Document, Vec<(Severity, String)>)> =
+            const { RefCell::new((ptr::null(), Vec::new())) };
+    }
+
+    /// The string value of an XPath expression in the document being checked on
+    /// this thread.
+    fn xpath(expression: &str) -> Result<String, Box<EvalAltResult>> {
+        let document = CURRENT.with(|current| current.borrow().0);
+        if document.is_null() {
+            return Err("no document is being checked".into());
+        }
+        unsafe { &*document }
+            .xpath_string(expression)
+            .map_err(Into::into)
+    }
+
+    /// Record an error of the document being checked on this thread.
+    fn report(severity: Severity, message: &str) {
+        CURRENT.with(|current| current.borrow_mut().1.push((severity, message.to_owned())));
+    }
+
+    /// A rule compiled from a Rhai script.
+    pub struct Script {
+        name: String,
+        engine: Engine,
+        ast: AST,
+    }
+
+    impl Script {
+        pub fn load(path: &str) -> Result<Script, String> {
+            let mut engine = Engine::new();
+            engine.set_max_operations(MAX_OPERATIONS);
+            engine.register_fn("xpath", xpath);
+            engine.register_fn("xpath_count", |expression: &str| {
+                let count = xpath(&format!("count({expression})"))?;
+                Ok::<_, Box<EvalAltResult>>(count.parse::<f64>().unwrap_or_default() as i64)
+            });
+            engine.register_fn("fail", |message: &str| report(Severity::Error, message));
+            engine.register_fn("warn", |message: &str| report(Severity::Warning, message));
+            let ast = engine
+                .compile_file(path.into())
+                .map_err(|e| format!("script {path}: {e}"))?;
+            Ok(Script {
+                name: path.to_owned(),
+                engine,
+                ast,
+            })
+        }
+    }
+
+    impl Rule for Script {
+        fn check(
+            &self,
+            document: &Document,
+            result: &FileResult,
+        ) -> Result<Vec<ErrorDetail>, String> {
+            let mut scope = Scope::new();
+            scope.push_constant("path", result.path.clone());
+            scope.push_constant("status", result.status.name());
+            scope.push_constant(
+                "root_element",
+                result.root_element.clone().unwrap_or_default(),
+            );
+            CURRENT.with(|current| *current.borrow_mut() = (document, Vec::new()));
+            let run = self.engine.run_ast_with_scope(&mut scope, &self.ast);
+            let reported = CURRENT.with(|current| {
+                let mut current = current.borrow_mut();
+                current.0 = ptr::null();
+                std::mem::take(&mut current.1)
+            });
+            run.map_err(|e| format!("script {} failed: {e}", self.name))?;
+            Ok(reported
+                .into_iter()
+                .map(|(severity, message)| ErrorDetail {
+                    file: Some(result.path.clone()),
+                    severity,
+                    ..ErrorDetail::new(message, &codes::SCRIPT)
+                })
+                .collect())
+        }
+    }
+}
+
+#[cfg(feature = "plugins")]
+mod plugin {
+    use libc::c_int;
+    use serde::Deserialize;
+    use serde_json::json;
+    use std::ptr;
+    use wasmi::{Config, Engine, Linker, Module, Store};
+
+    use super::{take_string, Document, Rule};
+    use crate::{codes, xmlDocDumpMemory, ErrorDetail, FileResult, Severity};
+
+    impl Document {
+        /// The document serialized as XML.
+        fn to_xml(&self) -> String {
+            let mut text = ptr::null_mut();
+            let mut len: c_int = 0;
+            unsafe {
+                xmlDocDumpMemory(self.doc, &mut text, &mut len);
+                take_string(text).unwrap_or_default()
+            }
+        }
+    }
+
+    /// The instructions a plugin may run per file, so that a looping plugin fails
+    /// rather than hangs.
+    const FUEL: u64 = 1_000_000_000;
+
+    /// A rule compiled from a WebAssembly module.
+    pub struct Plugin {
+        name: String,
+        engine: Engine,
+        module: Module,
+    }
+
+    /// An error as returned by a plugin.
+    #[derive(Deserialize)]
+    struct Found {
+        message: String,
+        #[serde(default)]
+        line: i32,
+        #[serde(default)]
+        severity: Option<String>,
+    }
+
+    impl Plugin {
+        /// Load a plugin from a `.wasm` file, or a `.wat` file in the text format.
+        pub fn load(path: &str) -> Result<Plugin, String> {
+            let bytes = std::fs::read(path).map_err(|e| format!("plugin {path}: {e}"))?;
+            let mut config = Config::default();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config);
+            let module = Module::new(&engine, bytes).map_err(|e| format!("plugin {path}: {e}"))?;
+            Ok(Plugin {
+                name: path.to_owned(),
+                engine,
+                module,
+            })
+        }
+
+        /// Run the plugin's `check` on an input, returning its output.
+        fn call(&self, input: &[u8]) -> Result<Vec<u8>, wasmi::Error> {
+            // A fresh instance per file, so that files do not see each other's state.
+            let mut store = Store::new(&self.engine, ());
+            store.set_fuel(FUEL)?;
+            let instance =
+                Linker::new(&self.engine).instantiate_and_start(&mut store, &self.module)?;
+            let memory = instance
+                .get_memory(&store, "memory")
+                .ok_or_else(|| wasmi::Error::new("no exported memory"))?;
+            let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+            let check = instance.get_typed_func::<(i32, i32), i64>(&store, "check")?;
+
+            let len = input.len() as i32;
+            let address = alloc.call(&mut store, len)?;
+            memory.write(&mut store, address as u32 as usize, input)?;
+            let output = check.call(&mut store, (address, len))?;
+            let mut bytes = vec![0; output as u32 as usize];
+            memory.read(&store, (output >> 32) as u32 as usize, &mut bytes)?;
+            Ok(bytes)
+        }
+    }
+
+    impl Rule for Plugin {
+        fn check(
+            &self,
+            document: &Document,
+            result: &FileResult,
+        ) -> Result<Vec<ErrorDetail>, String> {
+            let input = json!({ "result": result, "document": document.to_xml() }).to_string();
+            let output = self
+                .call(input.as_bytes())
+                .map_err(|e| format!("plugin {} failed: {e}", self.name))?;
+            let found: Vec<Found> = serde_json::from_slice(&output)
+                .map_err(|e| format!("plugin {} returned invalid errors: {e}", self.name))?;
+            Ok(found
+                .into_iter()
+                .map(|found| ErrorDetail {
+                    file: Some(result.path.clone()),
+                    line: found.line,
+                    severity: match found.severity.as_deref() {
+                        Some("warning") => Severity::Warning,
+                        _ => Severity::Error,
+                    },
+                    ..ErrorDetail::new(found.message, &codes::RULE)
+                })
+                .collect())
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! A long-running HTTP server validating documents against schemas kept in memory.
+//!
+//! Endpoints:
+//!
+//! - `POST /validate` validates the request body, against the schema given by the
+//!   `schema` query parameter or else the document's schemaLocation hint. The `name`
+//!   parameter names the document in errors. The `schema` parameter, and a local
+//!   schema of the hint, must be a configured schema or a file under the served
+//!   directory.
+//! - `GET /validate?path=...` validates a file under the served directory.
+//! - `GET /metrics` returns Prometheus metrics of the requests so far.
+//! - `GET /health` returns `ok`.
+//!
+//! Results are returned in the JSON result format of one file.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use serde_json::json;
+
+use crate::metrics::Metrics;
+use crate::{
+    extract_schema_urls, is_remote, local_path, log_errors, timed, validate, validate_memory,
+    Settings,
+};
+
+/// The largest request body accepted, in bytes.
+const MAX_BODY: usize = 64 * 1024 * 1024;
+
+/// The largest request head accepted, in bytes.
+const MAX_HEAD: usize = 64 * 1024;
+
+/// A response: status code, content type and body.
+type Response = (u16, &'static str, Vec<u8>);
+
+/// Serve requests on `listen`, e.g. "127.0.0.1:8080", one thread per connection.
+pub fn run(settings: &Settings, listen: &str, serve_files: bool) -> io::Result<()> {
+    let listener = TcpListener::bind(listen)?;
+    tracing::info!("Listening on http://{}", listener.local_addr()?);
+    let started = Instant::now();
+    let metrics = Mutex::new(Metrics::default());
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::warn!("Failed to accept a connection: {e}");
+                    continue;
+                }
+            };
+            let metrics = &metrics;
+            scope.spawn(move || {
+                log_errors();
+                if let Err(e) = handle(stream, settings, serve_files, metrics, started) {
+                    tracing::debug!("Connection failed: {e}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Answer the requests of one connection until the client closes it.
+fn handle(
+    stream: TcpStream,
+    settings: &Settings,
+    serve_files: bool,
+    metrics: &Mutex<Metrics>,
+    started: Instant,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+    loop {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if reader.read_until(b'\n', &mut head)? == 0 || head.len() > MAX_HEAD {
+                return Ok(());
+            }
+        }
+
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut request = httparse::Request::new(&mut headers);
+        if !matches!(request.parse(&head), Ok(httparse::Status::Complete(_))) {
+            return respond(&mut stream, error(400, "malformed request"), true);
+        }
+        let header = |name: &str| {
+            request
+                .headers
+                .iter()
+                .find(|header| header.name.eq_ignore_ascii_case(name))
+                .and_then(|header| std::str::from_utf8(header.value).ok())
+        };
+        let length: usize = match header("content-length").map(str::parse) {
+            None => 0,
+            Some(Ok(length)) if length <= MAX_BODY => length,
+            Some(Ok(_)) => return respond(&mut stream, error(413, "document too large"), true),
+            Some(Err(_)) => return respond(&mut stream, error(400, "bad Content-Length"), true),
+        };
+        let close = header("connection").is_some_and(|value| value.eq_ignore_ascii_case("close"))
+            || request.version == Some(0);
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+
+        let method = request.method.unwrap_or_default().to_owned();
+        let target = request.path.unwrap_or_default().to_owned();
+        let response = panic::catch_unwind(AssertUnwindSafe(|| {
+            route(
+                &method,
+                &target,
+                &body,
+                settings,
+                serve_files,
+                metrics,
+                started,
+            )
+        }))
+        .unwrap_or_else(|_| error(500, "validation failed unexpectedly"));
+        respond(&mut stream, response, close)?;
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+fn route(
+    method: &str,
+    target: &str,
+    body: &[u8],
+    settings: &Settings,
+    serve_files: bool,
+    metrics: &Mutex<Metrics>,
+    started: Instant,
+) -> Response {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let param = |name: &str| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let result = match (method, path) {
+        ("GET", "/health") => return (200, "text/plain", b"ok\n".to_vec()),
+        ("GET", "/metrics") => {
+            let text = metrics.lock().unwrap().render(started.elapsed());
+            return (200, "text/plain; version=0.0.4", text.into_bytes());
+        }
+        ("POST", "/validate") => {
+            let name = param("name").unwrap_or_else(|| "-".to_owned());
+            if name.contains('\0') {
+                return error(400, "bad document name");
+            }
+            let schemas = match (param("schema"), &settings.schema) {
+                (Some(schema), _) => {
+                    allowed_schema(schema, settings, serve_files).map(|url| vec![url])
+                }
+                (None, Some(schema)) => Ok(vec![schema.clone()]),
+                (None, None) => extract_schema_urls(body)
+                    .into_iter()
+                    .map(|url| allowed_hint(url, settings, serve_files))
+                    .collect(),
+            };
+            match schemas {
+                Ok(schemas) => timed(|| validate_memory(&name, body, Some(schemas), settings)),
+                Err(message) => return error(403, &message),
+            }
+        }
+        ("GET", "/validate") => {
+            let Some(relative) = param("path") else {
+                return error(400, "missing path parameter");
+            };
+            let relative = Path::new(&relative);
+            if !serve_files {
+                return error(404, "no directory is served");
+            }
+            if !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+            {
+                return error(400, "path must be relative to the served directory");
+            }
+            let path = settings.root.join(relative);
+            if !path.is_file() || path.to_str().is_none() {
+                return error(404, "no such file");
+            }
+            timed(|| validate(path, settings))
+        }
+        (_, "/validate" | "/metrics" | "/health") => return error(405, "method not allowed"),
+        _ => return error(404, "not found"),
+    };
+
+    let failed = result.status.is_failure(settings.require_schema);
+    metrics.lock().unwrap().record(&result, failed);
+    (
+        200,
+        "application/json",
+        serde_json::to_vec(&result).unwrap(),
+    )
+}
+
+/// The schema of a `schema` parameter, if it may be used: one configured by
+/// `--schema`, the schema rules or the overrides, or a file under the served
+/// directory. Either way, a remote schema must be allowed by the host policy.
+fn allowed_schema(
+    schema: String,
+    settings: &Settings,
+    serve_files: bool,
+) -> Result<String, String> {
+    if let Some(reason) = settings.hosts.refusal(&schema) {
+        return Err(format!("schema not allowed: {reason}"));
+    }
+    if settings.is_configured_schema(&schema) {
+        return Ok(schema);
+    }
+    if is_remote(&schema) || !serve_files {
+        return Err("schema must be configured or a file under the served directory".to_owned());
+    }
+    let path = local_path(&schema);
+    let path = if path.is_relative() {
+        settings.root.join(path)
+    } else {
+        path
+    };
+    let under_root = settings
+        .root
+        .canonicalize()
+        .and_then(|root| Ok((path.canonicalize()?, root)))
+        .ok()
+        .filter(|(path, root)| path.starts_with(root) && path.is_file());
+    match under_root.and_then(|(path, _)| path.into_os_string().into_string().ok()) {
+        Some(path) => Ok(path),
+        None => Err("schema must be configured or a file under the served directory".to_owned()),
+    }
+}
+
+/// The schema of a posted document's schemaLocation hint, if it may be used: a
+/// remote schema, left to the host policy like those of files, or a local schema
+/// that a `schema` parameter may name, so that documents cannot have any other file
+/// read.
+fn allowed_hint(url: String, settings: &Settings, serve_files: bool) -> Result<String, String> {
+    if is_remote(&url) {
+        return Ok(url);
+    }
+    allowed_schema(url, settings, serve_files)
+        .map_err(|message| format!("schemaLocation hint not allowed: {message}"))
+}
+
+fn error(status: u16, message: &str) -> Response {
+    let body = json!({ "error": message }).to_string().into_bytes();
+    (status, "application/json", body)
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    (status, content_type, body): Response,
+    close: bool,
+) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n{}\r\n",
+        body.len(),
+        if close { "Connection: close\r\n" } else { "" }
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codes, tests, HostPolicy, Status};
+    use serde_json::Value;
+
+    const SCHEMA: &str = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+        <xs:element name="note" type="xs:string"/>
+    </xs:schema>"#;
+
+    /// A document whose schemaLocation hint is `location`.
+    fn document(location: &str) -> Vec<u8> {
+        format!(
+            r#"<note xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
+                xsi:schemaLocation="urn:x {location}">hi</note>"#
+        )
+        .into_bytes()
+    }
+
+    /// Post `body` to `target`, returning the status and the parsed JSON body.
+    fn post(target: &str, body: &[u8], settings: &Settings) -> (u16, Value) {
+        tests::init();
+        let metrics = Mutex::new(Metrics::default());
+        let (status, _, body) = route(
+            "POST",
+            target,
+            body,
+            settings,
+            true,
+            &metrics,
+            Instant::now(),
+        );
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn hints_outside_the_served_directory_are_refused() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("note.xsd"), SCHEMA).unwrap();
+        let settings = tests::settings(root.path());
+
+        let existing = outside.path().join("note.xsd");
+        let missing = outside.path().join("missing.xsd");
+        let mut messages = Vec::new();
+        for location in [
+            existing.to_str().unwrap().to_owned(),
+            missing.to_str().unwrap().to_owned(),
+            "../note.xsd".to_owned(),
+            "/etc/passwd".to_owned(),
+            "file:///etc/passwd".to_owned(),
+        ] {
+            let (status, body) = post("/validate?name=note.xml", &document(&location), &settings);
+            assert_eq!(status, 403, "{location}");
+            messages.push(body["error"].as_str().unwrap().to_owned());
+        }
+        // Whether the file exists does not show.
+        assert!(messages.iter().all(|message| *message == messages[0]));
+        assert!(messages[0].starts_with("schemaLocation hint not allowed"));
+    }
+
+    #[test]
+    fn hints_under_the_served_directory_are_used() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("schemas")).unwrap();
+        std::fs::write(root.path().join("schemas").join("note.xsd"), SCHEMA).unwrap();
+        let settings = tests::settings(root.path());
+
+        let (status, body) = post(
+            "/validate?name=note.xml",
+            &document("schemas/note.xsd"),
+            &settings,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(body["status"], "valid");
+    }
+
+    #[test]
+    fn remote_hints_follow_the_host_policy() {
+        let root = tempfile::tempdir().unwrap();
+        let settings = Settings {
+            hosts: HostPolicy::new(None, Some("blocked.invalid"), false, false),
+            ..tests::settings(root.path())
+        };
+
+        let (status, body) = post(
+            "/validate?name=note.xml",
+            &document("http://blocked.invalid/note.xsd"),
+            &settings,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(
+            body["status"],
+            serde_json::to_value(Status::InternalError).unwrap()
+        );
+        assert_eq!(body["errors"][0]["error_code"], codes::BLOCKED_HOST.id);
+    }
+
+    #[test]
+    fn schema_parameters_must_be_configured_or_served() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("note.xsd"), SCHEMA).unwrap();
+        let settings = Settings {
+            schema: Some("https://example.org/configured.xsd".to_owned()),
+            ..tests::settings(root.path())
+        };
+
+        let (status, _) = post(
+            "/validate?schema=note.xsd",
+            &document("unused.xsd"),
+            &settings,
+        );
+        assert_eq!(status, 200);
+        let (status, _) = post("/validate?schema=/etc/passwd", b"<note/>", &settings);
+        assert_eq!(status, 403);
+        let (status, _) = post(
+            "/validate?schema=https://example.org/other.xsd",
+            b"<note/>",
+            &settings,
+        );
+        assert_eq!(status, 403);
+        assert!(allowed_schema(
+            "https://example.org/configured.xsd".to_owned(),
+            &settings,
+            false
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn paths_must_stay_in_the_served_directory() {
+        let root = tempfile::tempdir().unwrap();
+        let settings = tests::settings(root.path());
+        let metrics = Mutex::new(Metrics::default());
+        let get = |target: &str| {
+            route(
+                "GET",
+                target,
+                b"",
+                &settings,
+                true,
+                &metrics,
+                Instant::now(),
+            )
+            .0
+        };
+        assert_eq!(get("/validate?path=../etc/passwd"), 400);
+        assert_eq!(get("/validate?path=/etc/passwd"), 400);
+        assert_eq!(get("/validate?path=missing.xml"), 404);
+    }
+}
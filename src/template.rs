@@ -0,0 +1,76 @@
+//! Line templates for `--format-template` and `--summary-template`.
+
+/// A parsed template such as `"{path}: {status}"`.
+///
+/// `{{` and `}}` stand for literal braces, and `\n` and `\t` for a newline and a tab,
+/// so templates are easy to pass on the command line.
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+enum Part {
+    Text(String),
+    Field(String),
+}
+
+impl Template {
+    /// Parse `text`, accepting only the given field names.
+    pub fn parse(text: &str, fields: &[&str]) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '\\' if chars.peek() == Some(&'n') => {
+                    chars.next();
+                    literal.push('\n');
+                }
+                '\\' if chars.peek() == Some(&'t') => {
+                    chars.next();
+                    literal.push('\t');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed {{ in template {text:?}")),
+                        }
+                    }
+                    if !fields.contains(&name.as_str()) {
+                        return Err(format!(
+                            "unknown field {{{name}}} in template {text:?}; expected one of {}",
+                            fields.join(", ")
+                        ));
+                    }
+                    parts.push(Part::Text(std::mem::take(&mut literal)));
+                    parts.push(Part::Field(name));
+                }
+                '}' => return Err(format!("unmatched }} in template {text:?}")),
+                _ => literal.push(c),
+            }
+        }
+        parts.push(Part::Text(literal));
+        Ok(Template { parts })
+    }
+
+    /// Fill in the template, looking up each field with `value`.
+    pub fn render(&self, value: impl Fn(&str) -> String) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Field(name) => value(name),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,100 @@
+//! Re-validation of files as they change.
+//!
+//! Changes are reported by the platform's file system notifications, or else found
+//! by polling modification times and sizes, e.g. on file systems without
+//! notifications. Either way only the changed files are looked at again, rather
+//! than discovering all files anew.
+
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+use notify::{Config, Event, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{discover, discover_changed, Settings};
+
+/// How long changes must stop for before checking the changed files, and how often
+/// to poll for them without notifications.
+const INTERVAL: Duration = Duration::from_millis(500);
+
+/// Changes under the root directory since watching started.
+pub struct Watch {
+    /// Kept so that it keeps sending events.
+    _watcher: Box<dyn Watcher>,
+    events: Receiver<notify::Result<Event>>,
+    /// The canonical root directory, which changed paths are under.
+    root: PathBuf,
+    /// The files picked by `--sample`, the only ones checked again.
+    sample: Option<HashSet<PathBuf>>,
+}
+
+/// Start watching the files to check, so that files changed from then on, also
+/// during the first run, are checked again.
+pub fn start(settings: &Settings) -> notify::Result<Watch> {
+    let root = settings.root.canonicalize()?;
+    let (sender, events) = mpsc::channel();
+    let watcher: Box<dyn Watcher> = match watch(
+        RecommendedWatcher::new(sender.clone(), Config::default()),
+        &root,
+    ) {
+        Ok(watcher) => Box::new(watcher),
+        Err(e) => {
+            tracing::warn!("Polling for changes, since they are not reported: {e}");
+            let config = Config::default().with_poll_interval(INTERVAL);
+            Box::new(watch(PollWatcher::new(sender, config), &root)?)
+        }
+    };
+    Ok(Watch {
+        _watcher: watcher,
+        events,
+        root,
+        sample: settings
+            .sample
+            .map(|_| discover(settings).into_iter().collect()),
+    })
+}
+
+fn watch<W: Watcher>(watcher: notify::Result<W>, root: &Path) -> notify::Result<W> {
+    let mut watcher = watcher?;
+    watcher.watch(root, RecursiveMode::Recursive)?;
+    Ok(watcher)
+}
+
+impl Watch {
+    /// Wait for paths to change, then for the changes to stop for a whole interval,
+    /// so that an editor saving in several steps causes one check.
+    fn changed(&self) -> BTreeSet<PathBuf> {
+        let mut changed = BTreeSet::new();
+        let mut add = |event: notify::Result<Event>| match event {
+            Ok(event) if !matches!(event.kind, EventKind::Access(_)) => changed.extend(event.paths),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to watch for changes: {e}"),
+        };
+        add(self.events.recv().expect("the watcher stopped"));
+        while let Ok(event) = self.events.recv_timeout(INTERVAL) {
+            add(event);
+        }
+        changed
+    }
+}
+
+/// Forever wait for files to be added or changed, then `check` them.
+pub fn run(settings: &Settings, watch: Watch, check: impl Fn(Vec<PathBuf>)) -> ! {
+    loop {
+        // As found by walking the root directory as given.
+        let changed = watch
+            .changed()
+            .into_iter()
+            .filter_map(|path| Some(settings.root.join(path.strip_prefix(&watch.root).ok()?)))
+            .collect();
+        let mut paths = discover_changed(settings, changed);
+        if let Some(sample) = &watch.sample {
+            paths.retain(|path| sample.contains(path));
+        }
+        paths.sort();
+        if !paths.is_empty() {
+            check(paths);
+        }
+    }
+}
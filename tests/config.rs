@@ -0,0 +1,165 @@
+//! Configuration files and `VALIDATE_XML_*` environment variables: how they are
+//! read, and that only the commands checking files read them.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// validate-xml with arguments, run in `dir`.
+fn command(dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_validate-xml"));
+    command.args(args).current_dir(dir);
+    command
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    command(dir, args).output().unwrap()
+}
+
+/// A directory with a configuration file and an empty report.
+fn configured_dir() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("validate-xml.toml"),
+        "quiet = true\nextension = \"xml\"\nfail_on = \"invalid\"\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("report.json"),
+        r#"{"files": [], "schemas": []}"#,
+    )
+    .unwrap();
+    dir
+}
+
+#[test]
+fn commands_without_options_ignore_the_config_file() {
+    let dir = configured_dir();
+    for args in [
+        &["diff", "report.json", "report.json"][..],
+        &["merge-reports", "report.json", "report.json"],
+        &["--generate-man"],
+        &["config", "schema"],
+    ] {
+        let output = run(dir.path(), args);
+        assert!(
+            output.status.success(),
+            "{args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+#[test]
+fn history_ignores_the_config_file() {
+    let dir = configured_dir();
+    let output = run(dir.path(), &["history", "missing.db"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("Invalid arguments"), "{stderr}");
+    assert!(stderr.contains("missing.db does not exist"), "{stderr}");
+}
+
+#[test]
+fn checking_files_reads_the_config_file() {
+    let dir = configured_dir();
+    let output = run(dir.path(), &["config", "show"]);
+    assert!(output.status.success());
+    let shown = String::from_utf8_lossy(&output.stdout);
+    assert!(shown.contains("extension = \"xml\""), "{shown}");
+}
+
+#[test]
+fn history_ignores_the_environment() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = command(dir.path(), &["history", "missing.db"])
+        .env("VALIDATE_XML_QUIET", "1")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("Invalid arguments"), "{stderr}");
+    assert!(stderr.contains("missing.db does not exist"), "{stderr}");
+}
+
+#[test]
+fn checking_files_reads_the_environment() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = command(dir.path(), &["config", "show"])
+        .env("VALIDATE_XML_EXTENSION", "xml")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let shown = String::from_utf8_lossy(&output.stdout);
+    assert!(shown.contains("extension = \"xml\""), "{shown}");
+}
+
+#[test]
+fn toml_strings_arrays_and_profiles() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("validate-xml.toml"),
+        r#"# Escapes in basic strings, none in literal ones.
+extension = "xml"
+schema_rule = ["a/**=\"quoted\" \u00e9.xsd", 'b/**=c:\schemas\b.xsd']
+
+[profile.ci]
+max_error_details = 3
+"#,
+    )
+    .unwrap();
+    let output = run(dir.path(), &["--profile=ci", "config", "show"]);
+    assert!(output.status.success());
+    let shown = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        shown.contains(r#"schema_rule = ["a/**=\"quoted\" é.xsd","b/**=c:\\schemas\\b.xsd"]"#),
+        "{shown}"
+    );
+    assert!(shown.contains("max_error_details = 3"), "{shown}");
+}
+
+#[test]
+fn invalid_toml_is_reported_with_its_position() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("validate-xml.toml"),
+        "extension = \"xml\"\nquiet = yes\n",
+    )
+    .unwrap();
+    let output = run(dir.path(), &["config", "show"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 2, column 9"), "{stderr}");
+}
+
+#[test]
+fn the_environment_turns_off_flags_of_the_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("validate-xml.toml"),
+        "extension = \"xml\"\nrequire_schema = true\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("note.xml"), "<note/>").unwrap();
+
+    let output = run(dir.path(), &["."]);
+    assert!(!output.status.success(), "require_schema was not applied");
+    for value in ["0", "false"] {
+        let output = command(dir.path(), &["."])
+            .env("VALIDATE_XML_REQUIRE_SCHEMA", value)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{value}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let output = command(dir.path(), &["config", "show"])
+        .env("VALIDATE_XML_REQUIRE_SCHEMA", "0")
+        .output()
+        .unwrap();
+    let shown = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        shown.contains("require_schema = false  # VALIDATE_XML_REQUIRE_SCHEMA"),
+        "{shown}"
+    );
+}